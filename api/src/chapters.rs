@@ -0,0 +1,102 @@
+use serde::Serialize;
+
+// Chapter of a podcast episode
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Chapter {
+    pub title: String,
+    pub start_ms: u32,
+}
+
+// Chapters listed in an episode description, one per line like "12:34 Title",
+// "(1:02:03) - Title" or "[0:00] Title". Spotify has no chapter metadata of its own.
+// Timestamps that don't go up or lie beyond the episode aren't chapters, and neither
+// is a single timestamp.
+pub fn from_description(description: &str, duration_ms: u32) -> Vec<Chapter> {
+    let mut chapters: Vec<Chapter> = Vec::new();
+    for chapter in description.lines().filter_map(parse_line) {
+        let in_order = chapters
+            .last()
+            .map_or(true, |last| chapter.start_ms > last.start_ms);
+        if !in_order || (duration_ms > 0 && chapter.start_ms >= duration_ms) {
+            return Vec::new();
+        }
+        chapters.push(chapter);
+    }
+
+    if chapters.len() < 2 {
+        return Vec::new();
+    }
+    chapters
+}
+
+fn parse_line(line: &str) -> Option<Chapter> {
+    let line = line.trim_start().trim_start_matches(['(', '[']);
+    let end = line
+        .find(|c: char| !(c.is_ascii_digit() || c == ':'))
+        .unwrap_or(line.len());
+    let start_ms = parse_timestamp(&line[..end])?;
+
+    let title = line[end..]
+        .trim_start_matches([')', ']'])
+        .trim_start_matches(|c: char| c.is_whitespace() || "-–—:|".contains(c))
+        .trim_end();
+    if title.is_empty() {
+        return None;
+    }
+
+    Some(Chapter {
+        title: title.to_string(),
+        start_ms,
+    })
+}
+
+// [H:]MM:SS or M:SS in ms
+fn parse_timestamp(timestamp: &str) -> Option<u32> {
+    let parts: Vec<&str> = timestamp.split(':').collect();
+    if !(2..=3).contains(&parts.len()) || parts[1..].iter().any(|p| p.len() != 2) {
+        return None;
+    }
+
+    let mut seconds: u32 = 0;
+    for (i, part) in parts.iter().enumerate() {
+        let value: u32 = part.parse().ok()?;
+        if i > 0 && value >= 60 {
+            return None;
+        }
+        seconds = seconds.checked_mul(60)?.checked_add(value)?;
+    }
+    seconds.checked_mul(1000)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chapters_from_timestamp_lines() {
+        let description = "Our guest talks about synths.\n\
+            \n\
+            00:00 Intro\n\
+            (4:05) - Modular synths\n\
+            [1:02:03] Listener questions\n\
+            Find us at 12:30 on Fridays";
+        let chapters = from_description(description, 4_000_000);
+        let chapter = |title: &str, start_ms| Chapter {
+            title: title.to_string(),
+            start_ms,
+        };
+        assert_eq!(
+            chapters,
+            vec![
+                chapter("Intro", 0),
+                chapter("Modular synths", 245_000),
+                chapter("Listener questions", 3_723_000),
+            ]
+        );
+
+        // Not in order, beyond the end or just a single timestamp
+        assert!(from_description("10:00 Two\n05:00 One", 0).is_empty());
+        assert!(from_description("00:00 Intro\n05:00 Outro", 60_000).is_empty());
+        assert!(from_description("00:00 Intro", 60_000).is_empty());
+    }
+}
@@ -0,0 +1,94 @@
+use std::{net::SocketAddr, time::Duration};
+
+#[derive(Clone, Debug)]
+pub struct ServerConfig {
+    // Addresses to listen on, all sharing the same player state and notifications
+    pub listeners: Vec<ListenerConfig>,
+    // Serve the web UI
+    pub enable_web: bool,
+    // Serve the web UI from this directory instead of the bundled one
+    pub custom_path: Option<String>,
+    // Additional UI bundles as (name, directory), each served under /ui/<name>/
+    pub web_bundles: Vec<(String, String)>,
+    // Forward every player event as an OnRawEvent notification, for debugging
+    pub raw_events: bool,
+    // Serve album art of the current track from memory under /cover/<id>
+    pub cover_proxy: bool,
+    // Serve playback and server counters for Prometheus under /metrics
+    pub metrics: bool,
+    // How long a websocket subscription can be resumed after the connection drops
+    pub resume_ttl: Duration,
+    // Enable diagnostic methods, such as getRawNormalisation
+    pub debug: bool,
+    // Only these methods can be called when set, e.g. for untrusted clients
+    pub allowed_methods: Option<Vec<String>>,
+    // These methods can't be called, also when in allowed_methods
+    pub denied_methods: Vec<String>,
+    // Maximum of the volume scale used by API clients. Internally volume is
+    // always 0..=65535, as used by the mixer and spirc, and converted to and
+    // from this scale in setVolume, getVolume, getStatus and OnVolumeChange.
+    pub volume_max: u16,
+    // setVolume calls within this window are coalesced and only the latest volume is
+    // sent to spirc at its end, e.g. while dragging a slider. Zero sends every change.
+    pub volume_debounce: Duration,
+    // Save the position of the current track to this file and load it from there,
+    // paused, when spirc starts
+    pub position_file: Option<String>,
+    // Stalled or transiently failing websocket writes are retried this often before
+    // the connection is dropped. A client that closed the connection is dropped at once.
+    pub send_retries: u32,
+    // Let websocket clients ask for deflate compressed frames with `compress=true`
+    pub ws_compression: bool,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            listeners: vec![ListenerConfig::default()],
+            enable_web: false,
+            custom_path: None,
+            web_bundles: Vec::new(),
+            raw_events: false,
+            cover_proxy: false,
+            metrics: false,
+            resume_ttl: Duration::from_secs(60),
+            debug: false,
+            allowed_methods: None,
+            denied_methods: Vec::new(),
+            volume_max: u16::MAX,
+            volume_debounce: Duration::ZERO,
+            position_file: None,
+            send_retries: 2,
+            ws_compression: false,
+        }
+    }
+}
+
+// One address the server listens on, with its own access rules
+#[derive(Clone, Debug)]
+pub struct ListenerConfig {
+    pub addr: SocketAddr,
+    // Requests must carry this token, as `Authorization: Bearer TOKEN` or, for browser
+    // websockets and event streams, as a `token` query parameter. The web UI files
+    // are served without it.
+    pub token: Option<String>,
+    // Serve HTTPS instead of HTTP
+    pub tls: Option<TlsConfig>,
+}
+
+impl Default for ListenerConfig {
+    fn default() -> Self {
+        Self {
+            addr: SocketAddr::from(([0, 0, 0, 0], 3030)),
+            token: None,
+            tls: None,
+        }
+    }
+}
+
+// PEM files of the certificate chain and private key of a listener
+#[derive(Clone, Debug)]
+pub struct TlsConfig {
+    pub cert_path: String,
+    pub key_path: String,
+}
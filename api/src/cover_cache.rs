@@ -0,0 +1,64 @@
+use bytes::Bytes;
+use log::debug;
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use warp::hyper::{Body, Request};
+
+use librespot_core::{http_client::HttpClient, Error};
+
+// Number of covers kept in memory
+pub const COVER_CACHE_SIZE: usize = 16;
+
+// Album art fetched once and then served from memory. Only the most recently
+// used covers are kept.
+pub struct CoverCache {
+    http_client: HttpClient,
+    // (id, image), least recently used first
+    entries: Mutex<VecDeque<(String, Bytes)>>,
+}
+
+impl CoverCache {
+    pub fn new() -> Self {
+        Self {
+            http_client: HttpClient::new(None),
+            entries: Mutex::new(VecDeque::with_capacity(COVER_CACHE_SIZE)),
+        }
+    }
+
+    // Get a cover from the cache, or fetch it from url
+    pub async fn get(&self, id: &str, url: &str) -> Result<Bytes, Error> {
+        if let Some(image) = self.get_cached(id) {
+            return Ok(image);
+        }
+
+        debug!("Fetching cover {id}");
+        let req = Request::get(url).body(Body::empty())?;
+        let image = self.http_client.request_body(req).await?;
+        self.insert(id, image.clone());
+
+        Ok(image)
+    }
+
+    fn get_cached(&self, id: &str) -> Option<Bytes> {
+        let mut entries = self.entries.lock();
+        let index = entries.iter().position(|(cached, _)| cached == id)?;
+        let entry = entries.remove(index)?;
+        let image = entry.1.clone();
+        entries.push_back(entry);
+        Some(image)
+    }
+
+    fn insert(&self, id: &str, image: Bytes) {
+        let mut entries = self.entries.lock();
+        entries.retain(|(cached, _)| cached != id);
+        if entries.len() >= COVER_CACHE_SIZE {
+            entries.pop_front();
+        }
+        entries.push_back((id.to_string(), image));
+    }
+}
+
+// Covers are identified by the last segment of their url
+pub fn cover_id(url: &str) -> &str {
+    url.rsplit('/').next().unwrap_or(url)
+}
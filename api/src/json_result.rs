@@ -1,23 +1,40 @@
 use std::fmt::Display;
 
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use serde_json;
 use serde_repr::Serialize_repr;
 use thiserror::Error;
 
 pub type JsonResult = Result<JsonResponse, JsonError>;
 
+// Request id, echoed back unchanged in the response. Numbers are kept as sent,
+// so large or fractional ids don't lose precision.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum JsonId {
+    Number(serde_json::Number),
+    String(String),
+    Null,
+}
+
+// Responses carry the request id and either `result` or `error`, unlike
+// notifications, which carry a `method` and no id.
 #[derive(Debug, Serialize)]
 pub struct JsonResponse {
-    id: i64,
+    id: JsonId,
     jsonrpc: f32,
     result: serde_json::Value,
 }
 
 #[derive(Debug, Serialize, Error)]
 pub struct JsonError {
-    id: Option<i64>,
+    id: JsonId,
     jsonrpc: f32,
+    error: ErrorObject,
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorObject {
     code: JsonErrCode,
     message: String,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -35,10 +52,13 @@ pub enum JsonErrCode {
     NoStream = -32000,
     NoControl = -32001,
     PlayerPoison = -32002,
+    NotReady = -32003,
+    Forbidden = -32004,
+    Unavailable = -32005,
 }
 
 impl JsonResponse {
-    pub fn new(id: i64, result: serde_json::Value) -> Self {
+    pub fn new(id: JsonId, result: serde_json::Value) -> Self {
         Self {
             id,
             jsonrpc: 2.0,
@@ -46,75 +66,67 @@ impl JsonResponse {
         }
     }
 
-    pub fn set_id(&mut self, id: i64) {
+    pub fn set_id(&mut self, id: JsonId) {
         self.id = id;
     }
 }
 
 impl JsonError {
-    pub fn parse(data: Option<String>) -> Self {
+    fn new(code: JsonErrCode, message: &str, data: Option<String>) -> Self {
         Self {
-            id: None,
+            id: JsonId::Null,
             jsonrpc: 2.0,
-            code: JsonErrCode::Parse,
-            message: "Parse error".to_string(),
-            data,
+            error: ErrorObject {
+                code,
+                message: message.to_string(),
+                data,
+            },
         }
     }
 
+    pub fn parse(data: Option<String>) -> Self {
+        Self::new(JsonErrCode::Parse, "Parse error", data)
+    }
+
     pub fn invalid_request(data: Option<String>) -> Self {
-        Self {
-            id: None,
-            jsonrpc: 2.0,
-            code: JsonErrCode::InvalidReq,
-            message: "Invalid Request".to_string(),
-            data,
-        }
+        Self::new(JsonErrCode::InvalidReq, "Invalid Request", data)
     }
 
     pub fn method_not_found(data: Option<String>) -> Self {
-        Self {
-            id: None,
-            jsonrpc: 2.0,
-            code: JsonErrCode::MethodNotFound,
-            message: "Method not found".to_string(),
-            data,
-        }
+        Self::new(JsonErrCode::MethodNotFound, "Method not found", data)
     }
 
     pub fn invalid_param(data: Option<String>) -> Self {
-        Self {
-            id: None,
-            jsonrpc: 2.0,
-            code: JsonErrCode::InvalidParam,
-            message: "Invalid params".to_string(),
-            data,
-        }
+        Self::new(JsonErrCode::InvalidParam, "Invalid params", data)
     }
 
     pub fn internal(data: Option<String>) -> Self {
-        Self {
-            id: None,
-            jsonrpc: 2.0,
-            code: JsonErrCode::Internal,
-            message: "Internal jsonrpc error".to_string(),
-            data,
-        }
+        Self::new(JsonErrCode::Internal, "Internal jsonrpc error", data)
     }
 
     pub fn no_control(data: Option<String>) -> Self {
-        Self {
-            id: None,
-            jsonrpc: 2.0,
-            code: JsonErrCode::NoControl,
-            message: "No player to control".to_string(),
-            data,
-        }
+        Self::new(JsonErrCode::NoControl, "No player to control", data)
     }
 
-    pub fn set_id(&mut self, id: Option<i64>) {
+    pub fn not_ready(data: Option<String>) -> Self {
+        Self::new(JsonErrCode::NotReady, "Player not ready, retry later", data)
+    }
+
+    pub fn forbidden(data: Option<String>) -> Self {
+        Self::new(JsonErrCode::Forbidden, "Method not allowed", data)
+    }
+
+    pub fn unavailable(data: Option<String>) -> Self {
+        Self::new(JsonErrCode::Unavailable, "Track unavailable", data)
+    }
+
+    pub fn set_id(&mut self, id: JsonId) {
         self.id = id;
     }
+
+    pub fn code(&self) -> JsonErrCode {
+        self.error.code
+    }
 }
 
 impl Display for JsonError {
@@ -1,4 +1,10 @@
+mod chapters;
+pub mod config;
+mod cover_cache;
 pub mod json_result;
+mod metrics;
+pub mod raw_event;
 pub mod server;
 
+pub use crate::config::{ListenerConfig, ServerConfig, TlsConfig};
 pub use crate::server::Server;
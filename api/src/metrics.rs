@@ -0,0 +1,137 @@
+use parking_lot::Mutex;
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use librespot_core::SpotifyId;
+
+// Playback and server counters, served in the Prometheus text format under /metrics
+#[derive(Default)]
+pub struct Metrics {
+    tracks_played: AtomicU64,
+    load_failures: AtomicU64,
+    buffering: AtomicU64,
+    dropped_notifications: AtomicU64,
+    // Last track counted as played, so resuming it isn't counted again
+    last_played: Mutex<Option<(u64, SpotifyId)>>,
+    // Number of requests and the total time spent on them, by method
+    requests: Mutex<BTreeMap<&'static str, (u64, Duration)>>,
+}
+
+impl Metrics {
+    pub fn track_playing(&self, play_request_id: u64, track_id: SpotifyId) {
+        let mut last_played = self.last_played.lock();
+        if *last_played != Some((play_request_id, track_id)) {
+            *last_played = Some((play_request_id, track_id));
+            self.tracks_played.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn load_failed(&self) {
+        self.load_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn buffering(&self) {
+        self.buffering.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn notifications_dropped(&self, count: u64) {
+        self.dropped_notifications
+            .fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn request_handled(&self, method: &'static str, elapsed: Duration) {
+        let mut requests = self.requests.lock();
+        let (count, total) = requests.entry(method).or_default();
+        *count += 1;
+        *total += elapsed;
+    }
+
+    // Counters together with the gauges the server reads when scraped
+    pub fn render(&self, state: &str, websockets: usize) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: u64| {
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} {kind}");
+            let _ = writeln!(out, "{name} {value}");
+        };
+
+        metric(
+            "librespot_tracks_played_total",
+            "counter",
+            "Tracks that started playing.",
+            self.tracks_played.load(Ordering::Relaxed),
+        );
+        metric(
+            "librespot_load_failures_total",
+            "counter",
+            "Tracks that could not be loaded.",
+            self.load_failures.load(Ordering::Relaxed),
+        );
+        metric(
+            "librespot_buffering_total",
+            "counter",
+            "Times playback waited for a track to load.",
+            self.buffering.load(Ordering::Relaxed),
+        );
+        metric(
+            "librespot_dropped_notifications_total",
+            "counter",
+            "Notifications skipped by clients that fell behind.",
+            self.dropped_notifications.load(Ordering::Relaxed),
+        );
+        metric(
+            "librespot_websocket_connections",
+            "gauge",
+            "Open websocket connections.",
+            websockets as u64,
+        );
+
+        let _ = writeln!(out, "# HELP librespot_player_state Current play state.");
+        let _ = writeln!(out, "# TYPE librespot_player_state gauge");
+        for s in ["playing", "paused", "stopped"] {
+            let value = u8::from(s == state);
+            let _ = writeln!(out, "librespot_player_state{{state=\"{s}\"}} {value}");
+        }
+
+        let name = "librespot_api_request_seconds";
+        let _ = writeln!(out, "# HELP {name} Time taken to handle API requests.");
+        let _ = writeln!(out, "# TYPE {name} summary");
+        for (method, (count, total)) in self.requests.lock().iter() {
+            let total = total.as_secs_f64();
+            let _ = writeln!(out, "{name}_sum{{method=\"{method}\"}} {total}");
+            let _ = writeln!(out, "{name}_count{{method=\"{method}\"}} {count}");
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resumed_track_is_counted_once() {
+        let metrics = Metrics::default();
+        let track_id = SpotifyId::from_uri("spotify:track:4uLU6hMCjMI75M1A2tKUQC").unwrap();
+        metrics.track_playing(1, track_id);
+        metrics.track_playing(1, track_id);
+        metrics.track_playing(2, track_id);
+        metrics.notifications_dropped(3);
+        metrics.request_handled("getStatus", Duration::from_millis(1));
+        metrics.request_handled("getStatus", Duration::from_millis(2));
+
+        let out = metrics.render("paused", 2);
+        assert!(out.contains("\nlibrespot_tracks_played_total 2\n"));
+        assert!(out.contains("\nlibrespot_dropped_notifications_total 3\n"));
+        assert!(out.contains("\nlibrespot_websocket_connections 2\n"));
+        assert!(out.contains("\nlibrespot_player_state{state=\"paused\"} 1\n"));
+        assert!(out.contains("\nlibrespot_player_state{state=\"playing\"} 0\n"));
+        assert!(out.contains("\nlibrespot_api_request_seconds_sum{method=\"getStatus\"} 0.003\n"));
+        assert!(out.contains("\nlibrespot_api_request_seconds_count{method=\"getStatus\"} 2\n"));
+    }
+}
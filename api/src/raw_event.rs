@@ -0,0 +1,350 @@
+use serde_json::json;
+
+use librespot_core::SpotifyId;
+use librespot_metadata::audio::{AudioItem, UniqueFields};
+use librespot_playback::player::PlayerEvent;
+
+// Convert a player event to JSON, keeping every field.
+// Meant for diagnostics, the shape follows the internal event and is not stable.
+pub fn to_json(event: &PlayerEvent) -> serde_json::Value {
+    match event {
+        PlayerEvent::PlayRequestIdChanged { play_request_id } => json!({
+            "event": "PlayRequestIdChanged",
+            "play_request_id": play_request_id,
+        }),
+        PlayerEvent::Stopped {
+            play_request_id,
+            track_id,
+        } => json!({
+            "event": "Stopped",
+            "play_request_id": play_request_id,
+            "track_id": id_to_json(track_id),
+        }),
+        PlayerEvent::Loading {
+            play_request_id,
+            track_id,
+            position_ms,
+        } => json!({
+            "event": "Loading",
+            "play_request_id": play_request_id,
+            "track_id": id_to_json(track_id),
+            "position_ms": position_ms,
+        }),
+        PlayerEvent::Preloading { track_id } => json!({
+            "event": "Preloading",
+            "track_id": id_to_json(track_id),
+        }),
+        PlayerEvent::Playing {
+            play_request_id,
+            track_id,
+            position_ms,
+        } => json!({
+            "event": "Playing",
+            "play_request_id": play_request_id,
+            "track_id": id_to_json(track_id),
+            "position_ms": position_ms,
+        }),
+        PlayerEvent::Paused {
+            play_request_id,
+            track_id,
+            position_ms,
+        } => json!({
+            "event": "Paused",
+            "play_request_id": play_request_id,
+            "track_id": id_to_json(track_id),
+            "position_ms": position_ms,
+        }),
+        PlayerEvent::TimeToPreloadNextTrack {
+            play_request_id,
+            track_id,
+        } => json!({
+            "event": "TimeToPreloadNextTrack",
+            "play_request_id": play_request_id,
+            "track_id": id_to_json(track_id),
+        }),
+        PlayerEvent::EndOfTrack {
+            play_request_id,
+            track_id,
+        } => json!({
+            "event": "EndOfTrack",
+            "play_request_id": play_request_id,
+            "track_id": id_to_json(track_id),
+        }),
+        PlayerEvent::Unavailable {
+            play_request_id,
+            track_id,
+            reason,
+        } => json!({
+            "event": "Unavailable",
+            "play_request_id": play_request_id,
+            "track_id": id_to_json(track_id),
+            "reason": reason.as_str(),
+        }),
+        PlayerEvent::VolumeChanged { volume } => json!({
+            "event": "VolumeChanged",
+            "volume": volume,
+        }),
+        PlayerEvent::PositionCorrection {
+            play_request_id,
+            track_id,
+            position_ms,
+        } => json!({
+            "event": "PositionCorrection",
+            "play_request_id": play_request_id,
+            "track_id": id_to_json(track_id),
+            "position_ms": position_ms,
+        }),
+        PlayerEvent::Seeked {
+            play_request_id,
+            track_id,
+            position_ms,
+        } => json!({
+            "event": "Seeked",
+            "play_request_id": play_request_id,
+            "track_id": id_to_json(track_id),
+            "position_ms": position_ms,
+        }),
+        PlayerEvent::TrackChanged {
+            audio_item,
+            position_ms,
+        } => json!({
+            "event": "TrackChanged",
+            "audio_item": audio_item_to_json(audio_item),
+            "position_ms": position_ms,
+        }),
+        PlayerEvent::SessionConnected {
+            connection_id,
+            user_name,
+        } => json!({
+            "event": "SessionConnected",
+            "connection_id": connection_id,
+            "user_name": user_name,
+        }),
+        PlayerEvent::SessionDisconnected {
+            connection_id,
+            user_name,
+        } => json!({
+            "event": "SessionDisconnected",
+            "connection_id": connection_id,
+            "user_name": user_name,
+        }),
+        PlayerEvent::SessionClientChanged {
+            client_id,
+            client_name,
+            client_brand_name,
+            client_model_name,
+        } => json!({
+            "event": "SessionClientChanged",
+            "client_id": client_id,
+            "client_name": client_name,
+            "client_brand_name": client_brand_name,
+            "client_model_name": client_model_name,
+        }),
+        PlayerEvent::ShuffleChanged { shuffle } => json!({
+            "event": "ShuffleChanged",
+            "shuffle": shuffle,
+        }),
+        PlayerEvent::RepeatChanged { repeat } => json!({
+            "event": "RepeatChanged",
+            "repeat": repeat,
+        }),
+        PlayerEvent::AutoPlayChanged { auto_play } => json!({
+            "event": "AutoPlayChanged",
+            "auto_play": auto_play,
+        }),
+        PlayerEvent::PlaybackComplete { context_uri } => json!({
+            "event": "PlaybackComplete",
+            "context_uri": context_uri,
+        }),
+        PlayerEvent::FilterExplicitContentChanged { filter } => json!({
+            "event": "FilterExplicitContentChanged",
+            "filter": filter,
+        }),
+        PlayerEvent::ExplicitContentSkipped { track_id } => json!({
+            "event": "ExplicitContentSkipped",
+            "track_id": id_to_json(track_id),
+        }),
+        PlayerEvent::AudioKeyUnavailable {
+            play_request_id,
+            track_id,
+        } => json!({
+            "event": "AudioKeyUnavailable",
+            "play_request_id": play_request_id,
+            "track_id": id_to_json(track_id),
+        }),
+        PlayerEvent::OutputFormat {
+            track_id,
+            format,
+            ditherer,
+        } => json!({
+            "event": "OutputFormat",
+            "track_id": id_to_json(track_id),
+            "format": format.map(|f| format!("{f:?}")),
+            "bit_depth": format.map(|f| f.bit_depth()),
+            "ditherer": ditherer,
+        }),
+        PlayerEvent::SinkStatusChanged { status } => json!({
+            "event": "SinkStatusChanged",
+            "status": status.as_str(),
+        }),
+        PlayerEvent::Transitioning {
+            from_track_id,
+            to_track_id,
+        } => json!({
+            "event": "Transitioning",
+            "from_track_id": id_to_json(from_track_id),
+            "to_track_id": id_to_json(to_track_id),
+        }),
+        PlayerEvent::TransitionFinished {
+            from_track_id,
+            to_track_id,
+        } => json!({
+            "event": "TransitionFinished",
+            "from_track_id": id_to_json(from_track_id),
+            "to_track_id": id_to_json(to_track_id),
+        }),
+        PlayerEvent::SinkError { message } => json!({
+            "event": "SinkError",
+            "message": message,
+        }),
+        PlayerEvent::FatalError { message } => json!({
+            "event": "FatalError",
+            "message": message,
+        }),
+        PlayerEvent::StateReset { message } => json!({
+            "event": "StateReset",
+            "message": message,
+        }),
+        PlayerEvent::BufferProgress {
+            play_request_id,
+            track_id,
+            fraction,
+            range_to_end_available,
+        } => json!({
+            "event": "BufferProgress",
+            "play_request_id": play_request_id,
+            "track_id": id_to_json(track_id),
+            "fraction": fraction,
+            "range_to_end_available": range_to_end_available,
+        }),
+        PlayerEvent::LimiterState {
+            active,
+            strength_db,
+            factor,
+        } => json!({
+            "event": "LimiterState",
+            "active": active,
+            "strength_db": strength_db,
+            "factor": factor,
+        }),
+        PlayerEvent::Levels { peak, rms } => json!({
+            "event": "Levels",
+            "peak": peak,
+            "rms": rms,
+        }),
+        PlayerEvent::PositionCheckpoint {
+            play_request_id,
+            track_id,
+            position_ms,
+        } => json!({
+            "event": "PositionCheckpoint",
+            "play_request_id": play_request_id,
+            "track_id": id_to_json(track_id),
+            "position_ms": position_ms,
+        }),
+        PlayerEvent::QualityReduced {
+            play_request_id,
+            track_id,
+            bitrate,
+        } => json!({
+            "event": "QualityReduced",
+            "play_request_id": play_request_id,
+            "track_id": id_to_json(track_id),
+            "bitrate": bitrate.kbps(),
+        }),
+        PlayerEvent::DecodeStats {
+            avg_decode_us,
+            packets,
+            skipped,
+        } => json!({
+            "event": "DecodeStats",
+            "avg_decode_us": avg_decode_us,
+            "packets": packets,
+            "skipped": skipped,
+        }),
+    }
+}
+
+// Spotify IDs as base62, null if the ID can't be represented
+fn id_to_json(id: &SpotifyId) -> serde_json::Value {
+    match id.to_base62() {
+        Ok(id) => json!(id),
+        Err(_) => serde_json::Value::Null,
+    }
+}
+
+fn audio_item_to_json(item: &AudioItem) -> serde_json::Value {
+    let files: serde_json::Map<String, serde_json::Value> = item
+        .files
+        .iter()
+        .map(|(format, file_id)| (format!("{format:?}"), json!(file_id.to_string())))
+        .collect();
+
+    let unique_fields = match &item.unique_fields {
+        UniqueFields::Track {
+            artists,
+            album,
+            album_artists,
+            popularity,
+            number,
+            disc_number,
+        } => json!({
+            "type": "Track",
+            "artists": artists.iter().map(|a| json!({
+                "id": id_to_json(&a.id),
+                "name": a.name,
+                "role": format!("{:?}", a.role),
+            })).collect::<Vec<_>>(),
+            "album": album,
+            "album_artists": album_artists,
+            "popularity": popularity,
+            "number": number,
+            "disc_number": disc_number,
+        }),
+        UniqueFields::Episode {
+            description,
+            publish_time,
+            show_name,
+        } => json!({
+            "type": "Episode",
+            "description": description,
+            "publish_time": publish_time.unix_timestamp(),
+            "show_name": show_name,
+        }),
+    };
+
+    json!({
+        "track_id": id_to_json(&item.track_id),
+        "uri": item.uri,
+        "files": files,
+        "name": item.name,
+        "covers": item.covers.iter().map(|c| json!({
+            "url": c.url,
+            "size": format!("{:?}", c.size),
+            "width": c.width,
+            "height": c.height,
+        })).collect::<Vec<_>>(),
+        "language": item.language,
+        "duration_ms": item.duration_ms,
+        "is_explicit": item.is_explicit,
+        "availability": match &item.availability {
+            Ok(()) => serde_json::Value::Null,
+            Err(reason) => json!(reason.to_string()),
+        },
+        "alternatives": item
+            .alternatives
+            .as_ref()
+            .map(|alternatives| alternatives.iter().map(id_to_json).collect::<Vec<_>>()),
+        "unique_fields": unique_fields,
+    })
+}
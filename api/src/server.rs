@@ -1,37 +1,221 @@
 use bytes::Bytes;
-use log::{debug, info};
-use parking_lot::RwLock;
+use flate2::{write::DeflateEncoder, Compression};
+use log::{debug, error, info, log_enabled, warn, Level};
+use parking_lot::{Mutex, RwLock};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use std::{
-    collections::HashMap,
+    collections::{hash_map::RandomState, HashMap, VecDeque},
+    convert::Infallible,
+    hash::{BuildHasher, Hasher},
+    io::Write,
     str,
     sync::{
-        atomic::{AtomicUsize, Ordering},
+        atomic::{AtomicBool, AtomicUsize, Ordering},
         Arc,
     },
     thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
-use futures_util::{SinkExt, StreamExt};
-use static_dir::static_dir;
-use tokio::sync::{broadcast, mpsc};
+use futures_util::{future::BoxFuture, stream, FutureExt, Sink, SinkExt, Stream, StreamExt};
+use thiserror::Error;
+use tokio::sync::{broadcast, mpsc, oneshot};
 use tokio_util::sync::CancellationToken;
-use warp::{ws, Filter};
+use warp::{filters::fs::File, http::StatusCode, sse, ws, Filter, Rejection, Reply};
 
-use crate::json_result::{JsonError, JsonResponse, JsonResult};
+use crate::{
+    chapters::{self, Chapter},
+    config::{ListenerConfig, ServerConfig},
+    cover_cache::{cover_id, CoverCache},
+    json_result::{JsonError, JsonId, JsonResponse, JsonResult},
+    metrics::Metrics,
+    raw_event,
+};
 
-use librespot_connect::spirc::SpircCommand;
-use librespot_metadata::{audio::AudioItem, audio::UniqueFields};
-use librespot_playback::player::{PlayerEvent, PlayerEventChannel};
+use librespot_connect::spirc::{SpircCommand, SpircLoadCommand};
+use librespot_core::{error::ErrorKind, Error, Session, SpotifyId};
+use librespot_metadata::{audio::AudioItem, audio::UniqueFields, MetadataError};
+use librespot_playback::{
+    config::{NormalisationMethod, NormalisationType, PlayerConfig, QueueEndBehavior},
+    dither,
+    event_channel::{player_event_channel, PlayerEventSender},
+    player::{
+        coefficient_to_duration, Player, PlayerEvent, PlayerEventChannel, PlayerStateSnapshot,
+        UnavailableReason,
+    },
+};
+use librespot_protocol::spirc::TrackRef;
 
 static UID_NEXT: AtomicUsize = AtomicUsize::new(1);
 
+// The bundled web UI, embedded at build time. Empty when ./static was empty then.
+static WEB_UI: static_dir::Dir<'static> = static_dir::include_dir!("./static");
+
+// Commands received before spirc is available are held back for a while
+const PENDING_COMMANDS_MAX: usize = 8;
+const PENDING_COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+
+// How long getTrackInfo waits for metadata before giving up
+const TRACK_INFO_TIMEOUT: Duration = Duration::from_secs(10);
+
+// How long websockets get to close when the server shuts down
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(1);
+// Time a websocket write may stall before it is retried, see ServerConfig::send_retries
+const WS_SEND_TIMEOUT: Duration = Duration::from_secs(5);
+// Interval between volume changes when setVolume ramps to a new volume
+const VOLUME_RAMP_STEP: Duration = Duration::from_millis(50);
+// Longest ramp setVolume accepts
+const VOLUME_RAMP_MAX: Duration = Duration::from_secs(60);
+// Number of errors kept for getRecentErrors, the oldest are dropped first
+const RECENT_ERRORS_MAX: usize = 50;
+// The vol+ and vol- shortcuts change the volume by this fraction of its maximum
+const VOLUME_SHORTCUT_STEPS: u16 = 20;
+
+type MethodResult = Result<serde_json::Value, JsonError>;
+
+// A JSON-RPC method, handled by calling `handler` with the request params
+type SyncHandler = fn(&ServerInternal, Option<serde_json::Value>) -> MethodResult;
+type AsyncHandler =
+    for<'a> fn(&'a ServerInternal, Option<serde_json::Value>) -> BoxFuture<'a, MethodResult>;
+
+// Methods that wait for the player or the network are async, so that they don't hold
+// up a runtime worker while waiting
+enum Handler {
+    Sync(SyncHandler),
+    Async(AsyncHandler),
+}
+
+struct Method {
+    name: &'static str,
+    handler: Handler,
+    // Only available with the debug flag
+    debug: bool,
+    // Name of the single value the method takes, which can also be passed bare
+    param: Option<&'static str>,
+}
+
+impl Method {
+    const fn new(name: &'static str, handler: SyncHandler) -> Self {
+        Self {
+            name,
+            handler: Handler::Sync(handler),
+            debug: false,
+            param: None,
+        }
+    }
+
+    const fn new_async(name: &'static str, handler: AsyncHandler) -> Self {
+        Self {
+            name,
+            handler: Handler::Async(handler),
+            debug: false,
+            param: None,
+        }
+    }
+
+    const fn debug(mut self) -> Self {
+        self.debug = true;
+        self
+    }
+
+    const fn param(mut self, name: &'static str) -> Self {
+        self.param = Some(name);
+        self
+    }
+}
+
+// All methods do_request dispatches to, also reported by listMethods and getServerInfo
+const METHODS: &[Method] = &[
+    Method::new("getServerInfo", ServerInternal::get_server_info),
+    Method::new_async("getConfig", |s, p| s.get_config(p).boxed()),
+    Method::new("listMethods", ServerInternal::list_methods),
+    Method::new("getStatus", ServerInternal::get_status),
+    Method::new("getVolume", ServerInternal::get_volume),
+    Method::new("getPlayState", ServerInternal::get_play_state),
+    Method::new("getProgress", ServerInternal::get_progress),
+    Method::new("getNowPlayingRef", ServerInternal::get_now_playing_ref),
+    Method::new("getCover", ServerInternal::get_cover),
+    Method::new_async("getTrackInfo", |s, p| s.get_track_info(p).boxed()),
+    Method::new("validateUri", ServerInternal::validate_uri),
+    Method::new_async("getBufferProgress", |s, p| s.get_buffer_progress(p).boxed()),
+    Method::new_async("getDecryptionStatus", |s, p| {
+        s.get_decryption_status(p).boxed()
+    }),
+    Method::new_async("getPlaybackSource", |s, p| s.get_playback_source(p).boxed()),
+    Method::new("getRecentErrors", ServerInternal::get_recent_errors),
+    Method::new("getSessionStats", ServerInternal::get_session_stats),
+    Method::new("setPlay", ServerInternal::set_play),
+    Method::new("setPause", ServerInternal::set_pause),
+    Method::new("setStop", ServerInternal::set_stop),
+    Method::new("setNext", ServerInternal::set_next),
+    Method::new("restartTrack", ServerInternal::restart_track),
+    Method::new("seekPercent", ServerInternal::seek_percent),
+    Method::new("getChapters", ServerInternal::get_chapters),
+    Method::new("seekChapter", ServerInternal::seek_chapter).param("index"),
+    Method::new("loadAndPlay", ServerInternal::load_and_play),
+    Method::new("exportSession", ServerInternal::export_session),
+    Method::new("importSession", ServerInternal::import_session).param("session"),
+    Method::new("setShuffleOn", ServerInternal::set_shuffle_on),
+    Method::new("setShuffleOff", ServerInternal::set_shuffle_off),
+    Method::new("getLimiterState", ServerInternal::get_limiter_state),
+    Method::new("getDecodeStats", ServerInternal::get_decode_stats),
+    Method::new("resetLimiter", ServerInternal::reset_limiter),
+    Method::new("resyncState", ServerInternal::resync_state),
+    Method::new("setGapless", ServerInternal::set_gapless).param("gapless"),
+    Method::new("setPreloadLeadTime", ServerInternal::set_preload_lead_time).param("lead_time_ms"),
+    Method::new("setBalance", ServerInternal::set_balance).param("balance"),
+    Method::new("setDuck", ServerInternal::set_duck).param("ducked"),
+    Method::new("setTrackGainOverride", ServerInternal::set_gain_override),
+    Method::new_async("getDitherer", |s, p| s.get_ditherer(p).boxed()),
+    Method::new("setDitherer", ServerInternal::set_ditherer).param("ditherer"),
+    Method::new_async("getNormalisationMethod", |s, p| {
+        s.get_normalisation(p).boxed()
+    }),
+    Method::new("setNormalisationMethod", ServerInternal::set_normalisation).param("method"),
+    Method::new("setVolume", ServerInternal::set_volume).param("volume"),
+    Method::new_async("getRawNormalisation", |s, p| {
+        s.get_raw_normalisation(p).boxed()
+    })
+    .debug(),
+];
+const NOTIFICATIONS: &[&str] = &[
+    "OnNewTrack",
+    "OnPause",
+    "OnPlay",
+    "OnStop",
+    "OnVolumeChange",
+    "OnShuffleChange",
+    "OnControlAvailable",
+    "OnExplicitSkipped",
+    "OnAudioKeyUnavailable",
+    "OnUnavailable",
+    "OnSinkStatus",
+    "OnBufferProgress",
+    "OnTransition",
+    "OnPlaybackComplete",
+    "OnQualityReduced",
+    "OnLevels",
+    "OnSubscribed",
+    "OnServerShutdown",
+    "OnStateDelta",
+    "OnRawEvent",
+];
+// Replaced by OnStateDelta for connections subscribed to deltas
+const STATE_NOTIFICATIONS: &[&str] = &[
+    "OnNewTrack",
+    "OnPause",
+    "OnPlay",
+    "OnStop",
+    "OnVolumeChange",
+    "OnShuffleChange",
+];
+
 // Expected request message
 #[allow(dead_code)]
 #[derive(Debug, Deserialize)]
 struct JsonRequest {
-    id: i64,
+    id: JsonId,
     jsonrpc: f32,
     method: String,
     params: Option<serde_json::Value>,
@@ -40,12 +224,71 @@ struct JsonRequest {
 // Internal notification types
 #[derive(Debug, Serialize)]
 enum Notification {
-    Play,
+    // With the play request id of the track
+    Play(Option<u64>),
     Pause,
     Stop,
-    NewTrack(Track),
+    // With the play request id and where playback of the track starts
+    NewTrack(Track, Option<u64>, u32),
     VolumeChange(u16),
     Shuffle(bool),
+    ControlAvailable(bool),
+    ExplicitSkipped(String),
+    // Track id of a track playing without decryption
+    AudioKeyUnavailable(String),
+    // Track id and why it could not be loaded
+    Unavailable(String, &'static str),
+    SinkStatus(&'static str),
+    // Downloaded fraction of the current track, and whether it is available to the end
+    BufferProgress(f64, bool),
+    Transition(serde_json::Value),
+    // Context uri of the queue that finished
+    PlaybackComplete(String),
+    // Track id of the stalling track and the bitrate the next tracks load with
+    QualityReduced(String, u16),
+    // Peak and RMS level per channel
+    Levels(Vec<f64>, Vec<f64>),
+    StateDelta(serde_json::Value),
+    RawEvent(serde_json::Value),
+}
+
+// Per-connection options, given as query parameters on the websocket upgrade
+#[derive(Debug, Default, Deserialize, Serialize, Clone)]
+#[serde(default)]
+struct Subscription {
+    // Receive changed state fields as OnStateDelta instead of the discrete
+    // state notifications, starting with the full state
+    delta: bool,
+    // Token from OnSubscribed of a dropped connection, restores its options
+    #[serde(skip_serializing)]
+    resume: Option<String>,
+    // How responses and notifications are encoded on the websocket
+    encoding: Encoding,
+    // Receive OnLevels for a level meter
+    levels: bool,
+    // Add emitted_at_ms to notifications caused by a player event
+    timestamps: bool,
+    // Leave covers out of tracks in notifications, getCover has them on demand
+    no_covers: bool,
+    // Accept text shortcuts such as "pause" besides JSON-RPC requests
+    shortcuts: bool,
+    // Deflate responses and notifications into binary frames, if the server allows it
+    compress: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Encoding {
+    // Text frames
+    Json,
+    // Binary frames, for constrained clients. Requests are still JSON text.
+    Msgpack,
+}
+
+impl Default for Encoding {
+    fn default() -> Self {
+        Self::Json
+    }
 }
 
 // Websocket notification message
@@ -55,6 +298,10 @@ struct JsonNotification {
     method: String,
     #[serde(skip_serializing_if = "serde_json::Value::is_null")]
     params: serde_json::Value,
+    // When the player emitted the event behind this notification, in Unix ms, only
+    // sent to connections subscribed with timestamps
+    #[serde(skip_serializing_if = "Option::is_none")]
+    emitted_at_ms: Option<u64>,
 }
 
 // State of player
@@ -72,15 +319,30 @@ struct Cover {
     size: (i32, i32),
 }
 
+// Music track or podcast episode
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum TrackKind {
+    Track,
+    Episode,
+}
+
 // Relevant track information
 #[derive(Debug, Serialize, Clone)]
 struct Track {
     track_id: String,
+    uri: String,
+    #[serde(rename = "type")]
+    kind: TrackKind,
     name: String,
     covers: Vec<Cover>,
     album: Option<String>,
     artists: Vec<String>,
     show_name: Option<String>,
+    duration_ms: u32,
+    // Only sent by getChapters
+    #[serde(skip)]
+    chapters: Vec<Chapter>,
 }
 
 // Player state
@@ -90,6 +352,148 @@ struct PlayerState {
     playing: PlayingState,
     volume: u16,
     shuffle: bool,
+    // Only sent by exportSession
+    #[serde(skip)]
+    repeat: bool,
+    // Last reported position, and when it was reported if playing
+    position_ms: u32,
+    #[serde(skip)]
+    position_measured_at: Option<Instant>,
+    // Duration of the track, as of the last Playing or Paused event
+    duration_ms: u32,
+    // A track is being loaded, playback commands can't be applied yet
+    #[serde(skip)]
+    loading: bool,
+    // Id of the load request the current track was played for
+    play_request_id: Option<u64>,
+}
+
+// Exported playback state, checked again when imported. The queue stays with spirc,
+// which doesn't expose it.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SessionExport {
+    version: u32,
+    // None without a track
+    uri: Option<String>,
+    position_ms: u32,
+    playing: bool,
+    // In the client scale of the exporting server, up to volume_max
+    volume: u16,
+    volume_max: u16,
+    shuffle: bool,
+    repeat: bool,
+}
+const SESSION_EXPORT_VERSION: u32 = 1;
+
+// Dynamic limiter state
+#[derive(Debug, Serialize, Clone, Copy)]
+struct LimiterState {
+    active: bool,
+    strength_db: f64,
+    factor: f64,
+}
+
+// Decoder performance as of the last DecodeStats event
+#[derive(Debug, Serialize, Clone, Copy)]
+struct DecodeStats {
+    avg_decode_us: u64,
+    packets: u64,
+    skipped: u64,
+}
+
+// Counters for getSessionStats, kept whether or not metrics are enabled
+struct SessionStats {
+    started: Instant,
+    tracks_played: u64,
+    // Last track counted as played, so resuming it isn't counted again
+    last_played: Option<(u64, SpotifyId)>,
+    // Play time up to the last pause or stop, and since when it is playing again
+    play_time: Duration,
+    playing_since: Option<Instant>,
+}
+
+impl SessionStats {
+    fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            tracks_played: 0,
+            last_played: None,
+            play_time: Duration::ZERO,
+            playing_since: None,
+        }
+    }
+
+    fn track_playing(&mut self, play_request_id: u64, track_id: SpotifyId) {
+        if self.last_played != Some((play_request_id, track_id)) {
+            self.last_played = Some((play_request_id, track_id));
+            self.tracks_played += 1;
+        }
+    }
+
+    fn set_playing(&mut self, playing: bool) {
+        match (playing, self.playing_since) {
+            (true, None) => self.playing_since = Some(Instant::now()),
+            (false, Some(since)) => {
+                self.play_time += since.elapsed();
+                self.playing_since = None;
+            }
+            _ => (),
+        }
+    }
+
+    fn play_time(&self) -> Duration {
+        match self.playing_since {
+            Some(since) => self.play_time + since.elapsed(),
+            None => self.play_time,
+        }
+    }
+}
+
+// Error or warning reported by the player, kept for getRecentErrors
+#[derive(Debug, Serialize, Clone)]
+struct RecentError {
+    // Milliseconds since the Unix epoch
+    time_ms: u64,
+    level: &'static str,
+    event: &'static str,
+    message: String,
+    track_id: Option<String>,
+}
+
+impl RecentError {
+    fn from_event(event: &PlayerEvent) -> Option<Self> {
+        let (level, event, message, track_id) = match event {
+            PlayerEvent::Unavailable {
+                track_id, reason, ..
+            } => (
+                "warning",
+                "unavailable",
+                reason.as_str().to_string(),
+                Some(track_id),
+            ),
+            PlayerEvent::AudioKeyUnavailable { track_id, .. } => (
+                "warning",
+                "audio_key_unavailable",
+                "Playing without decryption".to_string(),
+                Some(track_id),
+            ),
+            PlayerEvent::SinkError { message } => ("error", "sink_error", message.clone(), None),
+            PlayerEvent::FatalError { message } => ("error", "fatal_error", message.clone(), None),
+            PlayerEvent::StateReset { message } => ("error", "state_reset", message.clone(), None),
+            _ => return None,
+        };
+        let time_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_millis() as u64);
+        Some(Self {
+            time_ms,
+            level,
+            event,
+            message,
+            track_id: track_id.and_then(|id| id.to_base62().ok()),
+        })
+    }
 }
 
 // map for websocket ID -> task handle 
@@ -97,49 +501,109 @@ type UserTaskVec = Arc<RwLock<HashMap<usize, tokio::task::JoinHandle<()>>>>;
 
 struct ServerInternal {
     player_state: Arc<RwLock<PlayerState>>,
+    limiter_state: RwLock<LimiterState>,
+    // None until the player has decoded for a few seconds
+    decode_stats: RwLock<Option<DecodeStats>>,
     user_tasks: UserTaskVec,
     user_message_tx: broadcast::Sender<JsonNotification>,
+    // OnLevels only, about 20 a second, so that they can't crowd other notifications
+    // out of the channel of connections that didn't ask for them
+    levels_tx: broadcast::Sender<JsonNotification>,
     rt: tokio::runtime::Handle,
     cancel: CancellationToken,
+    // Cancelled to close websockets and event streams when rebinding, a child of
+    // `cancel` so they close on shutdown too
+    connections: RwLock<CancellationToken>,
     spirc: Arc<RwLock<Option<mpsc::UnboundedSender<SpircCommand>>>>,
+    pending_commands: Mutex<VecDeque<(Instant, SpircCommand)>>,
+    // None for a detached server
+    player: Option<Arc<Player>>,
+    // Session to look up tracks with, set once connected
+    session: RwLock<Option<Session>>,
+    // Sent to websocket clients with OnServerShutdown
+    shutdown_reason: Mutex<Option<String>>,
+    // Connections subscribed to OnLevels, the player only measures levels while
+    // there are any
+    levels_listeners: Mutex<usize>,
+    raw_events: bool,
+    debug: bool,
+    allowed_methods: Option<Vec<String>>,
+    denied_methods: Vec<String>,
+    volume_max: u16,
+    covers: Option<Arc<CoverCache>>,
+    metrics: Option<Metrics>,
+    // Subscriptions of dropped websockets by resume token, and when they dropped
+    resume_tokens: Mutex<HashMap<String, (Instant, Subscription)>>,
+    resume_ttl: Duration,
+    // Task sending the steps of the current setVolume ramp
+    volume_ramp: Mutex<Option<tokio::task::JoinHandle<()>>>,
+    // Latest debounced volume, sent to spirc at the end of the debounce window
+    pending_volume: Arc<Mutex<Option<u16>>>,
+    // seekPercent received while loading, applied once the duration of the track is known
+    pending_seek_percent: Mutex<Option<f64>>,
+    volume_debounce: Duration,
+    // Latest errors reported by the player, oldest first
+    recent_errors: Mutex<VecDeque<RecentError>>,
+    session_stats: Mutex<SessionStats>,
+    // Something is served under / when the web UI is enabled
+    web_ui_available: bool,
+    // Where the position of the current track is saved, restored once spirc is up
+    position_file: Option<String>,
+    position_restored: AtomicBool,
+    send_retries: u32,
+    ws_compression: bool,
+    // Wall clock time at startup, emitted_at_ms counts from it on the monotonic clock so
+    // that clock changes can't reorder notifications
+    clock_anchor: (Instant, Duration),
 }
 
 pub struct Server {
-    handle: thread::JoinHandle<()>,
+    handle: Option<thread::JoinHandle<()>>,
     internal: Arc<ServerInternal>,
+    rebind_tx: mpsc::UnboundedSender<RebindRequest>,
+}
+
+#[derive(Debug, Error)]
+pub enum RebindError {
+    #[error("API server is not running")]
+    NotRunning,
+    #[error("Unable to bind new listeners: {0}")]
+    Bind(String),
 }
 
+type RebindResult = Result<(), RebindError>;
+// New listeners, and where to report whether the server moved to them
+type RebindRequest = (Vec<ListenerConfig>, oneshot::Sender<RebindResult>);
+
 impl Server {
-    pub fn new(
+    pub fn new(player: Arc<Player>, config: ServerConfig) -> Self {
+        let player_events = player.get_player_event_channel();
+        Self::start(Some(player), player_events, config)
+    }
+
+    // Server without an embedded player, e.g. for developing clients without Spotify
+    // credentials. Events sent on the returned channel are handled as if the player sent
+    // them, spirc commands go to the channel given to `set_spirc_channel`. Methods that
+    // act on the player directly fail with NoControl.
+    pub fn new_detached(config: ServerConfig) -> (Self, PlayerEventSender) {
+        let (event_tx, player_events) = player_event_channel(None);
+        (Self::start(None, player_events, config), event_tx)
+    }
+
+    fn start(
+        player: Option<Arc<Player>>,
         mut player_events: PlayerEventChannel,
-        enable_web: bool,
-        custom_path: Option<String>,
+        config: ServerConfig,
     ) -> Self {
         info!("Starting api server thread");
 
         // New runtime for all web-API related stuff
         let rt = tokio::runtime::Runtime::new().expect("Unable to start server runtime");
 
-        let cancel = CancellationToken::new();
-
-        // websocket notification channel
-        let (pub_tx, _) = broadcast::channel::<JsonNotification>(16);
-
-        let state = Arc::new(ServerInternal {
-            player_state: Arc::new(RwLock::new(PlayerState {
-                track: None,
-                playing: PlayingState::Stopped,
-                volume: 0,
-                shuffle: false,
-            })),
-            user_tasks: Arc::new(RwLock::new(HashMap::new())),
-            user_message_tx: pub_tx,
-            rt: rt.handle().clone(),
-            cancel,
-            spirc: Arc::new(RwLock::new(None)),
-        });
+        let state = Arc::new(ServerInternal::new(player, &config, rt.handle().clone()));
 
         let state1 = state.clone();
+        let (rebind_tx, mut rebind_rx) = mpsc::unbounded_channel::<RebindRequest>();
 
         // New thread for web-API runtime
         let handle = thread::spawn(move || {
@@ -147,46 +611,70 @@ impl Server {
             let state2 = state1.clone();
             // Listen for librespot events 
             let _event_task = rt.spawn(async move {
-                loop {
-                    if let Some(e) = player_events.recv().await {
-                        state2.handle_internal_event(e);
-                    };
+                while let Some((emitted_at, e)) = player_events.recv_timestamped().await {
+                    state2.handle_player_event(e, emitted_at);
                 }
+
+                // Channel closed, the player is gone
+                warn!("Player event channel closed");
+                state2.handle_player_closed();
             });
 
             let state2 = state1.clone();
             let with_state = warp::any().map(move || state2.clone().to_owned());
 
-            // Websocket connection path
-            let ws_path = warp::path::end().and(ws()).and(with_state.clone()).map(
-                |ws: ws::Ws, state2: Arc<ServerInternal>| {
-                    debug!("New websocket connection");
-                    ws.on_upgrade(|sock| async move { state2.add_user(sock) })
-                },
-            );
+            // Websocket connection path. No permessage-deflate, tungstenite doesn't implement
+            // it and rejects frames with the RSV1 bit compressed client messages would carry.
+            // Clients can ask for compressed frames with `compress=true` instead.
+            let ws_path = warp::path::end()
+                .and(ws())
+                .and(warp::query::<Subscription>())
+                .and(with_state.clone())
+                .map(
+                    |ws: ws::Ws, subscription: Subscription, state2: Arc<ServerInternal>| {
+                        let mut subscription = state2.resume_subscription(subscription);
+                        subscription.compress &= state2.ws_compression;
+                        debug!("New websocket connection, {subscription:?}");
+                        ws.on_upgrade(|sock| async move { state2.add_user(sock, subscription) })
+                    },
+                );
+
+            // Server-sent events, for clients that can't use websockets
+            let events_path = warp::path("events")
+                .and(warp::path::end())
+                .and(warp::get())
+                .and(warp::query::<Subscription>())
+                .and(with_state.clone())
+                .map(|subscription: Subscription, state2: Arc<ServerInternal>| {
+                    debug!("New event stream, {subscription:?}");
+                    sse::reply(sse::keep_alive().stream(state2.event_stream(subscription)))
+                });
 
             // Http post path
             let post_path = warp::path::end()
                 .and(warp::post())
                 .and(warp::body::bytes())
                 .and(with_state.clone())
-                .map(|body: Bytes, state2: Arc<ServerInternal>| {
+                .and_then(|body: Bytes, state2: Arc<ServerInternal>| async move {
                     debug!("New http POST request");
                     let req: &str = str::from_utf8(body.as_ref()).unwrap();
-                    match state2.handle_request(req) {
+                    let res = match state2.handle_request(req).await {
                         Ok(res) => {
                             serde_json::to_string(&res).expect("Unable to serialize response")
                         }
                         Err(err) => {
                             serde_json::to_string(&err).expect("Unable to serialize error response")
                         }
-                    }
+                    };
+                    Ok::<_, Rejection>(res)
                 });
 
+            let enable_web = config.enable_web;
+
             // Use custom dir if it is specified
-            let custom_dir = custom_path.is_some();
+            let custom_dir = config.custom_path.is_some();
 
-            let dir = match custom_path {
+            let dir = match config.custom_path {
                 Some(s) => s,
                 None => "".to_string(),
             };
@@ -212,39 +700,206 @@ impl Server {
                         Err(warp::reject::not_found())
                     }
                 })
-                .and(static_dir!("./static"))
+                .and(static_dir::dir(&WEB_UI))
+                .map(|_, d| d);
+
+            // Named bundles under /ui/<name>/, rejects when there are none
+            let no_bundle = warp::any()
+                .and_then(|| async { Err::<File, _>(warp::reject::not_found()) })
+                .boxed();
+            let bundles = config
+                .web_bundles
+                .into_iter()
+                .fold(no_bundle, |bundles, (name, dir)| {
+                    debug!("Serving web bundle \"{name}\" from {dir}");
+                    let bundle = warp::path("ui")
+                        .and(warp::path(name))
+                        .and(warp::fs::dir(dir));
+                    bundles.or(bundle).unify().boxed()
+                });
+            let get_path_bundles = warp::any()
+                .and_then(move || async move {
+                    if enable_web {
+                        Ok(())
+                    } else {
+                        Err(warp::reject::not_found())
+                    }
+                })
+                .and(bundles)
                 .map(|_, d| d);
 
-            let path = post_path
+            // Album art proxy
+            let cover_path = warp::path!("cover" / String)
+                .and(warp::get())
+                .and(with_state.clone())
+                .and_then(|id: String, state2: Arc<ServerInternal>| async move {
+                    state2.cover(id).await
+                });
+
+            // Prometheus metrics
+            let metrics_path = warp::path("metrics")
+                .and(warp::path::end())
+                .and(warp::get())
+                .and(with_state.clone())
+                .and_then(|state2: Arc<ServerInternal>| async move { state2.metrics() });
+
+            let api_path = post_path
                 .or(ws_path)
-                .or(get_path_custom)
-                .or(get_path_static);
+                .or(events_path)
+                .or(cover_path)
+                .or(metrics_path);
+            let web_path = get_path_bundles.or(get_path_custom).or(get_path_static);
+
+            // One server per listener, sharing the state. Only access differs. They
+            // stop accepting connections once `connections` is cancelled. Fails if any
+            // of them can't be bound, releasing those that could.
+            let serve = |listeners: &[ListenerConfig]| {
+                let connections = state1.connections.read().clone();
+                let mut http_servers = Vec::with_capacity(listeners.len());
+                for listener in listeners {
+                    let path = authorized(listener.token.clone())
+                        .and(api_path.clone())
+                        .or(web_path.clone())
+                        .recover(unauthorized);
+                    let server = warp::serve(path);
+                    let addr = listener.addr;
+                    let stop = connections.clone().cancelled_owned();
+                    let http_server = match &listener.tls {
+                        Some(tls) => {
+                            let server = server.tls().cert_path(&tls.cert_path);
+                            let server = server.key_path(&tls.key_path);
+                            let (_, server) = server.try_bind_with_graceful_shutdown(addr, stop)?;
+                            info!("Api server listening on https://{addr}");
+                            server.boxed()
+                        }
+                        None => {
+                            let (_, server) = server.try_bind_with_graceful_shutdown(addr, stop)?;
+                            info!("Api server listening on http://{addr}");
+                            server.boxed()
+                        }
+                    };
+                    http_servers.push(http_server);
+                }
+                Ok::<_, warp::Error>(futures_util::future::join_all(http_servers))
+            };
+
+            // Wait for server to fail, or internal cancellation. Rebinding closes the
+            // current connections and starts over on the new listeners, or on the old
+            // ones again if the new ones can't be bound.
+            rt.block_on(async {
+                let mut listeners = config.listeners;
+                let mut http_server = match serve(&listeners) {
+                    Ok(http_server) => http_server,
+                    Err(e) => panic!("Unable to start API server: {e}"),
+                };
+                loop {
+                    let (rebind, reply) = tokio::select! {
+                        _ = &mut http_server => break,
+                        _ = state1.cancel.cancelled() => break,
+                        Some(rebind) = rebind_rx.recv() => rebind,
+                    };
+
+                    info!("Rebinding API server");
+                    state1.close_connections();
+                    let stopped = tokio::time::timeout(SHUTDOWN_TIMEOUT, &mut http_server).await;
+                    if stopped.is_err() {
+                        debug!("Connections did not close in time");
+                    }
+                    // Frees the old addresses, so that listeners can move between them
+                    drop(http_server);
 
-            let http_server = Box::pin(warp::serve(path).run(([0, 0, 0, 0], 3030)));
+                    http_server = match serve(&rebind) {
+                        Ok(http_server) => {
+                            listeners = rebind;
+                            let _ = reply.send(Ok(()));
+                            http_server
+                        }
+                        Err(e) => {
+                            warn!("Unable to rebind API server, keeping its listeners: {e}");
+                            let _ = reply.send(Err(RebindError::Bind(e.to_string())));
+                            match serve(&listeners) {
+                                Ok(http_server) => http_server,
+                                Err(e) => {
+                                    error!("Unable to bind API server again: {e}");
+                                    break;
+                                }
+                            }
+                        }
+                    };
+                }
+            });
 
-            // Wait for server to fail, or internal cancellation
+            // Let websockets send OnServerShutdown and close, but don't hang on slow clients
+            let tasks: Vec<_> = state1.user_tasks.write().drain().map(|(_, t)| t).collect();
             rt.block_on(async {
-                    tokio::select! {
-                    _ = http_server => {},
-                    _ = state1.cancel.cancelled() => {},
+                let closed = futures_util::future::join_all(tasks);
+                let closed = tokio::time::timeout(SHUTDOWN_TIMEOUT, closed).await;
+                if closed.is_err() {
+                    debug!("Websockets did not close in time");
                 }
             });
 
-            // TODO: wait for all websocket tasks to finish
             info!("Shutting down API server")
         });
 
         Self {
-            handle,
+            handle: Some(handle),
             internal: state,
+            rebind_tx,
         }
     }
 
+    // Move the server to new listeners without restarting it. Open websockets and
+    // event streams are closed, their clients need to reconnect to the new address
+    // and can resume their subscriptions as usual. If any of the new listeners can't
+    // be bound, the server goes back to the old ones and the error is returned.
+    pub async fn rebind(&self, listeners: Vec<ListenerConfig>) -> RebindResult {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.rebind_tx
+            .send((listeners, reply_tx))
+            .map_err(|_| RebindError::NotRunning)?;
+        reply_rx.await.map_err(|_| RebindError::NotRunning)?
+    }
+
     // set the current spirc channel
     pub fn set_spirc_channel(&self, spirc: mpsc::UnboundedSender<SpircCommand>) {
         debug!("Spirc command channel set");
         let mut channel = self.internal.spirc.write();
+
+        // Flush commands that arrived before the channel was set,
+        // holding the lock so that new commands end up after them.
+        for (received, command) in self.internal.pending_commands.lock().drain(..) {
+            if received.elapsed() > PENDING_COMMAND_TIMEOUT {
+                debug!("Dropping expired spirc command: {command:?}");
+                continue;
+            }
+
+            debug!("Sending queued spirc command: {command:?}");
+            if let Err(e) = spirc.send(command) {
+                warn!("Unable to send queued spirc command: {e}");
+            }
+        }
+
         *channel = Some(spirc);
+        drop(channel);
+        self.internal.restore_position();
+    }
+
+    // set the session used to look up tracks, replacing the previous one on reconnect
+    pub fn set_session(&self, session: Session) {
+        *self.internal.session.write() = Some(session);
+    }
+
+    // Stop the server, telling websocket clients why with OnServerShutdown. Returns
+    // once they have been closed. Dropping the server stops it without a reason.
+    pub fn shutdown(mut self, reason: Option<String>) {
+        *self.internal.shutdown_reason.lock() = reason;
+        self.internal.cancel.cancel();
+        if let Some(handle) = self.handle.take() {
+            if handle.join().is_err() {
+                warn!("API server thread panicked");
+            }
+        }
     }
 }
 
@@ -255,37 +910,216 @@ impl Drop for Server {
 }
 
 impl ServerInternal {
-    // Recieves internal event, updates state, sends notifications
-    fn handle_internal_event(&self, player_event: PlayerEvent) {
+    fn new(player: Option<Arc<Player>>, config: &ServerConfig, rt: tokio::runtime::Handle) -> Self {
+        let cancel = CancellationToken::new();
+
+        let configured = config.allowed_methods.iter().flatten();
+        for method in configured.chain(config.denied_methods.iter()) {
+            if !METHODS.iter().any(|m| m.name == method) {
+                warn!("Unknown API method in allow or deny list: {method}");
+            }
+        }
+
+        let web_ui_available = config.enable_web
+            && (config.custom_path.is_some() || WEB_UI.get_file("index.html").is_some());
+        if config.enable_web && !web_ui_available {
+            warn!("Web interface enabled, but no web UI was bundled at build time. Use `--custom-web-dir` or rebuild with the UI in api/static.");
+        }
+
+        // websocket notification channel
+        let (pub_tx, _) = broadcast::channel::<JsonNotification>(16);
+        let (levels_tx, _) = broadcast::channel::<JsonNotification>(16);
+
+        Self {
+            player_state: Arc::new(RwLock::new(PlayerState {
+                track: None,
+                playing: PlayingState::Stopped,
+                volume: 0,
+                shuffle: false,
+                repeat: false,
+                position_ms: 0,
+                position_measured_at: None,
+                duration_ms: 0,
+                loading: false,
+                play_request_id: None,
+            })),
+            limiter_state: RwLock::new(LimiterState {
+                active: false,
+                strength_db: 0.0,
+                factor: 1.0,
+            }),
+            decode_stats: RwLock::new(None),
+            user_tasks: Arc::new(RwLock::new(HashMap::new())),
+            user_message_tx: pub_tx,
+            levels_tx,
+            rt,
+            connections: RwLock::new(cancel.child_token()),
+            cancel,
+            spirc: Arc::new(RwLock::new(None)),
+            pending_commands: Mutex::new(VecDeque::new()),
+            player,
+            session: RwLock::new(None),
+            shutdown_reason: Mutex::new(None),
+            levels_listeners: Mutex::new(0),
+            raw_events: config.raw_events,
+            debug: config.debug,
+            allowed_methods: config.allowed_methods.clone(),
+            denied_methods: config.denied_methods.clone(),
+            covers: config.cover_proxy.then(|| Arc::new(CoverCache::new())),
+            metrics: config.metrics.then(Metrics::default),
+            volume_max: config.volume_max,
+            resume_tokens: Mutex::new(HashMap::new()),
+            resume_ttl: config.resume_ttl,
+            volume_ramp: Mutex::new(None),
+            pending_seek_percent: Mutex::new(None),
+            pending_volume: Arc::new(Mutex::new(None)),
+            volume_debounce: config.volume_debounce,
+            recent_errors: Mutex::new(VecDeque::new()),
+            session_stats: Mutex::new(SessionStats::new()),
+            web_ui_available,
+            position_file: config.position_file.clone(),
+            position_restored: AtomicBool::new(false),
+            send_retries: config.send_retries,
+            ws_compression: config.ws_compression,
+            clock_anchor: (
+                Instant::now(),
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default(),
+            ),
+        }
+    }
+
+    // Recieves internal event, updates state, sends notifications. Positions were
+    // measured when the player emitted the event, not when it is handled.
+    fn handle_player_event(&self, player_event: PlayerEvent, emitted_at: Instant) {
         let mut notif: Option<Notification> = None;
+        let mut seek_ms = None;
+        let delta;
         debug!("Recieved PlayerEvent: {player_event:?}");
 
+        if self.raw_events {
+            let raw_event = raw_event::to_json(&player_event);
+            self.forward_event(Notification::RawEvent(raw_event), Some(emitted_at));
+        }
+
+        // Frequent and not part of the state
+        if let PlayerEvent::Levels { peak, rms } = player_event {
+            self.forward_event(Notification::Levels(peak, rms), Some(emitted_at));
+            return;
+        }
+
+        if let PlayerEvent::PositionCheckpoint {
+            track_id,
+            position_ms,
+            ..
+        } = player_event
+        {
+            self.save_position(track_id, position_ms);
+            return;
+        }
+
+        if let Some(error) = RecentError::from_event(&player_event) {
+            let mut recent_errors = self.recent_errors.lock();
+            if recent_errors.len() == RECENT_ERRORS_MAX {
+                recent_errors.pop_front();
+            }
+            recent_errors.push_back(error);
+        }
+
         {
             // Needs to drop lock before sending notification,
             // otherwise forward_event will wait forever to lock the same variable
             let mut state = self.player_state.write();
+            let before = json!(&*state);
 
             match player_event {
-                PlayerEvent::Playing { .. } => {
+                PlayerEvent::PlayRequestIdChanged { play_request_id } => {
+                    state.play_request_id = Some(play_request_id);
+                }
+                PlayerEvent::Playing {
+                    play_request_id,
+                    track_id,
+                    position_ms,
+                } => {
+                    if let Some(metrics) = &self.metrics {
+                        metrics.track_playing(play_request_id, track_id);
+                    }
+                    self.session_stats
+                        .lock()
+                        .track_playing(play_request_id, track_id);
                     state.playing = PlayingState::Playing;
-                    notif = Some(Notification::Play);
+                    state.loading = false;
+                    state.play_request_id = Some(play_request_id);
+                    state.set_position(position_ms, emitted_at);
+                    state.duration_ms = state.track.as_ref().map_or(0, |t| t.duration_ms);
+                    notif = Some(Notification::Play(state.play_request_id));
                 }
-                PlayerEvent::Paused { .. } => {
+                PlayerEvent::Paused { position_ms, .. } => {
                     state.playing = PlayingState::Paused;
+                    state.loading = false;
+                    state.set_position(position_ms, emitted_at);
+                    state.duration_ms = state.track.as_ref().map_or(0, |t| t.duration_ms);
                     notif = Some(Notification::Pause);
                 }
-                PlayerEvent::Stopped { .. } => {
+                PlayerEvent::Stopped { .. } | PlayerEvent::StateReset { .. } => {
                     state.playing = PlayingState::Stopped;
+                    state.loading = false;
                     state.track = None;
+                    state.play_request_id = None;
+                    state.set_position(0, emitted_at);
+                    state.duration_ms = 0;
                     notif = Some(Notification::Stop);
                 }
-                PlayerEvent::TrackChanged { audio_item } => {
+                PlayerEvent::Loading { position_ms, .. } => {
+                    if let Some(metrics) = &self.metrics {
+                        metrics.buffering();
+                    }
+                    *self.pending_seek_percent.lock() = None;
+                    state.loading = true;
+                    state.set_position(position_ms, emitted_at);
+                }
+                PlayerEvent::Unavailable {
+                    track_id, reason, ..
+                } => {
+                    if let Some(metrics) = &self.metrics {
+                        metrics.load_failed();
+                    }
+                    *self.pending_seek_percent.lock() = None;
+                    state.loading = false;
+                    match track_id.to_base62() {
+                        Ok(id) => notif = Some(Notification::Unavailable(id, reason.as_str())),
+                        Err(e) => warn!("Unavailable track has invalid id: {e}"),
+                    }
+                }
+                PlayerEvent::EndOfTrack { .. } => {
+                    state.loading = false;
+                }
+                PlayerEvent::Seeked { position_ms, .. }
+                | PlayerEvent::PositionCorrection { position_ms, .. } => {
+                    state.set_position(position_ms, emitted_at);
+                }
+                PlayerEvent::TrackChanged {
+                    audio_item,
+                    position_ms,
+                } => {
                     let track = Track::from_audio_item(*audio_item);
+                    if let Some(percent) = self.pending_seek_percent.lock().take() {
+                        seek_ms = Some(percent_position_ms(percent, track.duration_ms));
+                    }
+                    self.prewarm_cover(&track);
                     state.track = Some(track.clone());
+                    state.set_position(position_ms, emitted_at);
                     debug!("New track recieved: {track:?}");
-                    notif = Some(Notification::NewTrack(track));
+                    notif = Some(Notification::NewTrack(
+                        track,
+                        state.play_request_id,
+                        position_ms,
+                    ));
                 }
                 PlayerEvent::VolumeChanged { volume } => {
+                    // State is kept in the client volume scale
+                    let volume = self.volume_to_client(volume);
                     state.volume = volume;
                     notif = Some(Notification::VolumeChange(volume));
                 }
@@ -293,81 +1127,358 @@ impl ServerInternal {
                     state.shuffle = shuffle;
                     notif = Some(Notification::Shuffle(shuffle));
                 }
+                PlayerEvent::RepeatChanged { repeat } => state.repeat = repeat,
+                PlayerEvent::ExplicitContentSkipped { track_id } => match track_id.to_base62() {
+                    Ok(id) => notif = Some(Notification::ExplicitSkipped(id)),
+                    Err(e) => warn!("Skipped explicit track has invalid id: {e}"),
+                },
+                PlayerEvent::AudioKeyUnavailable { track_id, .. } => match track_id.to_base62() {
+                    Ok(id) => notif = Some(Notification::AudioKeyUnavailable(id)),
+                    Err(e) => warn!("Track without audio key has invalid id: {e}"),
+                },
+                PlayerEvent::QualityReduced {
+                    track_id, bitrate, ..
+                } => match track_id.to_base62() {
+                    Ok(id) => notif = Some(Notification::QualityReduced(id, bitrate.kbps())),
+                    Err(e) => warn!("Stalling track has invalid id: {e}"),
+                },
+                PlayerEvent::Transitioning {
+                    from_track_id,
+                    to_track_id,
+                } => notif = transition_notification("started", from_track_id, to_track_id),
+                PlayerEvent::TransitionFinished {
+                    from_track_id,
+                    to_track_id,
+                } => notif = transition_notification("finished", from_track_id, to_track_id),
+                PlayerEvent::SinkStatusChanged { status } => {
+                    notif = Some(Notification::SinkStatus(status.as_str()));
+                }
+                PlayerEvent::PlaybackComplete { context_uri } => {
+                    notif = Some(Notification::PlaybackComplete(context_uri));
+                }
+                PlayerEvent::BufferProgress {
+                    fraction,
+                    range_to_end_available,
+                    ..
+                } => {
+                    notif = Some(Notification::BufferProgress(
+                        fraction,
+                        range_to_end_available,
+                    ));
+                }
+                PlayerEvent::LimiterState {
+                    active,
+                    strength_db,
+                    factor,
+                } => {
+                    *self.limiter_state.write() = LimiterState {
+                        active,
+                        strength_db,
+                        factor,
+                    };
+                }
+                PlayerEvent::DecodeStats {
+                    avg_decode_us,
+                    packets,
+                    skipped,
+                } => {
+                    *self.decode_stats.write() = Some(DecodeStats {
+                        avg_decode_us,
+                        packets,
+                        skipped,
+                    });
+                }
                 _ => {}
             }
+
+            let playing = matches!(state.playing, PlayingState::Playing);
+            self.session_stats.lock().set_playing(playing);
+            delta = state_delta(&before, &json!(&*state));
         }
 
         if let Some(n) = notif {
-            self.forward_event(n);
+            self.forward_event(n, Some(emitted_at));
+        }
+        if let Some(d) = delta {
+            self.forward_event(Notification::StateDelta(d), Some(emitted_at));
+        }
+        if let Some(position_ms) = seek_ms {
+            if let Err(e) = self.send_command(SpircCommand::SetPosition(position_ms)) {
+                warn!("Unable to apply queued seek: {e:?}");
+            }
+        }
+    }
+
+    // Saves the position as loadAndPlay params, replacing the file so that it's never
+    // left half written
+    fn save_position(&self, track_id: SpotifyId, position_ms: u32) {
+        let (path, uri) = match (&self.position_file, track_id.to_uri()) {
+            (Some(path), Ok(uri)) => (path, uri),
+            _ => return,
+        };
+
+        let saved = json!({"uri": uri, "position_ms": position_ms}).to_string();
+        let tmp_path = format!("{path}.tmp");
+        let written =
+            std::fs::write(&tmp_path, saved).and_then(|_| std::fs::rename(&tmp_path, path));
+        if let Err(e) = written {
+            warn!("Unable to save position to {path}: {e}");
+        }
+    }
+
+    // Loads the saved track paused at its saved position, only for the first spirc
+    fn restore_position(&self) {
+        if self.position_restored.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        let path = match self.position_file {
+            Some(ref path) => path,
+            None => return,
+        };
+        let saved = match std::fs::read_to_string(path) {
+            Ok(saved) => saved,
+            Err(e) => {
+                debug!("No position to restore from {path}: {e}");
+                return;
+            }
+        };
+
+        let params = serde_json::from_str::<serde_json::Value>(&saved).map(|mut params| {
+            params["play"] = json!(false);
+            params
+        });
+        let restored = match params {
+            Ok(params) => self.load_and_play(Some(params)),
+            Err(e) => Err(JsonError::parse(Some(e.to_string()))),
+        };
+        match restored {
+            Ok(_) => info!("Restored position from {path}"),
+            Err(e) => warn!("Unable to restore position from {path}: {e}"),
+        }
+    }
+
+    // Player has shut down, reset state and tell clients there is nothing to control
+    fn handle_player_closed(&self) {
+        let delta = {
+            let mut state = self.player_state.write();
+            let before = json!(&*state);
+            state.playing = PlayingState::Stopped;
+            state.track = None;
+            state.play_request_id = None;
+            state.loading = false;
+            state.set_position(0, Instant::now());
+            state.duration_ms = 0;
+            self.session_stats.lock().set_playing(false);
+            state_delta(&before, &json!(&*state))
+        };
+
+        self.forward_event(Notification::Stop, None);
+        if let Some(d) = delta {
+            self.forward_event(Notification::StateDelta(d), None);
         }
+        self.forward_event(Notification::ControlAvailable(false), None);
     }
 
-    // Forward notifications as JsonNotifications to connected websockets
-    fn forward_event(&self, event: Notification) {
-        if self.user_message_tx.receiver_count() != 0 {
+    // Forward notifications as JsonNotifications to connected websockets, with the time
+    // of the player event that caused them if any
+    fn forward_event(&self, event: Notification, emitted_at: Option<Instant>) {
+        let channel = match event {
+            Notification::Levels(..) => &self.levels_tx,
+            _ => &self.user_message_tx,
+        };
+        if channel.receiver_count() != 0 {
             debug!("Sending notification to connected websockets");
-            let m = match event {
-                Notification::NewTrack(track) => JsonNotification {
+            let mut m = match event {
+                Notification::NewTrack(track, play_request_id, position_ms) => JsonNotification {
                     jsonrpc: 2.0,
                     method: "OnNewTrack".to_string(),
-                    params: json!({"track": track}),
+                    params: json!({
+                        "track": track,
+                        "play_request_id": play_request_id,
+                        "position_ms": position_ms,
+                    }),
+                    emitted_at_ms: None,
                 },
                 Notification::Pause => JsonNotification {
                     jsonrpc: 2.0,
                     method: "OnPause".to_string(),
                     params: serde_json::Value::Null,
+                    emitted_at_ms: None,
                 },
-                Notification::Play => JsonNotification {
+                Notification::Play(play_request_id) => JsonNotification {
                     jsonrpc: 2.0,
                     method: "OnPlay".to_string(),
-                    params: serde_json::Value::Null,
+                    params: json!({"play_request_id": play_request_id}),
+                    emitted_at_ms: None,
                 },
                 Notification::Stop => JsonNotification {
                     jsonrpc: 2.0,
                     method: "OnStop".to_string(),
                     params: serde_json::Value::Null,
+                    emitted_at_ms: None,
                 },
                 Notification::VolumeChange(vol) => JsonNotification {
                     jsonrpc: 2.0,
                     method: "OnVolumeChange".to_string(),
                     params: json!({"volume": vol}),
+                    emitted_at_ms: None,
                 },
                 Notification::Shuffle(shuffle) => JsonNotification {
                     jsonrpc: 2.0,
                     method: "OnShuffleChange".to_string(),
                     params: json!({"shuffle": shuffle}),
+                    emitted_at_ms: None,
                 },
-            };
-
-            // Errors if last receiver dropped since check,
-            // unlikely and can be ignored.
-            let _ = self.user_message_tx.send(m);
-        }
-    }
-
-    // Add new websocket
-    fn add_user(self: Arc<Self>, sock: warp::ws::WebSocket) {
-        let mut event_channel = self.user_message_tx.subscribe();
-
-        // Atomic ID
-        let uid = UID_NEXT.fetch_add(1, Ordering::Relaxed);
-        let num_open = self.user_message_tx.receiver_count();
-        debug!("Adding new websocket connection, ID: {uid}, currently open: {num_open}");
-
-        let users = self.user_tasks.clone();
-        let state = self.clone();
-        let cancel = self.cancel.clone();
-
-        // New thread for websocket connection
-        let thr = self.rt.spawn(async move {
-            let (mut tx, mut ws_rx) = sock.split();
+                Notification::ControlAvailable(available) => JsonNotification {
+                    jsonrpc: 2.0,
+                    method: "OnControlAvailable".to_string(),
+                    params: json!({"available": available}),
+                    emitted_at_ms: None,
+                },
+                Notification::ExplicitSkipped(track_id) => JsonNotification {
+                    jsonrpc: 2.0,
+                    method: "OnExplicitSkipped".to_string(),
+                    params: json!({"track_id": track_id}),
+                    emitted_at_ms: None,
+                },
+                Notification::AudioKeyUnavailable(track_id) => JsonNotification {
+                    jsonrpc: 2.0,
+                    method: "OnAudioKeyUnavailable".to_string(),
+                    params: json!({"track_id": track_id}),
+                    emitted_at_ms: None,
+                },
+                Notification::Unavailable(track_id, reason) => JsonNotification {
+                    jsonrpc: 2.0,
+                    method: "OnUnavailable".to_string(),
+                    params: json!({"track_id": track_id, "reason": reason}),
+                    emitted_at_ms: None,
+                },
+                Notification::Transition(transition) => JsonNotification {
+                    jsonrpc: 2.0,
+                    method: "OnTransition".to_string(),
+                    params: transition,
+                    emitted_at_ms: None,
+                },
+                Notification::PlaybackComplete(context_uri) => JsonNotification {
+                    jsonrpc: 2.0,
+                    method: "OnPlaybackComplete".to_string(),
+                    params: json!({"context_uri": context_uri}),
+                    emitted_at_ms: None,
+                },
+                Notification::QualityReduced(track_id, bitrate) => JsonNotification {
+                    jsonrpc: 2.0,
+                    method: "OnQualityReduced".to_string(),
+                    params: json!({"track_id": track_id, "bitrate": bitrate}),
+                    emitted_at_ms: None,
+                },
+                Notification::SinkStatus(status) => JsonNotification {
+                    jsonrpc: 2.0,
+                    method: "OnSinkStatus".to_string(),
+                    params: json!({"status": status}),
+                    emitted_at_ms: None,
+                },
+                Notification::Levels(peak, rms) => JsonNotification {
+                    jsonrpc: 2.0,
+                    method: "OnLevels".to_string(),
+                    params: json!({"peak": peak, "rms": rms}),
+                    emitted_at_ms: None,
+                },
+                Notification::BufferProgress(fraction, range_to_end_available) => {
+                    JsonNotification {
+                        jsonrpc: 2.0,
+                        method: "OnBufferProgress".to_string(),
+                        params: json!({
+                            "fraction": fraction,
+                            "range_to_end_available": range_to_end_available,
+                        }),
+                        emitted_at_ms: None,
+                    }
+                }
+                Notification::StateDelta(delta) => JsonNotification {
+                    jsonrpc: 2.0,
+                    method: "OnStateDelta".to_string(),
+                    params: delta,
+                    emitted_at_ms: None,
+                },
+                Notification::RawEvent(event) => JsonNotification {
+                    jsonrpc: 2.0,
+                    method: "OnRawEvent".to_string(),
+                    params: json!({"event": event}),
+                    emitted_at_ms: None,
+                },
+            };
+
+            m.emitted_at_ms = emitted_at.map(|t| self.unix_ms(t));
+
+            // Errors if last receiver dropped since check,
+            // unlikely and can be ignored.
+            let _ = channel.send(m);
+        }
+    }
+
+    // Unix time in ms of a monotonic instant, see clock_anchor
+    fn unix_ms(&self, instant: Instant) -> u64 {
+        let (anchor, unix) = self.clock_anchor;
+        let unix = match instant.checked_duration_since(anchor) {
+            Some(since) => unix + since,
+            None => unix.saturating_sub(anchor - instant),
+        };
+        unix.as_millis() as u64
+    }
+
+    // Add new websocket
+    fn add_user(self: Arc<Self>, sock: warp::ws::WebSocket, subscription: Subscription) {
+        let mut event_channel = self.user_message_tx.subscribe();
+
+        // Atomic ID
+        let uid = UID_NEXT.fetch_add(1, Ordering::Relaxed);
+        let num_open = self.user_message_tx.receiver_count();
+        debug!("Adding new websocket connection, ID: {uid}, currently open: {num_open}");
+
+        let users = self.user_tasks.clone();
+        let state = self.clone();
+        let cancel = self.connections.read().clone();
+        let mut levels_listener = self.levels_listener(&subscription);
+
+        // New thread for websocket connection
+        let thr = self.rt.spawn(async move {
+            let (mut tx, mut ws_rx) = sock.split();
+            let resume_token = new_resume_token();
+
+            // Tell the client how to resume this subscription if the connection drops
+            let subscribed = JsonNotification {
+                jsonrpc: 2.0,
+                method: "OnSubscribed".to_string(),
+                params: json!({"resume_token": resume_token, "subscription": subscription}),
+                emitted_at_ms: None,
+            };
+            // Never compressed, so that clients can tell from it whether compression is on.
+            // A broken socket is noticed by the loop below.
+            let plain = Subscription {
+                compress: false,
+                ..subscription.clone()
+            };
+            let _ = tx.send(plain.frame(&subscribed)).await;
+
+            // Deltas start from the full state
+            if subscription.delta {
+                let mut snapshot = JsonNotification {
+                    jsonrpc: 2.0,
+                    method: "OnStateDelta".to_string(),
+                    params: json!(state.player_state.as_ref()),
+                    emitted_at_ms: None,
+                };
+                subscription.tailor(&mut snapshot);
+                // A broken socket is noticed by the loop below
+                let _ = tx.send(subscription.frame(&snapshot)).await;
+            }
 
             // socket JSONs command -> player command -> socket response
             // internal event JSONs -> socket notification
 
             loop {
 
-                let data: String = tokio::select! {
+                let frame: ws::Message = tokio::select! {
                     // Receive messages from websocket
                     message = ws_rx.next() => {
                         debug!("New request from WS ID: {uid}");
@@ -380,10 +1491,10 @@ impl ServerInternal {
                                     Err(_) => ()
                                 }
 
-                                let res = state.handle_socket_message(m);
+                                let res = state.handle_socket_message(m, subscription.shortcuts).await;
                                 match res {
-                                    Ok(res) => serde_json::to_string(&res).expect("Should be able to parse result"),
-                                    Err(e) => serde_json::to_string(&e).expect("Should be able to parse error"),
+                                    Ok(res) => subscription.frame(&res),
+                                    Err(e) => subscription.frame(&e),
                                 }
                             },
                         }
@@ -392,48 +1503,226 @@ impl ServerInternal {
                     event = event_channel.recv() => {
                         debug!("New event to WS ID: {uid}");
                         match event {
-                            Ok(m) => {
-                                serde_json::to_string(&m).expect("Should be able to parse notification")
+                            Ok(mut m) => {
+                                if !subscription.wants(&m.method) {
+                                    continue;
+                                }
+                                subscription.tailor(&mut m);
+                                subscription.frame(&m)
+                            },
+                            // Only JSON-RPC messages are sent, so clients can tell
+                            // responses from notifications
+                            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                                warn!("WS ID {uid} skipped {skipped} notifications");
+                                state.notifications_dropped(skipped);
+                                continue;
+                            },
+                            Err(broadcast::error::RecvError::Closed) => break,
+                        }
+                    }
+                    levels = next_levels(&mut levels_listener) => {
+                        match levels {
+                            Some(mut m) => {
+                                subscription.tailor(&mut m);
+                                subscription.frame(&m)
                             },
-                            Err(e) => format!("Internal server error: {e}").to_string(),
+                            None => break,
                         }
                     }
                     _ = cancel.cancelled() => {
-                        // We don't care about result since we are shutting down
+                        // Tell clients this is not a network problem, so they don't
+                        // reconnect right away. After a rebind they should.
+                        if state.cancel.is_cancelled() {
+                            let shutdown = JsonNotification {
+                                jsonrpc: 2.0,
+                                method: "OnServerShutdown".to_string(),
+                                params: json!({"reason": *state.shutdown_reason.lock()}),
+                                emitted_at_ms: None,
+                            };
+                            // We don't care about result since we are shutting down
+                            let _ = tx.send(subscription.frame(&shutdown)).await;
+                        }
                         let _ = tx.send(ws::Message::close()).await;
                         break;
                     }
                 };
 
-                match tx.send(ws::Message::text(data)).await {
-                    Ok(_) => (),
-                    Err(e) => {debug!("{e}"); break;}
-                };
+                if let Err(e) = send_frame(&mut tx, frame, state.send_retries).await {
+                    debug!("Dropping WS ID {uid}: {e}");
+                    break;
+                }
             };
 
             debug!("dropping websocket id {uid}");
+            state.keep_for_resume(resume_token, subscription);
             users.write().remove(&uid);
         });
 
         self.user_tasks.write().insert(uid, thr);
     }
 
+    // Notifications as server-sent events, each named after its method. The
+    // subscription is dropped together with the stream when the client disconnects.
+    fn event_stream(
+        self: Arc<Self>,
+        subscription: Subscription,
+    ) -> impl Stream<Item = Result<sse::Event, Infallible>> {
+        let uid = UID_NEXT.fetch_add(1, Ordering::Relaxed);
+        debug!("Adding new event stream, ID: {uid}");
+
+        // Deltas start from the full state
+        let snapshot = subscription.delta.then(|| {
+            let mut snapshot = JsonNotification {
+                jsonrpc: 2.0,
+                method: "OnStateDelta".to_string(),
+                params: json!(self.player_state.as_ref()),
+                emitted_at_ms: None,
+            };
+            subscription.tailor(&mut snapshot);
+            snapshot
+        });
+
+        let subscriber = EventSubscriber {
+            uid,
+            event_channel: self.user_message_tx.subscribe(),
+            cancel: self.connections.read().clone(),
+            levels_listener: self.levels_listener(&subscription),
+            subscription,
+            state: self.clone(),
+        };
+
+        stream::iter(snapshot)
+            .chain(stream::unfold(subscriber, |mut subscriber| async move {
+                let notification = subscriber.next().await?;
+                Some((notification, subscriber))
+            }))
+            .map(|notification| {
+                let data = serde_json::to_string(&notification)
+                    .expect("Should be able to parse notification");
+                Ok(sse::Event::default().event(&notification.method).data(data))
+            })
+    }
+
+    // Closes open websockets and event streams, later ones get a new token
+    fn close_connections(&self) {
+        let mut connections = self.connections.write();
+        connections.cancel();
+        *connections = self.cancel.child_token();
+    }
+
+    // Turns level measuring on for the first connection subscribed to OnLevels
+    fn levels_listener(self: &Arc<Self>, subscription: &Subscription) -> Option<LevelsListener> {
+        if !subscription.levels {
+            return None;
+        }
+
+        let mut listeners = self.levels_listeners.lock();
+        if *listeners == 0 {
+            if let Some(player) = &self.player {
+                player.set_levels(true);
+            }
+        }
+        *listeners += 1;
+        Some(LevelsListener {
+            state: self.clone(),
+            events: self.levels_tx.subscribe(),
+        })
+    }
+
+    // Options of a dropped connection if the upgrade carries a valid resume
+    // token, otherwise the options as requested
+    fn resume_subscription(&self, subscription: Subscription) -> Subscription {
+        let token = match subscription.resume {
+            Some(ref token) => token,
+            None => return subscription,
+        };
+
+        match self.resume_tokens.lock().remove(token) {
+            Some((dropped, resumed)) if dropped.elapsed() <= self.resume_ttl => {
+                debug!("Resuming websocket subscription");
+                resumed
+            }
+            _ => {
+                debug!("Unknown or expired resume token");
+                Subscription {
+                    resume: None,
+                    ..subscription
+                }
+            }
+        }
+    }
+
+    fn keep_for_resume(&self, token: String, subscription: Subscription) {
+        if self.cancel.is_cancelled() {
+            return;
+        }
+        let mut tokens = self.resume_tokens.lock();
+        tokens.retain(|_, (dropped, _)| dropped.elapsed() <= self.resume_ttl);
+        tokens.insert(
+            token,
+            (
+                Instant::now(),
+                Subscription {
+                    resume: None,
+                    ..subscription
+                },
+            ),
+        );
+    }
+
     // handle raw websocket message
-    fn handle_socket_message(&self, message: Result<ws::Message, warp::Error>) -> JsonResult {
+    async fn handle_socket_message(
+        &self,
+        message: Result<ws::Message, warp::Error>,
+        shortcuts: bool,
+    ) -> JsonResult {
         let m = message.map_err(|e| JsonError::internal(Some(e.to_string())))?;
 
         let m = m
             .to_str()
             .map_err(|_| JsonError::invalid_request(Some("Malformed data".to_string())))?;
 
-        self.handle_request(m)
+        // Requests are JSON objects, anything else is a shortcut
+        if shortcuts && !m.trim_start().starts_with('{') {
+            return self.handle_shortcut(m.trim()).await;
+        }
+        self.handle_request(m).await
+    }
+
+    // Handles a text shortcut as the request it stands for, with the shortcut as its id
+    async fn handle_shortcut(&self, shortcut: &str) -> JsonResult {
+        let (method, params) = match shortcut {
+            "play" => ("setPlay", serde_json::Value::Null),
+            "pause" => ("setPause", serde_json::Value::Null),
+            "stop" => ("setStop", serde_json::Value::Null),
+            "next" => ("setNext", serde_json::Value::Null),
+            "vol+" | "vol-" => {
+                let step = (self.volume_max / VOLUME_SHORTCUT_STEPS).max(1);
+                let volume = self.player_state.read().volume;
+                let volume = match shortcut {
+                    "vol+" => volume.saturating_add(step).min(self.volume_max),
+                    _ => volume.saturating_sub(step),
+                };
+                ("setVolume", json!(volume))
+            }
+            _ => {
+                let mut error =
+                    JsonError::method_not_found(Some(format!("Unknown shortcut {shortcut:?}")));
+                error.set_id(JsonId::String(shortcut.to_string()));
+                return Err(error);
+            }
+        };
+
+        let request = json!({"id": shortcut, "jsonrpc": 2.0, "method": method, "params": params});
+        self.handle_request(&request.to_string()).await
     }
 
     // handle json request
-    fn handle_request(&self, request: &str) -> JsonResult {
+    async fn handle_request(&self, request: &str) -> JsonResult {
         let val: serde_json::Value = serde_json::from_str(request)?;
         let id = match &val["id"] {
-            serde_json::Value::Number(n) => n,
+            serde_json::Value::Number(n) => JsonId::Number(n.clone()),
+            serde_json::Value::String(s) => JsonId::String(s.clone()),
             serde_json::Value::Null => {
                 return Err(JsonError::invalid_request(Some(
                     "No id field found".to_string(),
@@ -442,103 +1731,2784 @@ impl ServerInternal {
             _ => return Err(JsonError::parse(Some("Unexpected id value".to_string()))),
         };
 
-        let id = match id.as_i64() {
-            Some(v) => v,
-            None => {
-                return Err(JsonError::invalid_request(Some(
-                    "Invalid id value".to_string(),
-                )))
-            }
+        // Only timed when it's logged or counted
+        let timed = log_enabled!(Level::Debug) || self.metrics.is_some();
+        let method = if timed {
+            val["method"].as_str().map(str::to_string)
+        } else {
+            None
         };
+        let started = Instant::now();
 
-        let mut res = self.do_request(val);
+        let mut res = self.do_request(val).await;
+
+        if let Some(method) = method {
+            self.request_handled(&method, &id, &res, started.elapsed());
+        }
 
         match res.as_mut() {
             Ok(resp) => resp.set_id(id),
-            Err(e) => e.set_id(Some(id)),
+            Err(e) => e.set_id(id),
         };
 
         res
     }
 
+    fn request_handled(&self, method: &str, id: &JsonId, res: &JsonResult, elapsed: Duration) {
+        let micros = elapsed.as_micros();
+        let id = json!(id);
+        match res {
+            Ok(_) => debug!("Handled {method} request {id} in {micros} µs"),
+            Err(e) => {
+                let code = e.code();
+                debug!("Failed {method} request {id} in {micros} µs: {code:?}");
+            }
+        }
+
+        // Only known methods, so that clients can't add arbitrary labels
+        let known = METHODS.iter().find(|m| m.name == method);
+        if let (Some(metrics), Some(known)) = (&self.metrics, known) {
+            metrics.request_handled(known.name, elapsed);
+        }
+    }
+
     // execute request
-    fn do_request(&self, req: serde_json::Value) -> JsonResult {
+    async fn do_request(&self, req: serde_json::Value) -> JsonResult {
         let req: JsonRequest = serde_json::from_value(req)?;
 
-        let result: serde_json::Value = match req.method.as_str() {
-            "getStatus" => json!(self.player_state.as_ref()),
-            "getVolume" => json!({"volume": self.player_state.read().volume}),
-            "getPlayState" => json!({"playing": &self.player_state.read().playing}),
-            "setPlay" => json!(self.send_command(SpircCommand::Play)?),
-            "setPause" => json!(self.send_command(SpircCommand::Pause)?),
-            "setNext" => json!(self.send_command(SpircCommand::Next)?),
-            "setShuffleOn" => json!(self.send_command(SpircCommand::Shuffle(true))?),
-            "setShuffleOff" => json!(self.send_command(SpircCommand::Shuffle(false))?),
-            "setVolume" => {
-                let vol = req.params;
-                let vol = match vol {
-                    Some(serde_json::Value::Number(v)) => v.as_u64().ok_or_else(|| {
-                        JsonError::invalid_param(Some("Volume not a number".to_string()))
-                    })? as u16,
-                    _ => {
-                        return Err(JsonError::invalid_param(Some(
-                            "Volume not a number".to_string(),
-                        )))
-                    }
-                };
-
-                json!(self.send_command(SpircCommand::SetVolume(vol))?)
-            }
-            _ => return Err(JsonError::method_not_found(None)),
+        let method = METHODS
+            .iter()
+            .filter(|m| self.debug || !m.debug)
+            .find(|m| m.name == req.method)
+            .ok_or_else(|| JsonError::method_not_found(None))?;
+        if !self.is_allowed(method.name) {
+            return Err(JsonError::forbidden(Some(req.method)));
+        }
+        let params = match method.param {
+            Some(name) => single_param(req.params, name),
+            None => req.params,
+        };
+        let result = match method.handler {
+            Handler::Sync(handler) => handler(self, params)?,
+            Handler::Async(handler) => handler(self, params).await?,
         };
 
         Ok(JsonResponse::new(req.id, result))
     }
 
-    // send command to internal player
-    fn send_command(&self, command: SpircCommand) -> Result<String, JsonError> {
-        let sp = self.spirc.read();
-        debug!("Sending spirc command: {command:?}");
+    // Methods available with the current configuration
+    fn methods(&self) -> impl Iterator<Item = &'static Method> + '_ {
+        METHODS
+            .iter()
+            .filter(move |m| (self.debug || !m.debug) && self.is_allowed(m.name))
+    }
 
-        match *sp {
-            Some(ref sp) => {
-                sp.send(command)
-                    .map_err(|e| JsonError::internal(Some(e.to_string())))?;
-                Ok("Ok".to_string())
+    fn is_allowed(&self, method: &str) -> bool {
+        let allowed = match self.allowed_methods {
+            Some(ref allowed) => allowed.iter().any(|m| m == method),
+            None => true,
+        };
+        allowed && !self.denied_methods.iter().any(|m| m == method)
+    }
+
+    fn get_server_info(&self, _: Option<serde_json::Value>) -> MethodResult {
+        Ok(self.server_info())
+    }
+
+    fn list_methods(&self, _: Option<serde_json::Value>) -> MethodResult {
+        let methods: Vec<&str> = self.methods().map(|m| m.name).collect();
+        Ok(json!(methods))
+    }
+
+    fn get_status(&self, _: Option<serde_json::Value>) -> MethodResult {
+        Ok(json!(self.player_state.as_ref()))
+    }
+
+    fn get_volume(&self, _: Option<serde_json::Value>) -> MethodResult {
+        Ok(json!({"volume": self.player_state.read().volume}))
+    }
+
+    fn get_play_state(&self, _: Option<serde_json::Value>) -> MethodResult {
+        Ok(json!({"playing": &self.player_state.read().playing}))
+    }
+
+    fn get_progress(&self, _: Option<serde_json::Value>) -> MethodResult {
+        Ok(self.player_state.read().progress())
+    }
+
+    // Current track together with the load request it is playing for, read at once so
+    // they always match. Null without a track.
+    fn get_now_playing_ref(&self, _: Option<serde_json::Value>) -> MethodResult {
+        let state = self.player_state.read();
+        Ok(match state.track {
+            Some(ref track) => json!({
+                "track_id": track.track_id,
+                "uri": track.uri,
+                "play_request_id": state.play_request_id,
+            }),
+            None => serde_json::Value::Null,
+        })
+    }
+
+    // Parses {"uri": URI} without side effects, so clients can check a pasted URI
+    fn validate_uri(&self, params: Option<serde_json::Value>) -> MethodResult {
+        let uri = uri_param(params.as_ref())?;
+        Ok(match SpotifyId::from_uri(uri) {
+            Ok(id) => json!({
+                "valid": true,
+                "kind": <&str>::from(id.item_type),
+                "playable": id.is_playable(),
+            }),
+            Err(e) => json!({
+                "valid": false,
+                "kind": null,
+                "playable": false,
+                "error": e.to_string(),
+            }),
+        })
+    }
+
+    // Metadata of any track without loading it, {"uri": URI}
+    async fn get_track_info(&self, params: Option<serde_json::Value>) -> MethodResult {
+        let id = parse_uri(uri_param(params.as_ref())?)?;
+        if !id.is_playable() {
+            return Err(JsonError::invalid_param(Some(
+                "Not a track or episode".to_string(),
+            )));
+        }
+        let session = self
+            .session
+            .read()
+            .clone()
+            .ok_or_else(|| JsonError::no_control(Some("No session".to_string())))?;
+
+        // Spawned, so that the lookup finishes and its result is cached even if the
+        // client goes away
+        let lookup = self.rt.spawn(async move {
+            tokio::time::timeout(TRACK_INFO_TIMEOUT, AudioItem::get_file(&session, id)).await
+        });
+        let item = lookup
+            .await
+            .map_err(|e| JsonError::internal(Some(e.to_string())))?
+            .map_err(|_| JsonError::unavailable(Some("timeout".to_string())))?
+            .map_err(track_info_error)?;
+
+        if let Err(reason) = item.availability {
+            let reason = UnavailableReason::from(reason);
+            return Err(JsonError::unavailable(Some(reason.as_str().to_string())));
+        }
+
+        Ok(json!({
+            "uri": item.uri,
+            "name": item.name,
+            "duration_ms": item.duration_ms,
+            "is_explicit": item.is_explicit,
+        }))
+    }
+
+    // Cover of the current track, {"track_id": ID, "min_width": N}
+    fn get_cover(&self, params: Option<serde_json::Value>) -> MethodResult {
+        let params = params.unwrap_or(serde_json::Value::Null);
+        let track_id = params.get("track_id").and_then(|id| id.as_str());
+        let min_width = match params.get("min_width") {
+            None => 0,
+            Some(width) => width
+                .as_i64()
+                .ok_or_else(|| JsonError::invalid_param(Some("Width not a number".to_string())))?,
+        };
+
+        let state = self.player_state.read();
+        match state.track {
+            Some(ref track) if track_id.map_or(true, |id| id == track.track_id) => {
+                Ok(json!(track.cover(min_width)))
             }
-            None => Err(JsonError::no_control(None)),
+            _ => Err(JsonError::invalid_param(Some(
+                "Not the current track".to_string(),
+            ))),
         }
     }
-}
 
-impl Track {
-    // Extract relevant information from internal representation
-    fn from_audio_item(item: AudioItem) -> Self {
-        let covers = item
-            .covers
-            .into_iter()
-            .map(|c| Cover {
-                url: c.url,
-                size: (c.width, c.height),
-            })
-            .collect();
+    fn set_play(&self, _: Option<serde_json::Value>) -> MethodResult {
+        Ok(json!(self.send_command(SpircCommand::Play)?))
+    }
 
-        let (album, artists, show_name) = match item.unique_fields {
-            UniqueFields::Track { artists, album, .. } => (
-                Some(album),
-                artists.0.into_iter().map(|a| a.name).collect(),
-                None,
-            ),
-            UniqueFields::Episode { show_name, .. } => (None, Vec::new(), Some(show_name)),
+    fn set_pause(&self, _: Option<serde_json::Value>) -> MethodResult {
+        Ok(json!(self.send_command(SpircCommand::Pause)?))
+    }
+
+    fn set_stop(&self, _: Option<serde_json::Value>) -> MethodResult {
+        // Stopped directly on the player, spirc follows the Stopped event.
+        // Also allowed while loading, to cancel the load.
+        self.player()?.stop();
+        Ok(json!("Ok"))
+    }
+
+    fn set_next(&self, _: Option<serde_json::Value>) -> MethodResult {
+        Ok(json!(self.send_command(SpircCommand::Next)?))
+    }
+
+    fn set_shuffle_on(&self, _: Option<serde_json::Value>) -> MethodResult {
+        Ok(json!(self.send_command(SpircCommand::Shuffle(true))?))
+    }
+
+    fn set_shuffle_off(&self, _: Option<serde_json::Value>) -> MethodResult {
+        Ok(json!(self.send_command(SpircCommand::Shuffle(false))?))
+    }
+
+    // Seek to a percentage of the current track, {"percent": P}
+    fn seek_percent(&self, params: Option<serde_json::Value>) -> MethodResult {
+        let percent = params
+            .as_ref()
+            .and_then(|params| params.get("percent"))
+            .and_then(|percent| percent.as_f64())
+            .ok_or_else(|| JsonError::invalid_param(Some("Percent not a number".to_string())))?;
+
+        let duration_ms = {
+            let state = self.player_state.read();
+            // Like seeks in the player, applied once the track is loaded
+            if state.loading {
+                *self.pending_seek_percent.lock() = Some(percent);
+                return Ok(json!("Queued"));
+            }
+            state.track.as_ref().map_or(0, |track| track.duration_ms)
+        };
+        if duration_ms == 0 {
+            return Err(JsonError::not_ready(Some(
+                "Duration of the current track is unknown".to_string(),
+            )));
+        }
+
+        let command = SpircCommand::SetPosition(percent_position_ms(percent, duration_ms));
+        Ok(json!(self.send_command(command)?))
+    }
+
+    // Chapters of the current episode, empty without any
+    fn get_chapters(&self, _: Option<serde_json::Value>) -> MethodResult {
+        let state = self.player_state.read();
+        let chapters = state.track.as_ref().map_or(&[][..], |t| &t.chapters);
+        Ok(json!(chapters))
+    }
+
+    fn seek_chapter(&self, params: Option<serde_json::Value>) -> MethodResult {
+        let index = params
+            .and_then(|index| index.as_u64())
+            .ok_or_else(|| JsonError::invalid_param(Some("Index not a number".to_string())))?;
+
+        self.ensure_ready()?;
+        let start_ms = {
+            let state = self.player_state.read();
+            let chapters = state.track.as_ref().map_or(&[][..], |t| &t.chapters);
+            match chapters.get(index as usize) {
+                Some(chapter) => chapter.start_ms,
+                None => {
+                    return Err(JsonError::invalid_param(Some(format!(
+                        "No chapter {index} in the current track"
+                    ))))
+                }
+            }
         };
 
-        Track {
-            track_id: item.track_id.to_base62().unwrap(),
-            name: item.name,
-            covers,
-            album,
-            artists,
-            show_name,
+        let command = SpircCommand::SetPosition(start_ms);
+        Ok(json!(self.send_command(command)?))
+    }
+
+    // Loads a single track at a position in one command, so a following seek can't
+    // race the load
+    fn load_and_play(&self, params: Option<serde_json::Value>) -> MethodResult {
+        let params = params.as_ref();
+        let uri = uri_param(params)?;
+        let id = parse_uri(uri)?;
+        if !id.is_playable() {
+            return Err(JsonError::invalid_param(Some(
+                "Not a track or episode".to_string(),
+            )));
+        }
+
+        let position_ms = match params.and_then(|params| params.get("position_ms")) {
+            Some(position) => position
+                .as_u64()
+                .and_then(|position| u32::try_from(position).ok())
+                .ok_or_else(|| {
+                    JsonError::invalid_param(Some("Position not a number".to_string()))
+                })?,
+            None => 0,
+        };
+        let start_playing = match params.and_then(|params| params.get("play")) {
+            Some(play) => play
+                .as_bool()
+                .ok_or_else(|| JsonError::invalid_param(Some("Play not a bool".to_string())))?,
+            None => true,
+        };
+
+        let mut track = TrackRef::new();
+        track.set_uri(uri.to_string());
+        let command = SpircCommand::Load(SpircLoadCommand {
+            context_uri: uri.to_string(),
+            start_playing,
+            shuffle: false,
+            repeat: false,
+            playing_track_index: 0,
+            tracks: vec![track],
+            position_ms,
+        });
+        Ok(json!(self.send_command(command)?))
+    }
+
+    // Playback state to pick up later or on another server with importSession
+    fn export_session(&self, _: Option<serde_json::Value>) -> MethodResult {
+        let state = self.player_state.read();
+        let session = SessionExport {
+            version: SESSION_EXPORT_VERSION,
+            uri: state.track.as_ref().map(|track| track.uri.clone()),
+            position_ms: state.position_ms(),
+            playing: matches!(state.playing, PlayingState::Playing),
+            volume: state.volume,
+            volume_max: self.volume_max,
+            shuffle: state.shuffle,
+            repeat: state.repeat,
+        };
+        Ok(json!(session))
+    }
+
+    // Loads the track of an exported session at its position, then applies its volume
+    fn import_session(&self, params: Option<serde_json::Value>) -> MethodResult {
+        let session = params
+            .ok_or_else(|| JsonError::invalid_param(Some("Session missing".to_string())))
+            .and_then(|params| {
+                serde_json::from_value::<SessionExport>(params)
+                    .map_err(|e| JsonError::invalid_param(Some(format!("Not a session: {e}"))))
+            })?;
+        if session.version != SESSION_EXPORT_VERSION {
+            return Err(JsonError::invalid_param(Some(format!(
+                "Unsupported session version {}",
+                session.version
+            ))));
+        }
+        if session.volume_max == 0 || session.volume > session.volume_max {
+            return Err(JsonError::invalid_param(Some(
+                "Session volume out of range".to_string(),
+            )));
+        }
+        if let Some(ref uri) = session.uri {
+            if !parse_uri(uri)?.is_playable() {
+                return Err(JsonError::invalid_param(Some(
+                    "Not a track or episode".to_string(),
+                )));
+            }
         }
+
+        match session.uri {
+            Some(uri) => {
+                let mut track = TrackRef::new();
+                track.set_uri(uri.clone());
+                self.send_command(SpircCommand::Load(SpircLoadCommand {
+                    context_uri: uri,
+                    start_playing: session.playing,
+                    shuffle: session.shuffle,
+                    repeat: session.repeat,
+                    playing_track_index: 0,
+                    tracks: vec![track],
+                    position_ms: session.position_ms,
+                }))?;
+            }
+            None => {
+                self.send_command(SpircCommand::Shuffle(session.shuffle))?;
+                self.send_command(SpircCommand::Repeat(session.repeat))?;
+            }
+        }
+
+        // Exported from a server with another volume scale
+        let volume = session.volume as u64 * self.volume_max as u64 / session.volume_max as u64;
+        self.set_volume(Some(json!(volume)))?;
+        Ok(json!("Ok"))
+    }
+
+    fn get_limiter_state(&self, _: Option<serde_json::Value>) -> MethodResult {
+        Ok(json!(*self.limiter_state.read()))
+    }
+
+    fn get_decode_stats(&self, _: Option<serde_json::Value>) -> MethodResult {
+        Ok(json!(*self.decode_stats.read()))
+    }
+
+    fn reset_limiter(&self, _: Option<serde_json::Value>) -> MethodResult {
+        self.player()?.reset_limiter();
+        Ok(json!("Ok"))
+    }
+
+    // The player repeats its state events, which reach every client as notifications
+    fn resync_state(&self, _: Option<serde_json::Value>) -> MethodResult {
+        self.player()?.resync();
+        Ok(json!("Ok"))
+    }
+
+    async fn get_buffer_progress(&self, _: Option<serde_json::Value>) -> MethodResult {
+        let snapshot = self.player_snapshot().await?;
+        Ok(json!({
+            "fraction": snapshot.buffered,
+            "range_to_end_available": snapshot.range_to_end_available,
+        }))
+    }
+
+    // Track whose file is played for the current one, which differs when an alternative
+    // is played in its place, null without a track
+    async fn get_playback_source(&self, _: Option<serde_json::Value>) -> MethodResult {
+        let snapshot = self.player_snapshot().await?;
+        let ids = snapshot.track_id.zip(snapshot.source_track_id);
+        let (track_id, source_track_id) = match ids.map(|(a, b)| (a.to_base62(), b.to_base62())) {
+            Some((Ok(track_id), Ok(source_track_id))) => (track_id, source_track_id),
+            _ => return Ok(serde_json::Value::Null),
+        };
+        Ok(json!({
+            "track_id": track_id,
+            "source_track_id": source_track_id,
+            "alternative": track_id != source_track_id,
+            "alternatives_evaluated": snapshot.alternatives_evaluated,
+        }))
+    }
+
+    // Whether the current track plays decrypted, null without a track
+    async fn get_decryption_status(&self, _: Option<serde_json::Value>) -> MethodResult {
+        let snapshot = self.player_snapshot().await?;
+        let track_id = match snapshot.track_id.map(|id| id.to_base62()) {
+            Some(Ok(id)) => id,
+            _ => return Ok(serde_json::Value::Null),
+        };
+        Ok(json!({
+            "track_id": track_id,
+            "decrypted": snapshot.decrypted,
+        }))
+    }
+
+    // Start the current track over and play it, e.g. on a double press of previous
+    fn restart_track(&self, _: Option<serde_json::Value>) -> MethodResult {
+        self.player()?.restart();
+        Ok(json!("Ok"))
+    }
+
+    fn get_recent_errors(&self, _: Option<serde_json::Value>) -> MethodResult {
+        Ok(json!(*self.recent_errors.lock()))
+    }
+
+    fn get_session_stats(&self, _: Option<serde_json::Value>) -> MethodResult {
+        let stats = self.session_stats.lock();
+        Ok(json!({
+            "uptime_ms": stats.started.elapsed().as_millis() as u64,
+            "tracks_played": stats.tracks_played,
+            "play_time_ms": stats.play_time().as_millis() as u64,
+        }))
+    }
+
+    fn set_duck(&self, params: Option<serde_json::Value>) -> MethodResult {
+        let ducked = match params {
+            Some(serde_json::Value::Bool(b)) => b,
+            _ => {
+                return Err(JsonError::invalid_param(Some(
+                    "Duck not a boolean".to_string(),
+                )))
+            }
+        };
+
+        self.player()?.set_duck(ducked);
+        Ok(json!("Ok"))
+    }
+
+    fn set_gapless(&self, params: Option<serde_json::Value>) -> MethodResult {
+        let gapless = match params {
+            Some(serde_json::Value::Bool(b)) => b,
+            _ => {
+                return Err(JsonError::invalid_param(Some(
+                    "Gapless not a boolean".to_string(),
+                )))
+            }
+        };
+
+        self.player()?.set_gapless(gapless);
+        Ok(json!("Ok"))
+    }
+
+    // Milliseconds before the end of a track at which the next one is preloaded
+    fn set_preload_lead_time(&self, params: Option<serde_json::Value>) -> MethodResult {
+        let lead_time_ms = params
+            .as_ref()
+            .and_then(|lead_time| lead_time.as_u64())
+            .ok_or_else(|| {
+                JsonError::invalid_param(Some("Lead time not a number of ms".to_string()))
+            })?;
+
+        let lead_time = Duration::from_millis(lead_time_ms);
+        self.player()?.set_preload_lead_time(lead_time);
+        Ok(json!("Ok"))
+    }
+
+    async fn get_ditherer(&self, _: Option<serde_json::Value>) -> MethodResult {
+        let snapshot = self.player_snapshot().await?;
+        Ok(json!({"ditherer": snapshot.ditherer.unwrap_or("none")}))
+    }
+
+    fn set_ditherer(&self, params: Option<serde_json::Value>) -> MethodResult {
+        let name = match params {
+            Some(serde_json::Value::String(name)) => name,
+            _ => {
+                return Err(JsonError::invalid_param(Some(
+                    "Ditherer not a string".to_string(),
+                )))
+            }
+        };
+
+        let ditherer = match name.as_str() {
+            "none" => None,
+            _ => match dither::find_ditherer(Some(name)) {
+                Some(ditherer) => Some(ditherer),
+                None => {
+                    return Err(JsonError::invalid_param(Some(
+                        "Ditherer must be one of none, gpdf, tpdf, tpdf_hp".to_string(),
+                    )))
+                }
+            },
+        };
+
+        self.player()?.set_ditherer(ditherer);
+        Ok(json!("Ok"))
+    }
+
+    // Player settings in effect, including changes made through the API
+    async fn get_config(&self, _: Option<serde_json::Value>) -> MethodResult {
+        let config = self.player_config().await?;
+        let normalisation_type = match config.normalisation_type {
+            NormalisationType::Album => "album",
+            NormalisationType::Track => "track",
+            NormalisationType::Auto => "auto",
+        };
+        let normalisation_method = match config.normalisation_method {
+            NormalisationMethod::Basic => "basic",
+            NormalisationMethod::Dynamic => "dynamic",
+            NormalisationMethod::Peak => "peak",
+        };
+        let on_queue_end = match config.on_queue_end {
+            QueueEndBehavior::Stop => "stop",
+            QueueEndBehavior::RepeatAll => "repeat_all",
+            QueueEndBehavior::Pause => "pause",
+        };
+        let equalizer: Vec<_> = config
+            .equalizer
+            .iter()
+            .map(|band| json!({"frequency": band.frequency, "gain_db": band.gain_db, "q": band.q}))
+            .collect();
+        // Coefficients are stored, not the times they were made from
+        let to_ms = |coefficient| {
+            let duration = coefficient_to_duration(coefficient);
+            (duration.as_secs_f64() * 1000.0).round() as u64
+        };
+
+        Ok(json!({
+            "bitrate": config.bitrate.kbps(),
+            "adaptive_bitrate": config.adaptive_bitrate.map(|adaptive| json!({
+                "stalls": adaptive.stalls,
+                "window_ms": adaptive.window.as_millis() as u64,
+                "recovery_ms": adaptive.recovery.as_millis() as u64,
+            })),
+            "gapless": config.gapless,
+            "passthrough": config.passthrough,
+            "downmix_mono": config.downmix_mono,
+            "normalisation": {
+                "enabled": config.normalisation,
+                "type": normalisation_type,
+                "method": normalisation_method,
+                "pregain_db": config.normalisation_pregain_db,
+                "threshold_dbfs": config.normalisation_threshold_dbfs,
+                "attack_ms": to_ms(config.normalisation_attack_cf),
+                "release_ms": to_ms(config.normalisation_release_cf),
+                "knee_db": config.normalisation_knee_db,
+            },
+            "equalizer": equalizer,
+            "balance": config.balance,
+            "duck_attenuation_db": config.duck_attenuation_db,
+            "duck_ramp_ms": config.duck_ramp.as_millis() as u64,
+            "stop_fade_ms": config.stop_fade_ms,
+            "load_timeout_ms": config.load_timeout.as_millis() as u64,
+            "trim_start_ms": config.trim_start_ms,
+            "trim_end_ms": config.trim_end_ms,
+            "preload_lead_time_ms": config.preload_lead_time.as_millis() as u64,
+            "on_queue_end": on_queue_end,
+        }))
+    }
+
+    async fn get_normalisation(&self, _: Option<serde_json::Value>) -> MethodResult {
+        let snapshot = self.player_snapshot().await?;
+        let method = match snapshot.normalisation_method {
+            NormalisationMethod::Basic => "basic",
+            NormalisationMethod::Dynamic => "dynamic",
+            NormalisationMethod::Peak => "peak",
+        };
+        Ok(json!({ "method": method }))
+    }
+
+    fn set_normalisation(&self, params: Option<serde_json::Value>) -> MethodResult {
+        let method = match params {
+            Some(serde_json::Value::String(name)) => name.parse::<NormalisationMethod>().ok(),
+            _ => None,
+        };
+        let method = method.ok_or_else(|| {
+            JsonError::invalid_param(Some(
+                "Normalisation method must be one of basic, dynamic, peak".to_string(),
+            ))
+        })?;
+
+        self.player()?.set_normalisation_method(method);
+        Ok(json!("Ok"))
+    }
+
+    fn set_balance(&self, params: Option<serde_json::Value>) -> MethodResult {
+        let balance = match params {
+            Some(serde_json::Value::Number(n)) => n.as_f64(),
+            _ => None,
+        };
+        let balance = match balance {
+            Some(b) if (-1.0..=1.0).contains(&b) => b,
+            _ => {
+                return Err(JsonError::invalid_param(Some(
+                    "Balance not a number between -1.0 and 1.0".to_string(),
+                )))
+            }
+        };
+
+        self.player()?.set_balance(balance);
+        Ok(json!("Ok"))
+    }
+
+    fn set_gain_override(&self, params: Option<serde_json::Value>) -> MethodResult {
+        // {"db": N} overrides the gain of the current track, {"db": null} clears it
+        let gain_db = match params.as_ref().and_then(|p| p.get("db")) {
+            Some(serde_json::Value::Null) => None,
+            Some(serde_json::Value::Number(n)) => n.as_f64(),
+            _ => {
+                return Err(JsonError::invalid_param(Some(
+                    "Gain not a number or null".to_string(),
+                )))
+            }
+        };
+
+        self.player()?.set_gain_override(gain_db);
+        Ok(json!("Ok"))
+    }
+
+    fn set_volume(&self, params: Option<serde_json::Value>) -> MethodResult {
+        let not_a_number = || JsonError::invalid_param(Some("Volume not a number".to_string()));
+
+        // Either a plain volume, or {"volume": N, "ramp_ms": M}
+        let (vol, ramp_ms) = match params {
+            Some(serde_json::Value::Number(v)) => (v.as_u64().ok_or_else(not_a_number)?, 0),
+            Some(serde_json::Value::Object(params)) => {
+                let vol = params
+                    .get("volume")
+                    .and_then(|v| v.as_u64())
+                    .ok_or_else(not_a_number)?;
+                let ramp_ms = match params.get("ramp_ms") {
+                    None => 0,
+                    Some(ramp_ms) => ramp_ms.as_u64().ok_or_else(|| {
+                        JsonError::invalid_param(Some("Ramp duration not a number".to_string()))
+                    })?,
+                };
+                (vol, ramp_ms)
+            }
+            _ => return Err(not_a_number()),
+        };
+
+        if vol > self.volume_max as u64 {
+            return Err(JsonError::invalid_param(Some(format!(
+                "Volume out of range 0-{}",
+                self.volume_max
+            ))));
+        }
+
+        if ramp_ms > VOLUME_RAMP_MAX.as_millis() as u64 {
+            return Err(JsonError::invalid_param(Some(format!(
+                "Ramp duration out of range 0-{}",
+                VOLUME_RAMP_MAX.as_millis()
+            ))));
+        }
+
+        // Any new volume replaces a ramp in progress
+        if let Some(ramp) = self.volume_ramp.lock().take() {
+            ramp.abort();
+        }
+
+        let client_vol = vol as u16;
+        let vol = self.volume_from_client(client_vol);
+        if ramp_ms == 0 && !self.volume_debounce.is_zero() && self.spirc.read().is_some() {
+            // Clients see the new volume right away, spirc only the last one of the window
+            let delta = {
+                let mut state = self.player_state.write();
+                let before = json!(&*state);
+                state.volume = client_vol;
+                state_delta(&before, &json!(&*state))
+            };
+            if let Some(d) = delta {
+                self.forward_event(Notification::VolumeChange(client_vol), None);
+                self.forward_event(Notification::StateDelta(d), None);
+            }
+            let window_open = self.pending_volume.lock().replace(vol).is_some();
+            if !window_open {
+                let pending_volume = self.pending_volume.clone();
+                let spirc = self.spirc.clone();
+                let debounce = self.volume_debounce;
+                self.rt.spawn(async move {
+                    tokio::time::sleep(debounce).await;
+                    let volume = pending_volume.lock().take();
+                    if let (Some(volume), Some(sp)) = (volume, &*spirc.read()) {
+                        if let Err(e) = sp.send(SpircCommand::SetVolume(volume)) {
+                            warn!("Unable to send debounced volume: {e}");
+                        }
+                    }
+                });
+            }
+            return Ok(json!("Ok"));
+        }
+
+        // Replaces a debounced volume that hasn't been sent yet
+        self.pending_volume.lock().take();
+        if ramp_ms == 0 || self.spirc.read().is_none() {
+            return Ok(json!(self.send_command(SpircCommand::SetVolume(vol))?));
+        }
+
+        let from = self.volume_from_client(self.player_state.read().volume);
+        let steps = volume_ramp(from, vol, ramp_ms);
+        let spirc = self.spirc.clone();
+        let ramp = self.rt.spawn(async move {
+            for (delay, volume) in steps {
+                tokio::time::sleep(delay).await;
+                if let Some(ref sp) = *spirc.read() {
+                    if let Err(e) = sp.send(SpircCommand::SetVolume(volume)) {
+                        warn!("Unable to send volume ramp step: {e}");
+                        return;
+                    }
+                }
+            }
+        });
+        *self.volume_ramp.lock() = Some(ramp);
+
+        Ok(json!("Ok"))
+    }
+
+    async fn get_raw_normalisation(&self, _: Option<serde_json::Value>) -> MethodResult {
+        self.raw_normalisation().await
+    }
+
+    fn server_info(&self) -> serde_json::Value {
+        let methods: Vec<&str> = self.methods().map(|m| m.name).collect();
+        let notifications: Vec<&str> = NOTIFICATIONS
+            .iter()
+            .copied()
+            .filter(|n| self.raw_events || *n != "OnRawEvent")
+            .collect();
+
+        json!({
+            "version": env!("CARGO_PKG_VERSION"),
+            "jsonrpc": "2.0",
+            "methods": methods,
+            "notifications": notifications,
+            "web_ui_available": self.web_ui_available,
+        })
+    }
+
+    // Fetch the largest cover of a new track in the background, so it is cached
+    // by the time clients ask for it
+    fn prewarm_cover(&self, track: &Track) {
+        let covers = match self.covers {
+            Some(ref covers) => covers.clone(),
+            None => return,
+        };
+        let url = match track.covers.iter().max_by_key(|c| c.size.0 * c.size.1) {
+            Some(cover) => cover.url.clone(),
+            None => return,
+        };
+
+        self.rt.spawn(async move {
+            if let Err(e) = covers.get(cover_id(&url), &url).await {
+                debug!("Unable to prefetch cover {url}: {e}");
+            }
+        });
+    }
+
+    // Serve a cover of the current track, other ids are rejected so this
+    // can't be used to fetch arbitrary urls
+    async fn cover(&self, id: String) -> Result<impl warp::Reply, warp::Rejection> {
+        let covers = self.covers.as_ref().ok_or_else(warp::reject::not_found)?;
+        let url = self
+            .player_state
+            .read()
+            .track
+            .as_ref()
+            .and_then(|t| t.covers.iter().find(|c| cover_id(&c.url) == id))
+            .map(|c| c.url.clone())
+            .ok_or_else(warp::reject::not_found)?;
+
+        match covers.get(&id, &url).await {
+            Ok(image) => Ok(warp::http::Response::builder()
+                .header("content-type", "image/jpeg")
+                .body(image)),
+            Err(e) => {
+                warn!("Unable to fetch cover {url}: {e}");
+                Err(warp::reject::not_found())
+            }
+        }
+    }
+
+    // Counters in the Prometheus text format, not found unless enabled
+    fn metrics(&self) -> Result<impl warp::Reply, warp::Rejection> {
+        let metrics = self.metrics.as_ref().ok_or_else(warp::reject::not_found)?;
+        let state = match self.player_state.read().playing {
+            PlayingState::Playing => "playing",
+            PlayingState::Paused => "paused",
+            PlayingState::Stopped => "stopped",
+        };
+        let body = metrics.render(state, self.user_tasks.read().len());
+
+        Ok(warp::http::Response::builder()
+            .header("content-type", "text/plain; version=0.0.4")
+            .body(body))
+    }
+
+    // Notifications a client missed because it fell behind
+    fn notifications_dropped(&self, skipped: u64) {
+        if let Some(metrics) = &self.metrics {
+            metrics.notifications_dropped(skipped);
+        }
+    }
+
+    // Normalisation values of the current track and the header bytes they were
+    // parsed from, null if there is no track or it has no normalisation header
+    async fn raw_normalisation(&self) -> MethodResult {
+        let snapshot = self.player_snapshot().await?;
+
+        Ok(match snapshot.normalisation_data {
+            Some(data) => {
+                let raw: Option<String> = data
+                    .raw
+                    .map(|raw| raw.iter().map(|b| format!("{b:02x}")).collect());
+
+                json!({
+                    "track_gain_db": data.track_gain_db,
+                    "track_peak": data.track_peak,
+                    "album_gain_db": data.album_gain_db,
+                    "album_peak": data.album_peak,
+                    "raw": raw,
+                })
+            }
+            None => serde_json::Value::Null,
+        })
+    }
+
+    // Convert volume from client scale to the internal 0..=u16::MAX scale
+    fn volume_from_client(&self, volume: u16) -> u16 {
+        if self.volume_max == 0 {
+            return 0;
+        }
+        let scaled = volume as u64 * u16::MAX as u64 / self.volume_max as u64;
+        scaled.min(u16::MAX as u64) as u16
+    }
+
+    // Convert volume from the internal 0..=u16::MAX scale to client scale
+    fn volume_to_client(&self, volume: u16) -> u16 {
+        // Round to nearest so that a round trip gives back the same value
+        let max = u16::MAX as u64;
+        ((volume as u64 * self.volume_max as u64 + max / 2) / max) as u16
+    }
+
+    // The embedded player, missing on a detached server
+    fn player(&self) -> Result<&Player, JsonError> {
+        self.player
+            .as_deref()
+            .ok_or_else(|| JsonError::no_control(Some("No player".to_string())))
+    }
+
+    // State of the embedded player, fails on a detached server
+    async fn player_snapshot(&self) -> Result<PlayerStateSnapshot, JsonError> {
+        Ok(self.player()?.get_state().await)
+    }
+
+    // Config of the embedded player, fails on a detached server or once the player
+    // has shut down
+    async fn player_config(&self) -> Result<PlayerConfig, JsonError> {
+        let config = self.player()?.get_config().await;
+        config.ok_or_else(|| JsonError::no_control(Some("Player has shut down".to_string())))
+    }
+
+    // Commands that act on the current track can't be applied while it is loading,
+    // clients should retry once OnPlay or OnPause arrives
+    fn ensure_ready(&self) -> Result<(), JsonError> {
+        if self.player_state.read().loading {
+            return Err(JsonError::not_ready(Some(
+                "Player is loading a track".to_string(),
+            )));
+        }
+        Ok(())
+    }
+
+    // send command to internal player
+    fn send_command(&self, command: SpircCommand) -> Result<String, JsonError> {
+        let sp = self.spirc.read();
+        debug!("Sending spirc command: {command:?}");
+
+        match *sp {
+            Some(ref sp) => {
+                sp.send(command)
+                    .map_err(|e| JsonError::internal(Some(e.to_string())))?;
+                Ok("Ok".to_string())
+            }
+            None => {
+                // No spirc yet, hold on to the command until there is one
+                let mut pending = self.pending_commands.lock();
+                pending.retain(|(received, _)| received.elapsed() <= PENDING_COMMAND_TIMEOUT);
+
+                if pending.len() >= PENDING_COMMANDS_MAX {
+                    return Err(JsonError::no_control(Some(
+                        "Command queue is full".to_string(),
+                    )));
+                }
+
+                debug!("No spirc channel, queueing command");
+                pending.push_back((Instant::now(), command));
+                Ok("Queued".to_string())
+            }
+        }
+    }
+}
+
+// Position at `percent` of a track, clamped to the track
+fn percent_position_ms(percent: f64, duration_ms: u32) -> u32 {
+    (percent.clamp(0.0, 100.0) / 100.0 * duration_ms as f64).round() as u32
+}
+
+// OnTransition for a gapless transition that has "started" or "finished"
+fn transition_notification(
+    state: &str,
+    from_track_id: SpotifyId,
+    to_track_id: SpotifyId,
+) -> Option<Notification> {
+    match (from_track_id.to_base62(), to_track_id.to_base62()) {
+        (Ok(from), Ok(to)) => Some(Notification::Transition(json!({
+            "state": state,
+            "from_track_id": from,
+            "to_track_id": to,
+        }))),
+        _ => {
+            warn!("Transition has an invalid track id");
+            None
+        }
+    }
+}
+
+// Error for a getTrackInfo lookup that failed before the track's availability was known
+fn track_info_error(e: Error) -> JsonError {
+    if let Some(MetadataError::ExplicitContentFiltered) = e.error.downcast_ref() {
+        return JsonError::unavailable(Some("explicit".to_string()));
+    }
+
+    match e.kind {
+        ErrorKind::NotFound | ErrorKind::InvalidArgument => {
+            JsonError::invalid_param(Some(format!("Unknown track: {e}")))
+        }
+        _ => JsonError::internal(Some(e.to_string())),
+    }
+}
+
+// Volumes to send, each after the given delay, to go linearly from `from` to
+// `to` in `ramp_ms`. Always ends with exactly `to`, and never repeats a volume.
+fn volume_ramp(from: u16, to: u16, ramp_ms: u64) -> Vec<(Duration, u16)> {
+    let steps = (ramp_ms / VOLUME_RAMP_STEP.as_millis() as u64)
+        .min(from.abs_diff(to) as u64)
+        .max(1);
+    let delay = Duration::from_millis(ramp_ms / steps);
+
+    (1..=steps)
+        .map(|step| {
+            let volume = from as i64 + (to as i64 - from as i64) * step as i64 / steps as i64;
+            (delay, volume as u16)
+        })
+        .collect()
+}
+
+// Tokens only restore connection options, so they need to be unguessable
+// enough to not collide, not cryptographically secure
+fn new_resume_token() -> String {
+    let random = || RandomState::new().build_hasher().finish();
+    format!("{:016x}{:016x}", random(), random())
+}
+
+#[derive(Debug)]
+struct Unauthorized;
+
+impl warp::reject::Reject for Unauthorized {}
+
+// Passes requests carrying the token of a listener, all requests if it has none
+fn authorized(token: Option<String>) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    let check = move |header: Option<String>, query: HashMap<String, String>| {
+        let result = if has_token(token.as_deref(), header.as_deref(), &query) {
+            Ok(())
+        } else {
+            Err(warp::reject::custom(Unauthorized))
+        };
+        async move { result }
+    };
+
+    warp::header::optional::<String>("authorization")
+        .and(warp::query::<HashMap<String, String>>())
+        .and_then(check)
+        .untuple_one()
+}
+
+fn has_token(token: Option<&str>, header: Option<&str>, query: &HashMap<String, String>) -> bool {
+    let token = match token {
+        Some(token) => token,
+        None => return true,
+    };
+    let bearer = header.and_then(|header| header.strip_prefix("Bearer "));
+    let query = query.get("token").map(String::as_str);
+    let matches = |given: Option<&str>| given.map_or(false, |given| tokens_equal(given, token));
+    // Both are compared, so that the time taken doesn't tell which one was given
+    matches(bearer) | matches(query)
+}
+
+// Compares without stopping at the first difference, so that response times don't
+// tell how much of a guessed token was right
+fn tokens_equal(a: &str, b: &str) -> bool {
+    let diff = a
+        .bytes()
+        .zip(b.bytes())
+        .fold(0, |diff, (a, b)| diff | (a ^ b));
+    a.len() == b.len() && diff == 0
+}
+
+async fn unauthorized(rejection: Rejection) -> Result<impl Reply, Rejection> {
+    if rejection.find::<Unauthorized>().is_some() {
+        let reply = warp::reply::with_status("Unauthorized", StatusCode::UNAUTHORIZED);
+        Ok(reply)
+    } else {
+        Err(rejection)
+    }
+}
+
+// Receiving end of an event stream
+struct EventSubscriber {
+    uid: usize,
+    event_channel: broadcast::Receiver<JsonNotification>,
+    cancel: CancellationToken,
+    subscription: Subscription,
+    state: Arc<ServerInternal>,
+    // Held for as long as the stream is open
+    levels_listener: Option<LevelsListener>,
+}
+
+impl EventSubscriber {
+    // Next notification this subscriber wants, None when the server shuts down or
+    // rebinds
+    async fn next(&mut self) -> Option<JsonNotification> {
+        loop {
+            let event = tokio::select! {
+                event = self.event_channel.recv() => event,
+                Some(levels) = next_levels(&mut self.levels_listener) => Ok(levels),
+                _ = self.cancel.cancelled() => return None,
+            };
+
+            match event {
+                Ok(mut m) if self.subscription.wants(&m.method) => {
+                    self.subscription.tailor(&mut m);
+                    return Some(m);
+                }
+                Ok(_) => (),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    let uid = self.uid;
+                    warn!("Event stream ID {uid} skipped {skipped} notifications");
+                    self.state.notifications_dropped(skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+impl Drop for EventSubscriber {
+    fn drop(&mut self) {
+        debug!("dropping event stream id {}", self.uid);
+    }
+}
+
+// A connection subscribed to OnLevels, level measuring stops when the last one is dropped
+struct LevelsListener {
+    state: Arc<ServerInternal>,
+    events: broadcast::Receiver<JsonNotification>,
+}
+
+impl Drop for LevelsListener {
+    fn drop(&mut self) {
+        let mut listeners = self.state.levels_listeners.lock();
+        *listeners -= 1;
+        if *listeners == 0 {
+            if let Some(player) = &self.state.player {
+                player.set_levels(false);
+            }
+        }
+    }
+}
+
+// Next OnLevels for a connection subscribed to them, never ready for others. Levels
+// that couldn't be sent in time are skipped, the next ones replace them anyway.
+async fn next_levels(listener: &mut Option<LevelsListener>) -> Option<JsonNotification> {
+    let listener = match listener {
+        Some(listener) => listener,
+        None => return std::future::pending().await,
+    };
+
+    loop {
+        match listener.events.recv().await {
+            Ok(m) => return Some(m),
+            Err(broadcast::error::RecvError::Lagged(_)) => (),
+            Err(broadcast::error::RecvError::Closed) => return None,
+        }
+    }
+}
+
+impl Subscription {
+    // Websocket frame carrying `value` in the encoding this connection asked for
+    fn frame<T: Serialize>(&self, value: &T) -> ws::Message {
+        let frame = match self.encoding {
+            Encoding::Json => ws::Message::text(
+                serde_json::to_string(value).expect("Should be able to serialize message"),
+            ),
+            Encoding::Msgpack => ws::Message::binary(
+                rmp_serde::to_vec_named(value).expect("Should be able to serialize message"),
+            ),
+        };
+        if !self.compress {
+            return frame;
+        }
+
+        let data = frame.as_bytes();
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        let compressed = encoder
+            .write_all(data)
+            .and_then(|_| encoder.finish())
+            .expect("Should be able to compress message");
+        if !data.is_empty() {
+            let ratio = compressed.len() as f64 / data.len() as f64;
+            debug!(
+                "Compressed websocket frame from {} to {} bytes ({:.0}%)",
+                data.len(),
+                compressed.len(),
+                ratio * 100.0
+            );
+        }
+        ws::Message::binary(compressed)
+    }
+
+    // Leaves out of a notification what this connection didn't ask for
+    fn tailor(&self, notification: &mut JsonNotification) {
+        if !self.timestamps {
+            notification.emitted_at_ms = None;
+        }
+        if self.no_covers {
+            let track = notification.params.get_mut("track");
+            if let Some(track) = track.and_then(|t| t.as_object_mut()) {
+                track.remove("covers");
+            }
+        }
+    }
+
+    // Whether a notification should be sent to this connection
+    fn wants(&self, method: &str) -> bool {
+        if method == "OnStateDelta" {
+            self.delta
+        } else if method == "OnLevels" {
+            self.levels
+        } else {
+            !(self.delta && STATE_NOTIFICATIONS.contains(&method))
+        }
+    }
+}
+
+// Writes a frame to a websocket. Writes that stall or fail with a transient error are
+// retried up to `retries` times, a client that closed the connection fails at once.
+async fn send_frame<S>(tx: &mut S, frame: ws::Message, retries: u32) -> Result<(), String>
+where
+    S: Sink<ws::Message> + Unpin,
+    S::Error: std::error::Error + 'static,
+{
+    // Once queued the frame is only flushed again, so that it's never sent twice
+    let mut queued = false;
+    let mut attempt = 0;
+    loop {
+        let written = tokio::time::timeout(WS_SEND_TIMEOUT, async {
+            if !queued {
+                tx.feed(frame.clone()).await?;
+                queued = true;
+            }
+            tx.flush().await
+        })
+        .await;
+
+        let error = match written {
+            Ok(Ok(())) => return Ok(()),
+            Ok(Err(e)) if !is_transient(&e) => return Err(e.to_string()),
+            Ok(Err(e)) => e.to_string(),
+            Err(_) => "Write stalled".to_string(),
+        };
+        if attempt == retries {
+            return Err(error);
+        }
+        attempt += 1;
+        debug!("Retrying websocket write: {error}");
+    }
+}
+
+// Whether a failed write may succeed when retried, as opposed to a closed connection
+fn is_transient(error: &(dyn std::error::Error + 'static)) -> bool {
+    let mut source = Some(error);
+    while let Some(error) = source {
+        if let Some(error) = error.downcast_ref::<std::io::Error>() {
+            return matches!(
+                error.kind(),
+                std::io::ErrorKind::Interrupted
+                    | std::io::ErrorKind::TimedOut
+                    | std::io::ErrorKind::WouldBlock
+            );
+        }
+        source = error.source();
+    }
+    false
+}
+
+// Methods taking a single value get it bare, whether it was sent like that, by
+// name as {"name": value} or by position as [value], as generic JSON-RPC clients do
+fn single_param(params: Option<serde_json::Value>, name: &str) -> Option<serde_json::Value> {
+    match params {
+        Some(serde_json::Value::Object(mut map)) if map.len() == 1 && map.contains_key(name) => {
+            map.remove(name)
+        }
+        Some(serde_json::Value::Array(mut values)) if values.len() == 1 => values.pop(),
+        params => params,
+    }
+}
+
+// The "uri" param of {"uri": URI}
+fn uri_param(params: Option<&serde_json::Value>) -> Result<&str, JsonError> {
+    params
+        .and_then(|params| params.get("uri"))
+        .and_then(|uri| uri.as_str())
+        .ok_or_else(|| JsonError::invalid_param(Some("Uri not a string".to_string())))
+}
+
+// Same parsing for every method taking a URI, see validateUri
+fn parse_uri(uri: &str) -> Result<SpotifyId, JsonError> {
+    SpotifyId::from_uri(uri)
+        .map_err(|e| JsonError::invalid_param(Some(format!("Invalid uri: {e}"))))
+}
+
+// Top level fields of the serialized state that changed, None if nothing did
+fn state_delta(before: &serde_json::Value, after: &serde_json::Value) -> Option<serde_json::Value> {
+    let (before, after) = (before.as_object()?, after.as_object()?);
+    let changed: serde_json::Map<String, serde_json::Value> = after
+        .iter()
+        .filter(|(key, value)| before.get(*key) != Some(*value))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+
+    if changed.is_empty() {
+        None
+    } else {
+        Some(serde_json::Value::Object(changed))
+    }
+}
+
+impl PlayerState {
+    // Store a position reported at `measured_at`, it keeps advancing while playing
+    fn set_position(&mut self, position_ms: u32, measured_at: Instant) {
+        self.position_ms = position_ms;
+        self.position_measured_at = match self.playing {
+            PlayingState::Playing => Some(measured_at),
+            _ => None,
+        };
+    }
+
+    // Estimated current position, capped at the track duration if known
+    fn position_ms(&self) -> u32 {
+        let elapsed_ms = self
+            .position_measured_at
+            .map_or(0, |t| t.elapsed().as_millis() as u32);
+        let position_ms = self.position_ms.saturating_add(elapsed_ms);
+
+        match self.track.as_ref().map(|t| t.duration_ms) {
+            Some(duration_ms) if duration_ms > 0 => position_ms.min(duration_ms),
+            _ => position_ms,
+        }
+    }
+
+    fn progress(&self) -> serde_json::Value {
+        let position_ms = self.position_ms();
+        let duration_ms = self.track.as_ref().map_or(0, |t| t.duration_ms);
+
+        let fraction = if duration_ms > 0 {
+            (position_ms as f64 / duration_ms as f64).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+
+        json!({
+            "position_ms": position_ms,
+            "duration_ms": duration_ms,
+            "position_str": format_time(position_ms),
+            "duration_str": format_time(duration_ms),
+            "fraction": fraction,
+        })
+    }
+}
+
+// Format milliseconds as m:ss, or h:mm:ss for long content
+fn format_time(ms: u32) -> String {
+    let total_seconds = ms / 1000;
+    let hours = total_seconds / 3600;
+    let minutes = (total_seconds % 3600) / 60;
+    let seconds = total_seconds % 60;
+
+    if hours > 0 {
+        format!("{hours}:{minutes:02}:{seconds:02}")
+    } else {
+        format!("{minutes}:{seconds:02}")
+    }
+}
+
+impl Track {
+    // Extract relevant information from internal representation
+    fn from_audio_item(item: AudioItem) -> Self {
+        let covers = item
+            .covers
+            .into_iter()
+            .map(|c| Cover {
+                url: c.url,
+                size: (c.width, c.height),
+            })
+            .collect();
+
+        let (kind, album, artists, show_name, chapters) = match item.unique_fields {
+            UniqueFields::Track { artists, album, .. } => (
+                TrackKind::Track,
+                Some(album),
+                artists.0.into_iter().map(|a| a.name).collect(),
+                None,
+                Vec::new(),
+            ),
+            UniqueFields::Episode {
+                show_name,
+                description,
+                ..
+            } => (
+                TrackKind::Episode,
+                None,
+                Vec::new(),
+                Some(show_name),
+                chapters::from_description(&description, item.duration_ms),
+            ),
+        };
+
+        Track {
+            track_id: item.track_id.to_base62().unwrap(),
+            uri: item.uri,
+            kind,
+            name: item.name,
+            covers,
+            album,
+            artists,
+            show_name,
+            duration_ms: item.duration_ms,
+            chapters,
+        }
+    }
+
+    // Smallest cover at least `min_width` wide, or the largest if none is
+    fn cover(&self, min_width: i64) -> Option<&Cover> {
+        let mut covers: Vec<&Cover> = self.covers.iter().collect();
+        covers.sort_by_key(|c| c.size.0);
+        covers
+            .iter()
+            .find(|c| c.size.0 as i64 >= min_width)
+            .or_else(|| covers.last())
+            .copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use librespot_core::{date::Date, Session, SessionConfig, SpotifyId};
+    use librespot_playback::{audio_backend::MockSink, config::PlayerConfig, mixer::NoOpVolume};
+    use std::{
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    #[test]
+    fn format_progress_time() {
+        assert_eq!(format_time(0), "0:00");
+        assert_eq!(format_time(151_000), "2:31");
+        assert_eq!(format_time(245_999), "4:05");
+        assert_eq!(format_time(3_723_000), "1:02:03");
+    }
+
+    #[test]
+    fn delta_contains_changed_fields() {
+        let before = json!({"volume": 10, "shuffle": false, "track": null});
+        let after = json!({"volume": 42, "shuffle": false, "track": null});
+        assert_eq!(state_delta(&before, &after), Some(json!({"volume": 42})));
+        assert_eq!(state_delta(&after, &after), None);
+    }
+
+    fn test_server(rt: &tokio::runtime::Runtime, config: &ServerConfig) -> ServerInternal {
+        let player = {
+            let _guard = rt.enter();
+            let session = Session::new(SessionConfig::default(), None);
+            Player::new(
+                PlayerConfig::default(),
+                session,
+                Box::new(NoOpVolume),
+                || Box::new(MockSink::new()),
+            )
+        };
+        ServerInternal::new(Some(player), config, rt.handle().clone())
+    }
+
+    // Server whose spirc commands arrive on the returned channel. The runtime has to
+    // be kept until the end of the test.
+    fn spirc_test_server(
+        config: &ServerConfig,
+    ) -> (
+        tokio::runtime::Runtime,
+        ServerInternal,
+        mpsc::UnboundedReceiver<SpircCommand>,
+    ) {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let server = test_server(&rt, config);
+        let (tx, rx) = mpsc::unbounded_channel();
+        *server.spirc.write() = Some(tx);
+        (rt, server, rx)
+    }
+
+    const TEST_TRACK_URI: &str = "spotify:track:4uLU6hMCjMI75M1A2tKUQC";
+
+    // Track of TEST_TRACK_URI as clients see it
+    fn test_track(duration_ms: u32) -> Track {
+        Track {
+            track_id: "4uLU6hMCjMI75M1A2tKUQC".to_string(),
+            uri: TEST_TRACK_URI.to_string(),
+            kind: TrackKind::Track,
+            name: "Test Track".to_string(),
+            covers: vec![],
+            album: None,
+            artists: vec![],
+            show_name: None,
+            duration_ms,
+            chapters: vec![],
+        }
+    }
+
+    // Track of TEST_TRACK_URI as the player reports it
+    fn test_audio_item(duration_ms: u32) -> AudioItem {
+        AudioItem {
+            track_id: SpotifyId::from_uri(TEST_TRACK_URI).unwrap(),
+            uri: TEST_TRACK_URI.to_string(),
+            files: Default::default(),
+            name: "Test Track".to_string(),
+            covers: vec![],
+            language: vec![],
+            duration_ms,
+            is_explicit: false,
+            availability: Ok(()),
+            alternatives: None,
+            unique_fields: UniqueFields::Track {
+                artists: Default::default(),
+                album: "Test Album".to_string(),
+                album_artists: vec![],
+                popularity: 0,
+                number: 1,
+                disc_number: 1,
+            },
+        }
+    }
+
+    impl ServerInternal {
+        // As if the player just emitted the event
+        fn handle_internal_event(&self, player_event: PlayerEvent) {
+            self.handle_player_event(player_event, Instant::now());
+        }
+
+        // As if a client sent the request, waiting for the response
+        fn request(&self, request: &str) -> JsonResult {
+            self.rt.block_on(self.handle_request(request))
+        }
+    }
+
+    #[test]
+    fn status_keeps_paused_position() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let server = test_server(&rt, &ServerConfig::default());
+
+        server.handle_internal_event(PlayerEvent::Paused {
+            play_request_id: 0,
+            track_id: SpotifyId::from_uri("spotify:track:4uLU6hMCjMI75M1A2tKUQC").unwrap(),
+            position_ms: 83_000,
+        });
+
+        let response = server
+            .request(r#"{"id": 1, "jsonrpc": 2.0, "method": "getStatus"}"#)
+            .unwrap();
+        let status = &serde_json::to_value(response).unwrap()["result"];
+        assert_eq!(status["playing"], "Paused");
+        assert_eq!(status["position_ms"], 83_000);
+    }
+
+    #[test]
+    fn request_ids_are_echoed_unchanged() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let server = test_server(&rt, &ServerConfig::default());
+        let id_of = |req: &str| {
+            let response = match server.request(req) {
+                Ok(res) => serde_json::to_value(res).unwrap(),
+                Err(e) => serde_json::to_value(e).unwrap(),
+            };
+            response["id"].clone()
+        };
+
+        let uuid = "1b4e28ba-2fa1-11d2-883f-0016d3cca427";
+        let req = format!(r#"{{"id": "{uuid}", "jsonrpc": 2.0, "method": "getVolume"}}"#);
+        assert_eq!(id_of(&req), uuid);
+
+        let req = r#"{"id": 18446744073709551615, "jsonrpc": 2.0, "method": "getVolume"}"#;
+        assert_eq!(id_of(req), json!(u64::MAX));
+
+        // Errors carry the id as well
+        let req = r#"{"id": "a", "jsonrpc": 2.0, "method": "unknown"}"#;
+        assert_eq!(id_of(req), "a");
+    }
+
+    #[test]
+    fn responses_and_notifications_are_distinguishable() {
+        let (_rt, server, _rx) = spirc_test_server(&ServerConfig::default());
+        let mut notifications = server.user_message_tx.subscribe();
+
+        let req = r#"{"id": "next", "jsonrpc": 2.0, "method": "setNext"}"#;
+        let response = json!(server.request(req).unwrap());
+        assert_eq!(response["id"], "next");
+        assert!(response.get("result").is_some());
+        assert!(response.get("method").is_none());
+
+        let req = r#"{"id": 2, "jsonrpc": 2.0, "method": "seekPercent"}"#;
+        let error = json!(server.request(req).unwrap_err());
+        assert_eq!(error["id"], 2);
+        assert_eq!(error["error"]["code"], -32602);
+        assert!(error.get("result").is_none());
+        assert!(error.get("method").is_none());
+
+        server.handle_internal_event(PlayerEvent::VolumeChanged { volume: 0 });
+        let notification = json!(notifications.try_recv().unwrap());
+        assert_eq!(notification["method"], "OnVolumeChange");
+        assert!(notification.get("id").is_none());
+    }
+
+    #[test]
+    fn list_methods_hides_debug_methods() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let list = |server: &ServerInternal| {
+            let response = server
+                .request(r#"{"id": 1, "jsonrpc": 2.0, "method": "listMethods"}"#)
+                .unwrap();
+            let result = serde_json::to_value(response).unwrap()["result"].clone();
+            serde_json::from_value::<Vec<String>>(result).unwrap()
+        };
+
+        let methods = list(&test_server(&rt, &ServerConfig::default()));
+        assert_eq!(methods.len(), METHODS.len() - 1);
+        assert!(methods.iter().any(|m| m == "listMethods"));
+        assert!(!methods.iter().any(|m| m == "getRawNormalisation"));
+
+        let config = ServerConfig {
+            debug: true,
+            ..Default::default()
+        };
+        let methods = list(&test_server(&rt, &config));
+        assert!(methods.iter().any(|m| m == "getRawNormalisation"));
+    }
+
+    #[test]
+    fn server_info_reports_web_ui() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let info = |config: &ServerConfig| test_server(&rt, config).server_info();
+
+        assert_eq!(info(&ServerConfig::default())["web_ui_available"], false);
+        let config = ServerConfig {
+            enable_web: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            info(&config)["web_ui_available"],
+            WEB_UI.get_file("index.html").is_some()
+        );
+        let config = ServerConfig {
+            enable_web: true,
+            custom_path: Some("/srv/ui".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(info(&config)["web_ui_available"], true);
+    }
+
+    #[test]
+    fn volume_ramp_ends_at_target() {
+        let steps = volume_ramp(1000, 400, 200);
+        assert_eq!(steps.len(), 4);
+        assert!(steps.windows(2).all(|w| w[0].1 > w[1].1));
+        assert_eq!(steps.last().unwrap().1, 400);
+
+        // Shorter than one step still sets the target
+        assert_eq!(
+            volume_ramp(0, 500, 10),
+            vec![(Duration::from_millis(10), 500)]
+        );
+
+        // No more steps than there are volumes in between
+        let steps = volume_ramp(100, 103, 60_000);
+        assert_eq!(steps.len(), 3);
+        assert_eq!(steps[0], (Duration::from_secs(20), 101));
+        assert_eq!(volume_ramp(100, 100, 60_000).len(), 1);
+    }
+
+    #[test]
+    fn set_volume_ramps_to_target() {
+        let config = ServerConfig {
+            volume_max: 100,
+            ..Default::default()
+        };
+        let (rt, server, mut rx) = spirc_test_server(&config);
+
+        server
+            .request(
+                r#"{"id": 1, "jsonrpc": 2.0, "method": "setVolume", "params": {"volume": 50, "ramp_ms": 150}}"#,
+            )
+            .unwrap();
+
+        let mut volumes = Vec::new();
+        while volumes.len() < 3 {
+            match rt.block_on(rx.recv()) {
+                Some(SpircCommand::SetVolume(volume)) => volumes.push(volume),
+                command => panic!("Unexpected command {command:?}"),
+            }
+        }
+        assert_eq!(volumes.last(), Some(&server.volume_from_client(50)));
+
+        let req = r#"{"id": 2, "jsonrpc": 2.0, "method": "setVolume", "params": {"volume": 50, "ramp_ms": 60001}}"#;
+        let error = json!(server.request(req).unwrap_err());
+        assert_eq!(error["error"]["code"], -32602);
+    }
+
+    #[test]
+    fn set_volume_debounces_spirc_commands() {
+        let config = ServerConfig {
+            volume_max: 100,
+            volume_debounce: Duration::from_millis(50),
+            ..Default::default()
+        };
+        let (rt, server, mut rx) = spirc_test_server(&config);
+        let set_volume = |volume: u16| {
+            let req = format!(
+                r#"{{"id": 1, "jsonrpc": 2.0, "method": "setVolume", "params": {volume}}}"#
+            );
+            server.request(&req).unwrap();
+        };
+
+        for volume in [10, 20, 30] {
+            set_volume(volume);
+        }
+        assert!(rx.try_recv().is_err());
+        assert_eq!(server.player_state.read().volume, 30);
+
+        match rt.block_on(rx.recv()) {
+            Some(SpircCommand::SetVolume(volume)) => {
+                assert_eq!(volume, server.volume_from_client(30))
+            }
+            command => panic!("Unexpected command {command:?}"),
+        }
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn single_params_can_be_named() {
+        let config = ServerConfig {
+            volume_max: 100,
+            ..Default::default()
+        };
+        let (_rt, server, mut rx) = spirc_test_server(&config);
+        let mut set_volume = |params: &str| {
+            let req = format!(
+                r#"{{"id": 1, "jsonrpc": 2.0, "method": "setVolume", "params": {params}}}"#
+            );
+            server.request(&req).map(|_| match rx.try_recv() {
+                Ok(SpircCommand::SetVolume(volume)) => volume,
+                command => panic!("Unexpected command {command:?}"),
+            })
+        };
+
+        let volume = server.volume_from_client(50);
+        assert_eq!(set_volume("50").unwrap(), volume);
+        assert_eq!(set_volume(r#"{"volume": 50}"#).unwrap(), volume);
+        assert_eq!(set_volume("[50]").unwrap(), volume);
+
+        for params in [r#"{"level": 50}"#, r#"{"volume": "50"}"#, "[50, 60]"] {
+            let error = set_volume(params).unwrap_err();
+            assert_eq!(json!(error)["error"]["code"], -32602);
+        }
+    }
+
+    #[test]
+    fn shortcuts_stand_for_requests() {
+        let config = ServerConfig {
+            volume_max: 100,
+            ..ServerConfig::default()
+        };
+        let (rt, server, mut rx) = spirc_test_server(&config);
+        let send = |text: &str, shortcuts| {
+            rt.block_on(server.handle_socket_message(Ok(ws::Message::text(text)), shortcuts))
+        };
+
+        let response = send("pause", true).unwrap();
+        assert_eq!(json!(response)["id"], "pause");
+        assert!(matches!(rx.try_recv(), Ok(SpircCommand::Pause)));
+
+        server.player_state.write().volume = 50;
+        send(" vol+\n", true).unwrap();
+        let volume = server.volume_from_client(55);
+        assert!(matches!(rx.try_recv(), Ok(SpircCommand::SetVolume(v)) if v == volume));
+
+        // Requests still work, shortcuts are only taken when asked for
+        send(r#"{"id": 1, "jsonrpc": 2.0, "method": "setNext"}"#, true).unwrap();
+        assert!(matches!(rx.try_recv(), Ok(SpircCommand::Next)));
+        assert!(send("next", false).is_err());
+        let error = send("dance", true).unwrap_err();
+        assert_eq!(json!(error)["error"]["code"], -32601);
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn seek_percent_uses_track_duration() {
+        let (_rt, server, mut rx) = spirc_test_server(&ServerConfig::default());
+        let seek = |percent: f64| {
+            let req = format!(
+                r#"{{"id": 1, "jsonrpc": 2.0, "method": "seekPercent", "params": {{"percent": {percent}}}}}"#
+            );
+            server.request(&req)
+        };
+
+        // No track, so no duration to seek in
+        assert!(seek(50.0).is_err());
+
+        server.player_state.write().track = Some(test_track(200_000));
+        for (percent, position_ms) in [(25.5, 51_000), (150.0, 200_000), (-1.0, 0)] {
+            seek(percent).unwrap();
+            match rx.try_recv() {
+                Ok(SpircCommand::SetPosition(position)) => assert_eq!(position, position_ms),
+                command => panic!("Unexpected command {command:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn seek_percent_is_queued_while_loading() {
+        let (_rt, server, mut rx) = spirc_test_server(&ServerConfig::default());
+
+        server.handle_internal_event(PlayerEvent::Loading {
+            play_request_id: 1,
+            track_id: SpotifyId::from_uri(TEST_TRACK_URI).unwrap(),
+            position_ms: 0,
+        });
+        let req =
+            r#"{"id": 1, "jsonrpc": 2.0, "method": "seekPercent", "params": {"percent": 25}}"#;
+        server.request(req).unwrap();
+        assert!(rx.try_recv().is_err());
+
+        // Sent once the duration of the loaded track is known
+        server.handle_internal_event(PlayerEvent::TrackChanged {
+            audio_item: Box::new(test_audio_item(200_000)),
+            position_ms: 0,
+        });
+        match rx.try_recv() {
+            Ok(SpircCommand::SetPosition(position)) => assert_eq!(position, 50_000),
+            command => panic!("Unexpected command {command:?}"),
+        }
+    }
+
+    #[test]
+    fn play_and_pause_pass_while_loading() {
+        let (_rt, server, mut rx) = spirc_test_server(&ServerConfig::default());
+
+        server.handle_internal_event(PlayerEvent::Loading {
+            play_request_id: 1,
+            track_id: SpotifyId::from_uri("spotify:track:4uLU6hMCjMI75M1A2tKUQC").unwrap(),
+            position_ms: 0,
+        });
+        // The player decides whether the track starts playing once loaded
+        for method in ["setPause", "setPlay"] {
+            let req = format!(r#"{{"id": 1, "jsonrpc": 2.0, "method": "{method}"}}"#);
+            server.request(&req).unwrap();
+        }
+        assert!(matches!(rx.try_recv(), Ok(SpircCommand::Pause)));
+        assert!(matches!(rx.try_recv(), Ok(SpircCommand::Play)));
+    }
+
+    #[test]
+    fn seek_chapter_uses_chapter_start() {
+        let (_rt, server, mut rx) = spirc_test_server(&ServerConfig::default());
+        let request = |method: &str, params: &str| {
+            let req =
+                format!(r#"{{"id": 1, "jsonrpc": 2.0, "method": "{method}", "params": {params}}}"#);
+            let response = server.request(&req)?;
+            Ok::<_, JsonError>(json!(response)["result"].clone())
+        };
+
+        // No episode, no chapters
+        assert_eq!(request("getChapters", "null").unwrap(), json!([]));
+        assert!(request("seekChapter", r#"{"index": 0}"#).is_err());
+
+        let description = "00:00 Intro\n12:30 Interview";
+        server.player_state.write().track = Some(Track {
+            track_id: "4uLU6hMCjMI75M1A2tKUQC".to_string(),
+            uri: "spotify:episode:4uLU6hMCjMI75M1A2tKUQC".to_string(),
+            kind: TrackKind::Episode,
+            name: "Test Episode".to_string(),
+            covers: vec![],
+            album: None,
+            artists: vec![],
+            show_name: Some("Test Show".to_string()),
+            duration_ms: 3_600_000,
+            chapters: chapters::from_description(description, 3_600_000),
+        });
+        assert_eq!(
+            request("getChapters", "null").unwrap(),
+            json!([
+                {"title": "Intro", "start_ms": 0},
+                {"title": "Interview", "start_ms": 750_000},
+            ])
+        );
+
+        request("seekChapter", r#"{"index": 1}"#).unwrap();
+        match rx.try_recv() {
+            Ok(SpircCommand::SetPosition(position)) => assert_eq!(position, 750_000),
+            command => panic!("Unexpected command {command:?}"),
+        }
+        assert!(request("seekChapter", "[2]").is_err());
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn sessions_export_and_import() {
+        let config = ServerConfig {
+            volume_max: 100,
+            ..Default::default()
+        };
+        let (_rt, server, mut rx) = spirc_test_server(&config);
+        let request = |method: &str, params: &str| {
+            let req =
+                format!(r#"{{"id": 1, "jsonrpc": 2.0, "method": "{method}", "params": {params}}}"#);
+            let response = server.request(&req)?;
+            Ok::<_, JsonError>(json!(response)["result"].clone())
+        };
+
+        {
+            let mut state = server.player_state.write();
+            state.track = Some(test_track(180_000));
+            state.playing = PlayingState::Paused;
+            state.volume = 40;
+            state.shuffle = true;
+            state.repeat = true;
+            state.position_ms = 30_000;
+        }
+        assert_eq!(
+            request("exportSession", "null").unwrap(),
+            json!({
+                "version": 1,
+                "uri": "spotify:track:4uLU6hMCjMI75M1A2tKUQC",
+                "position_ms": 30_000,
+                "playing": false,
+                "volume": 40,
+                "volume_max": 100,
+                "shuffle": true,
+                "repeat": true,
+            })
+        );
+
+        // Exported where the volume went up to 200
+        let session = r#"{"version": 1, "uri": "spotify:track:4uLU6hMCjMI75M1A2tKUQC",
+            "position_ms": 60000, "playing": true, "volume": 100, "volume_max": 200,
+            "shuffle": false, "repeat": true}"#;
+        request("importSession", session).unwrap();
+        match rx.try_recv() {
+            Ok(SpircCommand::Load(command)) => {
+                assert_eq!(command.position_ms, 60_000);
+                assert!(command.start_playing);
+                assert!(!command.shuffle);
+                assert!(command.repeat);
+                assert_eq!(
+                    command.tracks[0].uri(),
+                    "spotify:track:4uLU6hMCjMI75M1A2tKUQC"
+                );
+            }
+            command => panic!("Unexpected command {command:?}"),
+        }
+        match rx.try_recv() {
+            Ok(SpircCommand::SetVolume(volume)) => {
+                assert_eq!(volume, server.volume_from_client(50))
+            }
+            command => panic!("Unexpected command {command:?}"),
+        }
+
+        let invalid = [
+            r#"{"version": 2, "uri": null, "position_ms": 0, "playing": false, "volume": 0, "volume_max": 100, "shuffle": false, "repeat": false}"#,
+            r#"{"version": 1, "uri": null, "position_ms": 0, "playing": false, "volume": 0, "volume_max": 100, "shuffle": false}"#,
+            r#"{"version": 1, "uri": null, "position_ms": 0, "playing": false, "volume": 0, "volume_max": 100, "shuffle": false, "repeat": false, "queue": []}"#,
+            r#"{"version": 1, "uri": null, "position_ms": 0, "playing": false, "volume": 101, "volume_max": 100, "shuffle": false, "repeat": false}"#,
+            r#"{"version": 1, "uri": "spotify:album:4uLU6hMCjMI75M1A2tKUQC", "position_ms": 0, "playing": false, "volume": 0, "volume_max": 100, "shuffle": false, "repeat": false}"#,
+        ];
+        for session in invalid {
+            assert!(request("importSession", session).is_err(), "{session}");
+        }
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn load_and_play_sends_position_with_load() {
+        let (_rt, server, mut rx) = spirc_test_server(&ServerConfig::default());
+
+        let req = r#"{"id": 1, "jsonrpc": 2.0, "method": "loadAndPlay", "params": {"uri": "spotify:track:4uLU6hMCjMI75M1A2tKUQC", "position_ms": 30000, "play": false}}"#;
+        server.request(req).unwrap();
+        match rx.try_recv() {
+            Ok(SpircCommand::Load(command)) => {
+                assert_eq!(command.position_ms, 30_000);
+                assert!(!command.start_playing);
+                assert_eq!(command.tracks.len(), 1);
+                assert_eq!(
+                    command.tracks[0].uri(),
+                    "spotify:track:4uLU6hMCjMI75M1A2tKUQC"
+                );
+            }
+            command => panic!("Unexpected command {command:?}"),
+        }
+
+        let req = r#"{"id": 2, "jsonrpc": 2.0, "method": "loadAndPlay", "params": {"uri": "spotify:album:4uLU6hMCjMI75M1A2tKUQC"}}"#;
+        assert!(server.request(req).is_err());
+        let req = r#"{"id": 3, "jsonrpc": 2.0, "method": "loadAndPlay", "params": {"uri": "spotify:track:4uLU6hMCjMI75M1A2tKUQC", "position_ms": -1}}"#;
+        assert!(server.request(req).is_err());
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn validate_uri_reports_kind() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let server = test_server(&rt, &ServerConfig::default());
+        let validate = |uri: &str| {
+            let req =
+                json!({"id": 1, "jsonrpc": 2.0, "method": "validateUri", "params": {"uri": uri}});
+            let response = server.request(&req.to_string()).unwrap();
+            serde_json::to_value(response).unwrap()["result"].clone()
+        };
+
+        let result = validate("spotify:album:4uLU6hMCjMI75M1A2tKUQC");
+        assert_eq!(result["valid"], true);
+        assert_eq!(result["kind"], "album");
+        assert_eq!(result["playable"], false);
+
+        let result = validate("spotify:user:name:playlist:4uLU6hMCjMI75M1A2tKUQC");
+        assert_eq!(result["kind"], "playlist");
+
+        let result = validate("spotify:episode:4uLU6hMCjMI75M1A2tKUQC");
+        assert_eq!(result["kind"], "episode");
+        assert_eq!(result["playable"], true);
+
+        for uri in ["spotify:track:4uLU6h", "https://open.spotify.com", ""] {
+            let result = validate(uri);
+            assert_eq!(result["valid"], false, "{uri}");
+            assert_eq!(result["kind"], serde_json::Value::Null);
+            assert!(result["error"].is_string());
+        }
+
+        let req = r#"{"id": 1, "jsonrpc": 2.0, "method": "validateUri", "params": {}}"#;
+        assert!(server.request(req).is_err());
+    }
+
+    #[test]
+    fn position_is_saved_and_restored() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let path = std::env::temp_dir().join(format!("librespot-position-{}", std::process::id()));
+        let config = ServerConfig {
+            position_file: Some(path.to_string_lossy().into_owned()),
+            ..ServerConfig::default()
+        };
+
+        let server = test_server(&rt, &config);
+        server.handle_internal_event(PlayerEvent::PositionCheckpoint {
+            play_request_id: 1,
+            track_id: SpotifyId::from_uri("spotify:track:4uLU6hMCjMI75M1A2tKUQC").unwrap(),
+            position_ms: 12_000,
+        });
+        let saved: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(
+            saved,
+            json!({"uri": "spotify:track:4uLU6hMCjMI75M1A2tKUQC", "position_ms": 12_000})
+        );
+
+        // Only restored for the first spirc
+        let server = test_server(&rt, &config);
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        *server.spirc.write() = Some(tx);
+        server.restore_position();
+        server.restore_position();
+        std::fs::remove_file(&path).unwrap();
+        match rx.try_recv() {
+            Ok(SpircCommand::Load(command)) => {
+                assert_eq!(command.position_ms, 12_000);
+                assert!(!command.start_playing);
+                assert_eq!(
+                    command.tracks[0].uri(),
+                    "spotify:track:4uLU6hMCjMI75M1A2tKUQC"
+                );
+            }
+            command => panic!("Unexpected command {command:?}"),
+        }
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn play_notification_has_play_request_id() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let server = test_server(&rt, &ServerConfig::default());
+        let mut notifications = server.user_message_tx.subscribe();
+        let track_id = SpotifyId::from_uri("spotify:track:4uLU6hMCjMI75M1A2tKUQC").unwrap();
+
+        server.handle_internal_event(PlayerEvent::PlayRequestIdChanged { play_request_id: 7 });
+        server.handle_internal_event(PlayerEvent::Playing {
+            play_request_id: 7,
+            track_id,
+            position_ms: 0,
+        });
+
+        let notification = std::iter::from_fn(|| notifications.try_recv().ok())
+            .find(|n| n.method == "OnPlay")
+            .unwrap();
+        assert_eq!(notification.params, json!({"play_request_id": 7}));
+
+        let response = server
+            .request(r#"{"id": 1, "jsonrpc": 2.0, "method": "getStatus"}"#)
+            .unwrap();
+        let status = &serde_json::to_value(response).unwrap()["result"];
+        assert_eq!(status["play_request_id"], 7);
+    }
+
+    #[test]
+    fn levels_reach_only_levels_subscribers() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let server = Arc::new(test_server(&rt, &ServerConfig::default()));
+        let mut notifications = server.user_message_tx.subscribe();
+        let subscription = Subscription {
+            levels: true,
+            ..Default::default()
+        };
+
+        assert!(server.levels_listener(&Subscription::default()).is_none());
+        let mut listener = server.levels_listener(&subscription);
+        assert_eq!(*server.levels_listeners.lock(), 1);
+
+        server.handle_internal_event(PlayerEvent::Levels {
+            peak: vec![0.5, 0.25],
+            rms: vec![0.25, 0.125],
+        });
+        // Not on the channel of all other notifications
+        assert!(notifications.try_recv().is_err());
+        let notification = listener.as_mut().unwrap().events.try_recv().unwrap();
+        assert_eq!(notification.method, "OnLevels");
+        assert_eq!(
+            notification.params,
+            json!({"peak": [0.5, 0.25], "rms": [0.25, 0.125]})
+        );
+        assert!(subscription.wants("OnLevels"));
+        assert!(!Subscription::default().wants("OnLevels"));
+
+        drop(listener);
+        assert_eq!(*server.levels_listeners.lock(), 0);
+    }
+
+    #[test]
+    fn preload_lead_time_must_be_milliseconds() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let server = test_server(&rt, &ServerConfig::default());
+        let set = |params: &str| {
+            let req = format!(
+                r#"{{"id": 1, "jsonrpc": 2.0, "method": "setPreloadLeadTime", "params": {params}}}"#
+            );
+            server.request(&req)
+        };
+
+        assert!(set("60000").is_ok());
+        assert!(set("-1").is_err());
+        assert!(set("1.5").is_err());
+        assert!(set(r#""60000""#).is_err());
+    }
+
+    #[test]
+    fn audio_key_unavailable_is_forwarded() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let server = test_server(&rt, &ServerConfig::default());
+        let mut notifications = server.user_message_tx.subscribe();
+
+        server.handle_internal_event(PlayerEvent::AudioKeyUnavailable {
+            play_request_id: 1,
+            track_id: SpotifyId::from_uri("spotify:track:4uLU6hMCjMI75M1A2tKUQC").unwrap(),
+        });
+
+        let notification = notifications.try_recv().unwrap();
+        assert_eq!(notification.method, "OnAudioKeyUnavailable");
+        assert_eq!(
+            notification.params,
+            json!({"track_id": "4uLU6hMCjMI75M1A2tKUQC"})
+        );
+    }
+
+    #[test]
+    fn player_requests_work_on_current_thread_runtime() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let server = test_server(&rt, &ServerConfig::default());
+        for method in ["getConfig", "getBufferProgress", "getDitherer"] {
+            let req = format!(r#"{{"id": 1, "jsonrpc": 2.0, "method": "{method}"}}"#);
+            assert!(rt.block_on(server.handle_request(&req)).is_ok());
+        }
+    }
+
+    #[test]
+    fn get_config_reflects_runtime_changes() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let server = test_server(&rt, &ServerConfig::default());
+        let config = |server: &ServerInternal| {
+            let response = server
+                .request(r#"{"id": 1, "jsonrpc": 2.0, "method": "getConfig"}"#)
+                .unwrap();
+            serde_json::to_value(response).unwrap()["result"].clone()
+        };
+
+        let before = config(&server);
+        assert_eq!(before["bitrate"], 160);
+        assert_eq!(before["gapless"], true);
+        assert_eq!(before["normalisation"]["method"], "dynamic");
+        assert_eq!(before["normalisation"]["attack_ms"], 5);
+        assert_eq!(before["normalisation"]["release_ms"], 100);
+
+        let req = r#"{"id": 2, "jsonrpc": 2.0, "method": "setGapless", "params": false}"#;
+        server.request(req).unwrap();
+        let req = r#"{"id": 3, "jsonrpc": 2.0, "method": "setBalance", "params": 0.5}"#;
+        server.request(req).unwrap();
+
+        let after = config(&server);
+        assert_eq!(after["gapless"], false);
+        assert_eq!(after["balance"], 0.5);
+    }
+
+    #[test]
+    fn recent_errors_are_bounded() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let server = test_server(&rt, &ServerConfig::default());
+
+        server.handle_internal_event(PlayerEvent::Unavailable {
+            play_request_id: 1,
+            track_id: SpotifyId::from_uri("spotify:track:4uLU6hMCjMI75M1A2tKUQC").unwrap(),
+            reason: UnavailableReason::Region,
+        });
+        for i in 0..RECENT_ERRORS_MAX {
+            server.handle_internal_event(PlayerEvent::SinkError {
+                message: format!("error {i}"),
+            });
+        }
+        server.handle_internal_event(PlayerEvent::VolumeChanged { volume: 1 });
+
+        let response = server
+            .request(r#"{"id": 1, "jsonrpc": 2.0, "method": "getRecentErrors"}"#)
+            .unwrap();
+        let result = &serde_json::to_value(response).unwrap()["result"];
+        let errors = result.as_array().unwrap();
+        assert_eq!(errors.len(), RECENT_ERRORS_MAX);
+        assert_eq!(errors[0]["event"], "sink_error");
+        assert_eq!(errors[0]["message"], "error 0");
+        assert_eq!(errors[0]["track_id"], serde_json::Value::Null);
+        assert!(errors[0]["time_ms"].as_u64().unwrap() > 0);
+
+        let server = test_server(&rt, &ServerConfig::default());
+        server.handle_internal_event(PlayerEvent::Unavailable {
+            play_request_id: 1,
+            track_id: SpotifyId::from_uri("spotify:track:4uLU6hMCjMI75M1A2tKUQC").unwrap(),
+            reason: UnavailableReason::Region,
+        });
+        let error = server.recent_errors.lock()[0].clone();
+        assert_eq!(error.level, "warning");
+        assert_eq!(error.message, "region");
+        assert_eq!(error.track_id.as_deref(), Some("4uLU6hMCjMI75M1A2tKUQC"));
+    }
+
+    #[test]
+    fn decode_stats_follow_events() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let server = test_server(&rt, &ServerConfig::default());
+        let request = r#"{"id": 1, "jsonrpc": 2.0, "method": "getDecodeStats"}"#;
+
+        let response = server.request(request).unwrap();
+        assert_eq!(
+            serde_json::to_value(response).unwrap()["result"],
+            serde_json::Value::Null
+        );
+
+        server.handle_internal_event(PlayerEvent::DecodeStats {
+            avg_decode_us: 250,
+            packets: 240,
+            skipped: 1,
+        });
+        let response = server.request(request).unwrap();
+        assert_eq!(
+            serde_json::to_value(response).unwrap()["result"],
+            json!({"avg_decode_us": 250, "packets": 240, "skipped": 1})
+        );
+    }
+
+    #[test]
+    fn session_stats_count_tracks_and_play_time() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let server = test_server(&rt, &ServerConfig::default());
+        let track_id = SpotifyId::from_uri("spotify:track:4uLU6hMCjMI75M1A2tKUQC").unwrap();
+        let playing = |play_request_id| PlayerEvent::Playing {
+            play_request_id,
+            track_id,
+            position_ms: 0,
+        };
+        let paused = PlayerEvent::Paused {
+            play_request_id: 2,
+            track_id,
+            position_ms: 0,
+        };
+
+        // Resuming doesn't count as another track
+        server.handle_internal_event(playing(1));
+        server.handle_internal_event(playing(2));
+        server.handle_internal_event(paused.clone());
+        server.handle_internal_event(playing(2));
+        std::thread::sleep(Duration::from_millis(20));
+        server.handle_internal_event(paused);
+
+        let response = server
+            .request(r#"{"id": 1, "jsonrpc": 2.0, "method": "getSessionStats"}"#)
+            .unwrap();
+        let result = serde_json::to_value(response).unwrap()["result"].clone();
+        assert_eq!(result["tracks_played"], 2);
+        let play_time_ms = result["play_time_ms"].as_u64().unwrap();
+        assert!(play_time_ms >= 20);
+        assert!(result["uptime_ms"].as_u64().unwrap() >= play_time_ms);
+
+        // Not counted while paused
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(
+            server.session_stats.lock().play_time().as_millis() as u64,
+            play_time_ms
+        );
+    }
+
+    #[test]
+    fn listener_token_is_required() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let open = authorized(None);
+        let protected = authorized(Some("secret".to_string()));
+
+        rt.block_on(async {
+            assert!(warp::test::request().matches(&open).await);
+            assert!(!warp::test::request().matches(&protected).await);
+            assert!(
+                warp::test::request()
+                    .header("authorization", "Bearer secret")
+                    .matches(&protected)
+                    .await
+            );
+            assert!(
+                warp::test::request()
+                    .path("/?delta=true&token=secret")
+                    .matches(&protected)
+                    .await
+            );
+            assert!(
+                !warp::test::request()
+                    .header("authorization", "Bearer wrong")
+                    .matches(&protected)
+                    .await
+            );
+        });
+
+        assert!(tokens_equal("secret", "secret"));
+        assert!(!tokens_equal("secret", "secreT"));
+        assert!(!tokens_equal("secret", "secret2"));
+    }
+
+    #[test]
+    fn playback_complete_is_forwarded() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let server = test_server(&rt, &ServerConfig::default());
+        let mut notifications = server.user_message_tx.subscribe();
+
+        let context_uri = "spotify:album:4uLU6hMCjMI75M1A2tKUQC".to_string();
+        server.handle_internal_event(PlayerEvent::PlaybackComplete { context_uri });
+
+        let notification = notifications.try_recv().unwrap();
+        assert_eq!(notification.method, "OnPlaybackComplete");
+        assert_eq!(
+            notification.params,
+            json!({"context_uri": "spotify:album:4uLU6hMCjMI75M1A2tKUQC"})
+        );
+    }
+
+    #[test]
+    fn event_stream_sends_notifications() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let server = Arc::new(test_server(&rt, &ServerConfig::default()));
+        let events = server.clone().event_stream(Subscription::default());
+        futures_util::pin_mut!(events);
+
+        server.handle_internal_event(PlayerEvent::VolumeChanged { volume: 0 });
+
+        let event = rt.block_on(events.next()).unwrap().unwrap().to_string();
+        assert!(event.starts_with("event:OnVolumeChange\n"));
+        let data = r#"data:{"jsonrpc":2.0,"method":"OnVolumeChange","params":{"volume":0}}"#;
+        assert!(event.contains(data));
+    }
+
+    #[test]
+    fn positions_count_from_event_emission() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let server = Arc::new(test_server(&rt, &ServerConfig::default()));
+        let subscription: Subscription =
+            serde_json::from_value(json!({"timestamps": true})).unwrap();
+        let events = server.clone().event_stream(subscription);
+        futures_util::pin_mut!(events);
+
+        // Waited in the channel for a while before being handled
+        let emitted_at = Instant::now() - Duration::from_secs(2);
+        let playing = PlayerEvent::Playing {
+            play_request_id: 1,
+            track_id: SpotifyId::from_uri("spotify:track:4uLU6hMCjMI75M1A2tKUQC").unwrap(),
+            position_ms: 10_000,
+        };
+        server.handle_player_event(playing, emitted_at);
+
+        assert!(server.player_state.read().position_ms() >= 12_000);
+        let event = rt.block_on(events.next()).unwrap().unwrap().to_string();
+        assert!(event.starts_with("event:OnPlay\n"));
+        let emitted_at_ms = format!(r#""emitted_at_ms":{}"#, server.unix_ms(emitted_at));
+        assert!(event.contains(&emitted_at_ms));
+    }
+
+    #[test]
+    fn closing_connections_ends_event_streams() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let server = Arc::new(test_server(&rt, &ServerConfig::default()));
+        let events = server.clone().event_stream(Subscription::default());
+        futures_util::pin_mut!(events);
+
+        server.close_connections();
+        assert!(rt.block_on(events.next()).is_none());
+        assert!(!server.cancel.is_cancelled());
+
+        // Connections made after a rebind stay open
+        let events = server.clone().event_stream(Subscription::default());
+        futures_util::pin_mut!(events);
+        server.handle_internal_event(PlayerEvent::VolumeChanged { volume: 0 });
+        assert!(rt.block_on(events.next()).is_some());
+    }
+
+    #[test]
+    fn rebind_keeps_listeners_when_binding_fails() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let listener = |port| ListenerConfig {
+            addr: ([127, 0, 0, 1], port).into(),
+            ..Default::default()
+        };
+        let free_port = || {
+            let socket = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            socket.local_addr().unwrap().port()
+        };
+        let port = free_port();
+        let config = ServerConfig {
+            listeners: vec![listener(port)],
+            ..Default::default()
+        };
+        let (server, _events) = Server::new_detached(config);
+        let connects = |port| {
+            // Binding happens on the server thread
+            (0..50).any(|_| {
+                std::thread::sleep(Duration::from_millis(10));
+                std::net::TcpStream::connect(("127.0.0.1", port)).is_ok()
+            })
+        };
+        assert!(connects(port));
+
+        let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let taken_port = taken.local_addr().unwrap().port();
+        let rebind = rt.block_on(server.rebind(vec![listener(taken_port)]));
+        assert!(matches!(rebind, Err(RebindError::Bind(_))));
+        assert!(connects(port));
+
+        // Moving to the address it is on already works too
+        rt.block_on(server.rebind(vec![listener(port)])).unwrap();
+        assert!(connects(port));
+
+        let new_port = free_port();
+        rt.block_on(server.rebind(vec![listener(new_port)]))
+            .unwrap();
+        assert!(connects(new_port));
+        server.shutdown(None);
+    }
+
+    #[test]
+    fn track_type_follows_unique_fields() {
+        let episode_id = SpotifyId::from_uri("spotify:episode:4uLU6hMCjMI75M1A2tKUQC").unwrap();
+        let episode = AudioItem {
+            track_id: episode_id,
+            uri: "spotify:episode:4uLU6hMCjMI75M1A2tKUQC".to_string(),
+            files: Default::default(),
+            name: "Test Episode".to_string(),
+            covers: vec![],
+            language: vec![],
+            duration_ms: 0,
+            is_explicit: false,
+            availability: Ok(()),
+            alternatives: None,
+            unique_fields: UniqueFields::Episode {
+                description: String::new(),
+                publish_time: Date::now_utc(),
+                show_name: "Test Show".to_string(),
+            },
+        };
+
+        let track = json!(Track::from_audio_item(episode.clone()));
+        assert_eq!(track["type"], "episode");
+        assert_eq!(track["show_name"], "Test Show");
+
+        let unique_fields = UniqueFields::Track {
+            artists: Default::default(),
+            album: "Test Album".to_string(),
+            album_artists: vec![],
+            popularity: 0,
+            number: 1,
+            disc_number: 1,
+        };
+        let track = json!(Track::from_audio_item(AudioItem {
+            unique_fields,
+            ..episode
+        }));
+        assert_eq!(track["type"], "track");
+        assert_eq!(track["album"], "Test Album");
+    }
+
+    #[test]
+    fn cover_by_min_width() {
+        let cover = |width| Cover {
+            url: format!("https://i.scdn.co/image/{width}"),
+            size: (width, width),
+        };
+        let track = Track {
+            covers: vec![cover(640), cover(64), cover(300)],
+            ..test_track(0)
+        };
+
+        assert_eq!(track.cover(0).unwrap().size.0, 64);
+        assert_eq!(track.cover(200).unwrap().size.0, 300);
+        assert_eq!(track.cover(300).unwrap().size.0, 300);
+        assert_eq!(track.cover(1000).unwrap().size.0, 640);
+    }
+
+    #[test]
+    fn now_playing_ref_matches_track_and_request() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let server = test_server(&rt, &ServerConfig::default());
+        let request = || {
+            let req = r#"{"id": 1, "jsonrpc": 2.0, "method": "getNowPlayingRef"}"#;
+            let response = server.request(req).unwrap();
+            serde_json::to_value(response).unwrap()["result"].clone()
+        };
+        assert_eq!(request(), serde_json::Value::Null);
+
+        server.handle_internal_event(PlayerEvent::PlayRequestIdChanged { play_request_id: 7 });
+        server.player_state.write().track = Some(test_track(0));
+        assert_eq!(
+            request(),
+            json!({
+                "track_id": "4uLU6hMCjMI75M1A2tKUQC",
+                "uri": "spotify:track:4uLU6hMCjMI75M1A2tKUQC",
+                "play_request_id": 7,
+            })
+        );
+    }
+
+    #[test]
+    fn track_info_rejects_invalid_uris() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let server = test_server(&rt, &ServerConfig::default());
+        let error_code = |params: &str| {
+            let req = format!(
+                r#"{{"id": 1, "jsonrpc": 2.0, "method": "getTrackInfo", "params": {params}}}"#
+            );
+            let response = server.request(&req).unwrap_err();
+            serde_json::to_value(response).unwrap()["error"]["code"].clone()
+        };
+
+        assert_eq!(error_code("null"), json!(-32602));
+        let invalid = r#"{"uri": "spotify:track:nope"}"#;
+        assert_eq!(error_code(invalid), json!(-32602));
+        assert_eq!(
+            error_code(r#"{"uri": "spotify:album:4uLU6hMCjMI75M1A2tKUQC"}"#),
+            json!(-32602)
+        );
+        // A valid uri needs a session to look it up
+        let uri = r#"{"uri": "spotify:track:4uLU6hMCjMI75M1A2tKUQC"}"#;
+        assert_eq!(error_code(uri), json!(-32001));
+    }
+
+    #[test]
+    fn denied_methods_are_forbidden() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let config = ServerConfig {
+            allowed_methods: Some(vec!["getStatus".to_string(), "setVolume".to_string()]),
+            denied_methods: vec!["setVolume".to_string()],
+            ..Default::default()
+        };
+        let server = test_server(&rt, &config);
+        let request = |method: &str| {
+            let req = format!(r#"{{"id": 1, "jsonrpc": 2.0, "method": "{method}"}}"#);
+            server
+                .request(&req)
+                .map_err(|e| serde_json::to_value(e).unwrap())
+        };
+
+        assert!(request("getStatus").is_ok());
+        assert_eq!(request("setVolume").unwrap_err()["error"]["code"], -32004);
+        assert_eq!(request("setPlay").unwrap_err()["error"]["code"], -32004);
+        assert_eq!(request("unknown").unwrap_err()["error"]["code"], -32601);
+    }
+
+    #[test]
+    fn detached_server_handles_synthetic_events() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let server = ServerInternal::new(None, &ServerConfig::default(), rt.handle().clone());
+        let request = |method: &str| {
+            let req = format!(r#"{{"id": 1, "jsonrpc": 2.0, "method": "{method}"}}"#);
+            server
+                .request(&req)
+                .map(|res| serde_json::to_value(res).unwrap())
+                .map_err(|e| serde_json::to_value(e).unwrap())
+        };
+
+        server.handle_internal_event(PlayerEvent::Playing {
+            play_request_id: 0,
+            track_id: SpotifyId::from_uri("spotify:track:4uLU6hMCjMI75M1A2tKUQC").unwrap(),
+            position_ms: 1_000,
+        });
+        let status = request("getStatus").unwrap();
+        assert_eq!(status["result"]["playing"], "Playing");
+
+        // Spirc commands wait for a channel, player commands have nowhere to go
+        assert_eq!(request("setPause").unwrap()["result"], "Queued");
+        assert_eq!(request("getDitherer").unwrap_err()["error"]["code"], -32001);
+    }
+
+    #[test]
+    fn no_covers_subscription_leaves_out_covers() {
+        let notification = JsonNotification {
+            jsonrpc: 2.0,
+            method: "OnNewTrack".to_string(),
+            params: json!({
+                "track": {"name": "Song", "covers": [{"url": "https://i.scdn.co/image/a"}]},
+                "position_ms": 0,
+            }),
+            emitted_at_ms: Some(1),
+        };
+        let no_covers: Subscription = serde_json::from_value(json!({"no_covers": true})).unwrap();
+
+        let mut lightweight = notification.clone();
+        no_covers.tailor(&mut lightweight);
+        assert_eq!(
+            lightweight.params,
+            json!({"track": {"name": "Song"}, "position_ms": 0})
+        );
+        assert_eq!(lightweight.emitted_at_ms, None);
+
+        let mut full = notification.clone();
+        Subscription::default().tailor(&mut full);
+        assert_eq!(full.params, notification.params);
+    }
+
+    // Fails writes with the given errors, last first, then accepts them
+    struct FlakySink {
+        errors: Vec<std::io::ErrorKind>,
+        sent: Vec<ws::Message>,
+    }
+
+    impl Sink<ws::Message> for FlakySink {
+        type Error = std::io::Error;
+
+        fn poll_ready(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn start_send(mut self: Pin<&mut Self>, frame: ws::Message) -> Result<(), Self::Error> {
+            match self.errors.pop() {
+                Some(kind) => Err(kind.into()),
+                None => {
+                    self.sent.push(frame);
+                    Ok(())
+                }
+            }
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[test]
+    fn transient_write_errors_are_retried() {
+        use std::io::ErrorKind::{BrokenPipe, Interrupted, TimedOut};
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let send = |errors: Vec<std::io::ErrorKind>, retries| {
+            let mut sink = FlakySink {
+                errors,
+                sent: vec![],
+            };
+            let sent = rt.block_on(send_frame(&mut sink, ws::Message::text("{}"), retries));
+            (sent.is_ok(), sink)
+        };
+
+        let (sent, sink) = send(vec![Interrupted, TimedOut], 2);
+        assert!(sent);
+        assert_eq!(sink.sent.len(), 1);
+
+        let (sent, _) = send(vec![Interrupted, TimedOut], 1);
+        assert!(!sent);
+
+        // A closed connection isn't retried
+        let (sent, sink) = send(vec![Interrupted, BrokenPipe], 2);
+        assert!(!sent);
+        assert_eq!(sink.errors, vec![Interrupted]);
+    }
+
+    #[test]
+    fn msgpack_subscription_sends_binary_frames() {
+        let json: Subscription = serde_json::from_value(json!({})).unwrap();
+        let msgpack: Subscription = serde_json::from_value(json!({"encoding": "msgpack"})).unwrap();
+        let value = json!({"method": "OnPause"});
+
+        assert_eq!(json.frame(&value), ws::Message::text(value.to_string()));
+        let frame = msgpack.frame(&value);
+        assert!(frame.is_binary());
+        let decoded: serde_json::Value = rmp_serde::from_slice(frame.as_bytes()).unwrap();
+        assert_eq!(decoded, value);
+
+        // Structs are maps keyed by field name, as in JSON
+        let notification = JsonNotification {
+            jsonrpc: 2.0,
+            method: "OnPause".to_string(),
+            params: serde_json::Value::Null,
+            emitted_at_ms: None,
+        };
+        let frame = msgpack.frame(&notification);
+        let decoded: serde_json::Value = rmp_serde::from_slice(frame.as_bytes()).unwrap();
+        assert_eq!(decoded, json!(notification));
+    }
+
+    #[test]
+    fn compressed_subscription_sends_deflated_frames() {
+        use flate2::read::DeflateDecoder;
+        use std::io::Read;
+
+        let value = json!({"method": "OnPause", "params": {"track": "x".repeat(500)}});
+        let inflate = |frame: ws::Message| {
+            assert!(frame.is_binary());
+            let mut data = Vec::new();
+            DeflateDecoder::new(frame.as_bytes())
+                .read_to_end(&mut data)
+                .unwrap();
+            data
+        };
+
+        let json: Subscription = serde_json::from_value(json!({"compress": true})).unwrap();
+        let frame = json.frame(&value);
+        assert!(frame.as_bytes().len() < value.to_string().len());
+        assert_eq!(inflate(frame), value.to_string().into_bytes());
+
+        let msgpack: Subscription =
+            serde_json::from_value(json!({"compress": true, "encoding": "msgpack"})).unwrap();
+        let data = inflate(msgpack.frame(&value));
+        let decoded: serde_json::Value = rmp_serde::from_slice(&data).unwrap();
+        assert_eq!(decoded, value);
+    }
+
+    #[test]
+    fn resume_token_restores_subscription_once() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let server = test_server(&rt, &ServerConfig::default());
+        let resume = |token: &str| Subscription {
+            delta: false,
+            resume: Some(token.to_string()),
+            ..Default::default()
+        };
+
+        let delta = Subscription {
+            delta: true,
+            resume: None,
+            ..Default::default()
+        };
+        let token = new_resume_token();
+        server.keep_for_resume(token.clone(), delta);
+
+        assert!(!server.resume_subscription(resume("unknown")).delta);
+        assert!(server.resume_subscription(resume(&token)).delta);
+        assert!(!server.resume_subscription(resume(&token)).delta);
+    }
+
+    #[test]
+    fn resume_token_expires() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let config = ServerConfig {
+            resume_ttl: Duration::ZERO,
+            ..Default::default()
+        };
+        let server = test_server(&rt, &config);
+
+        let delta = Subscription {
+            delta: true,
+            resume: None,
+            ..Default::default()
+        };
+        let token = new_resume_token();
+        server.keep_for_resume(token.clone(), delta);
+        thread::sleep(Duration::from_millis(1));
+
+        let resumed = server.resume_subscription(Subscription {
+            delta: false,
+            resume: Some(token),
+            ..Default::default()
+        });
+        assert!(!resumed.delta);
     }
 }
@@ -152,6 +152,14 @@ impl StreamLoaderController {
         }
     }
 
+    // Number of bytes of the file that have been downloaded, in any range
+    pub fn downloaded_len(&self) -> usize {
+        match self.stream_shared {
+            Some(ref shared) => shared.download_status.lock().downloaded.len(),
+            None => self.len(),
+        }
+    }
+
     pub fn ping_time(&self) -> Option<Duration> {
         self.stream_shared.as_ref().map(|shared| shared.ping_time())
     }
@@ -136,6 +136,8 @@ pub struct SpircLoadCommand {
     pub repeat: bool,
     pub playing_track_index: u32,
     pub tracks: Vec<TrackRef>,
+    /// Position in the first played track to start from.
+    pub position_ms: u32,
 }
 
 impl From<SpircLoadCommand> for State {
@@ -151,6 +153,7 @@ impl From<SpircLoadCommand> for State {
         state.set_repeat(command.repeat);
         state.set_playing_track_index(command.playing_track_index);
         state.track = command.tracks;
+        state.set_position_ms(command.position_ms);
         state
     }
 }
@@ -655,6 +658,11 @@ impl SpircTask {
                     self.handle_activate();
                     self.notify(None)
                 }
+                // Loading activates the device on its own
+                SpircCommand::Load(command) => {
+                    self.handle_load(&command.into())?;
+                    self.notify(None)
+                }
                 _ => {
                     warn!("SpircCommand::{:?} will be ignored while Not Active", cmd);
                     Ok(())
@@ -669,6 +677,12 @@ impl SpircTask {
             self.play_request_id = Some(play_request_id);
             return Ok(());
         }
+        // The player stopped after an internal error, without a Stopped event
+        if let PlayerEvent::StateReset { .. } = event {
+            self.state.set_status(PlayStatus::kPlayStatusStop);
+            self.play_status = SpircPlayStatus::Stopped;
+            return self.notify(None);
+        }
         // we only process events if the play_request_id matches. If it doesn't, it is
         // an event that belongs to a previous track and only arrives now due to a race
         // condition. In this case we have updated the state already and don't want to
@@ -1211,7 +1225,8 @@ impl SpircTask {
         self.handle_preload_next_track();
     }
 
-    fn handle_next(&mut self) {
+    // True when the end of the context was reached and playback doesn't continue
+    fn handle_next(&mut self) -> bool {
         let context_uri = self.state.context_uri().to_owned();
         let mut tracks_len = self.state.track.len() as u32;
         let mut new_index = self.consume_queued_track() as u32;
@@ -1237,6 +1252,8 @@ impl SpircTask {
             }
         }
 
+        let mut finished = false;
+
         // When not in autoplay, either start autoplay or loop back to the start
         if new_index >= tracks_len {
             // for some contexts there is no autoplay, such as shows and episodes
@@ -1251,6 +1268,7 @@ impl SpircTask {
                 self.player.set_auto_normalise_as_album(false);
             } else {
                 new_index = 0;
+                finished = !self.state.repeat();
                 continue_playing &= self.state.repeat();
                 debug!("Looping back to start, repeat is {}", continue_playing);
             }
@@ -1259,10 +1277,12 @@ impl SpircTask {
         if tracks_len > 0 {
             self.state.set_playing_track_index(new_index);
             self.load_track(continue_playing, 0);
+            finished
         } else {
             info!("Not playing next track because there are no more tracks left in queue.");
             self.state.set_playing_track_index(0);
             self.handle_stop();
+            true
         }
     }
 
@@ -1317,7 +1337,11 @@ impl SpircTask {
     }
 
     fn handle_end_of_track(&mut self) -> Result<(), Error> {
-        self.handle_next();
+        let context_uri = self.state.context_uri().to_owned();
+        if self.handle_next() {
+            info!("Reached the end of <{}>", context_uri);
+            self.player.emit_playback_complete_event(context_uri);
+        }
         self.notify(None)
     }
 
@@ -1512,13 +1536,14 @@ impl SpircTask {
         let new_volume = volume as u32;
         if old_volume != new_volume {
             self.device.set_volume(new_volume);
+            // Reported before the mixer does, which then leaves it out as a repeat
+            if self.device.is_active() {
+                self.player.emit_volume_changed_event(volume);
+            }
             self.mixer.set_volume(volume);
             if let Some(cache) = self.session.cache() {
                 cache.save_volume(volume)
             }
-            if self.device.is_active() {
-                self.player.emit_volume_changed_event(volume);
-            }
         }
     }
 }
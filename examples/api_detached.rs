@@ -0,0 +1,48 @@
+use librespot::{
+    api_server::{Server, ServerConfig},
+    connect::spirc::SpircCommand,
+    core::SpotifyId,
+    playback::player::PlayerEvent,
+};
+use tokio::sync::mpsc;
+
+// Runs the API server and web interface on port 3030 without a player or Spotify
+// account. Playback commands are answered with the events the player would send,
+// so clients can be developed against a fake track.
+#[tokio::main]
+async fn main() {
+    let config = ServerConfig {
+        enable_web: true,
+        ..Default::default()
+    };
+    let (server, events) = Server::new_detached(config);
+
+    let (spirc_tx, mut spirc_rx) = mpsc::unbounded_channel();
+    server.set_spirc_channel(spirc_tx);
+
+    let track_id = SpotifyId::from_uri("spotify:track:4uLU6hMCjMI75M1A2tKUQC").unwrap();
+
+    println!("Serving on http://localhost:3030");
+    while let Some(command) = spirc_rx.recv().await {
+        println!("Received {:?}", command);
+        let event = match command {
+            SpircCommand::Play => PlayerEvent::Playing {
+                play_request_id: 0,
+                track_id,
+                position_ms: 0,
+            },
+            SpircCommand::Pause => PlayerEvent::Paused {
+                play_request_id: 0,
+                track_id,
+                position_ms: 0,
+            },
+            SpircCommand::SetVolume(volume) => PlayerEvent::VolumeChanged { volume },
+            SpircCommand::Shuffle(shuffle) => PlayerEvent::ShuffleChanged { shuffle },
+            _ => continue,
+        };
+
+        if events.send(event).is_err() {
+            break;
+        }
+    }
+}
@@ -91,6 +91,7 @@ async fn main() {
                 repeat: false,
                 playing_track_index: 0, // the index specifies which track in the context starts playing, in this case the first in the album
                 tracks,
+                position_ms: 0,
             })
             .unwrap();
     });
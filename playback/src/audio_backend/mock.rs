@@ -0,0 +1,103 @@
+use super::{Open, Sink, SinkError, SinkResult};
+use crate::config::AudioFormat;
+use crate::convert::Converter;
+use crate::decoder::AudioPacket;
+
+use parking_lot::Mutex;
+use std::sync::Arc;
+
+/// Everything a `MockSink` has been asked to do so far.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct MockSinkLog {
+    pub running: bool,
+    pub starts: usize,
+    pub stops: usize,
+    pub packets: usize,
+    pub samples: usize,
+}
+
+/// Number of upcoming calls that fail, to simulate a flaky device.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct MockSinkFaults {
+    pub writes: usize,
+    pub starts: usize,
+    pub stops: usize,
+}
+
+/// A sink that discards all audio and only records how it was driven.
+/// Clones share the same log, so a test can keep a handle to a sink it
+/// hands over to the player.
+#[derive(Debug, Default, Clone)]
+pub struct MockSink {
+    log: Arc<Mutex<MockSinkLog>>,
+    faults: Arc<Mutex<MockSinkFaults>>,
+}
+
+impl MockSink {
+    pub const NAME: &'static str = "mock";
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn log(&self) -> MockSinkLog {
+        self.log.lock().clone()
+    }
+
+    pub fn set_faults(&self, faults: MockSinkFaults) {
+        *self.faults.lock() = faults;
+    }
+}
+
+impl Open for MockSink {
+    fn open(_: Option<String>, _: AudioFormat) -> Self {
+        info!("Using MockSink, audio will be discarded");
+        Self::new()
+    }
+}
+
+impl Sink for MockSink {
+    fn start(&mut self) -> SinkResult<()> {
+        let mut faults = self.faults.lock();
+        if faults.starts > 0 {
+            faults.starts -= 1;
+            return Err(SinkError::ConnectionRefused(
+                "mock start failure".to_string(),
+            ));
+        }
+
+        let mut log = self.log.lock();
+        log.running = true;
+        log.starts += 1;
+        Ok(())
+    }
+
+    fn stop(&mut self) -> SinkResult<()> {
+        let mut faults = self.faults.lock();
+        if faults.stops > 0 {
+            faults.stops -= 1;
+            return Err(SinkError::StateChange("mock stop failure".to_string()));
+        }
+
+        let mut log = self.log.lock();
+        log.running = false;
+        log.stops += 1;
+        Ok(())
+    }
+
+    fn write(&mut self, packet: &AudioPacket, _: &mut Converter) -> SinkResult<()> {
+        let mut faults = self.faults.lock();
+        if faults.writes > 0 {
+            faults.writes -= 1;
+            return Err(SinkError::OnWrite("mock write failure".to_string()));
+        }
+
+        let mut log = self.log.lock();
+        log.packets += 1;
+        log.samples += match packet {
+            AudioPacket::Samples(samples) => samples.len(),
+            AudioPacket::Raw(bytes) => bytes.len(),
+        };
+        Ok(())
+    }
+}
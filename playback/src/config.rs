@@ -1,6 +1,7 @@
 use std::{mem, str::FromStr, time::Duration};
 
 pub use crate::dither::{mk_ditherer, DithererBuilder, TriangularDitherer};
+pub use crate::filter::EqBand;
 use crate::{convert::i24, player::duration_to_coefficient};
 
 #[derive(Clone, Copy, Debug, Hash, PartialOrd, Ord, PartialEq, Eq)]
@@ -28,6 +29,45 @@ impl Default for Bitrate {
     }
 }
 
+impl Bitrate {
+    pub fn kbps(&self) -> u16 {
+        match self {
+            Self::Bitrate96 => 96,
+            Self::Bitrate160 => 160,
+            Self::Bitrate320 => 320,
+        }
+    }
+
+    // Next lower bitrate, None at the lowest
+    pub fn lower(&self) -> Option<Self> {
+        match self {
+            Self::Bitrate96 => None,
+            Self::Bitrate160 => Some(Self::Bitrate96),
+            Self::Bitrate320 => Some(Self::Bitrate160),
+        }
+    }
+}
+
+// Lowers the bitrate of the following tracks when the current one keeps stalling
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AdaptiveBitrate {
+    // stalls of one track within `window` that lower the bitrate by a step
+    pub stalls: u32,
+    pub window: Duration,
+    // go back to the configured bitrate after this long without a stall
+    pub recovery: Duration,
+}
+
+impl Default for AdaptiveBitrate {
+    fn default() -> Self {
+        Self {
+            stalls: 3,
+            window: Duration::from_secs(60),
+            recovery: Duration::from_secs(300),
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug, Hash, PartialOrd, Ord, PartialEq, Eq)]
 pub enum AudioFormat {
     F64,
@@ -71,6 +111,16 @@ impl AudioFormat {
             _ => mem::size_of::<i32>(), // S32 and S24 are both stored in i32
         }
     }
+
+    // significant bits per sample, regardless of how they are packed
+    pub fn bit_depth(&self) -> u8 {
+        match self {
+            Self::F64 => 64,
+            Self::F32 | Self::S32 => 32,
+            Self::S24 | Self::S24_3 => 24,
+            Self::S16 => 16,
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -102,6 +152,8 @@ impl Default for NormalisationType {
 pub enum NormalisationMethod {
     Basic,
     Dynamic,
+    // Scale the peak level to the threshold, without ReplayGain
+    Peak,
 }
 
 impl FromStr for NormalisationMethod {
@@ -110,6 +162,7 @@ impl FromStr for NormalisationMethod {
         match s.to_lowercase().as_ref() {
             "basic" => Ok(Self::Basic),
             "dynamic" => Ok(Self::Dynamic),
+            "peak" => Ok(Self::Peak),
             _ => Err(()),
         }
     }
@@ -121,11 +174,66 @@ impl Default for NormalisationMethod {
     }
 }
 
+// What the player does by itself when a track ends, no next track is preloaded and no
+// other track is loaded shortly after
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QueueEndBehavior {
+    // Leave the last track at its end
+    Stop,
+    // Start over from the first track of the queue
+    RepeatAll,
+    // Rewind the last track and pause
+    Pause,
+}
+
+impl Default for QueueEndBehavior {
+    fn default() -> Self {
+        Self::Stop
+    }
+}
+
 #[derive(Clone)]
 pub struct PlayerConfig {
     pub bitrate: Bitrate,
+    // lower the bitrate of the next tracks while playback keeps stalling, off when None
+    pub adaptive_bitrate: Option<AdaptiveBitrate>,
     pub gapless: bool,
     pub passthrough: bool,
+    // average left and right into both channels, for mono speakers
+    pub downmix_mono: bool,
+    // parametric equaliser bands, applied in order
+    pub equalizer: Vec<EqBand>,
+    // channel balance from -1.0 (left) to 1.0 (right)
+    pub balance: f64,
+    // attenuation in dB while ducked, e.g. during a voice assistant prompt
+    pub duck_attenuation_db: f64,
+    // how long ducking takes to fade in and out
+    pub duck_ramp: Duration,
+    // fade out over this many milliseconds when stopping, so it doesn't click. Stopping
+    // waits for the fade to be written, keep it short. 0 stops right away.
+    pub stop_fade_ms: u32,
+    // give up loading a track after this long, e.g. when the network stalls
+    pub load_timeout: Duration,
+    // retry a failed sink write this many times before pausing, restarting the sink in between
+    pub sink_write_retries: u32,
+    // deliver player events from a separate thread, so many or slow subscribers
+    // never delay the player thread
+    pub offload_events: bool,
+    // buffer at most this many events per subscriber, dropping the oldest droppable ones
+    // first, see `PlayerEvent::is_droppable`. Unbounded when None.
+    pub event_channel_capacity: Option<usize>,
+    // start every track this far in, e.g. to skip leading silence. Loads at a later
+    // position are not affected.
+    pub trim_start_ms: u32,
+    // end every track this far before the decoder does, to skip trailing
+    // padding between gapless tracks. 0 plays tracks to the end.
+    pub trim_end_ms: u32,
+    // for embedders without spirc, which otherwise decides what plays next. The queue
+    // starts at the last track loaded while not at the end of a track.
+    pub on_queue_end: QueueEndBehavior,
+    // ask for the next track to be preloaded this long before the end of the current
+    // one, can be changed while playing
+    pub preload_lead_time: Duration,
 
     pub normalisation: bool,
     pub normalisation_type: NormalisationType,
@@ -145,6 +253,7 @@ impl Default for PlayerConfig {
     fn default() -> Self {
         Self {
             bitrate: Bitrate::default(),
+            adaptive_bitrate: None,
             gapless: true,
             normalisation: false,
             normalisation_type: NormalisationType::default(),
@@ -155,6 +264,20 @@ impl Default for PlayerConfig {
             normalisation_release_cf: duration_to_coefficient(Duration::from_millis(100)),
             normalisation_knee_db: 5.0,
             passthrough: false,
+            downmix_mono: false,
+            equalizer: Vec::new(),
+            balance: 0.0,
+            duck_attenuation_db: 20.0,
+            duck_ramp: Duration::from_millis(200),
+            stop_fade_ms: 0,
+            load_timeout: Duration::from_secs(30),
+            sink_write_retries: 2,
+            offload_events: false,
+            event_channel_capacity: None,
+            trim_start_ms: 0,
+            trim_end_ms: 0,
+            on_queue_end: QueueEndBehavior::default(),
+            preload_lead_time: Duration::from_secs(30),
             ditherer: Some(mk_ditherer::<TriangularDitherer>),
         }
     }
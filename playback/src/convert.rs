@@ -1,3 +1,4 @@
+use crate::config::AudioFormat;
 use crate::dither::{Ditherer, DithererBuilder};
 use zerocopy::AsBytes;
 
@@ -19,6 +20,7 @@ impl i24 {
 
 pub struct Converter {
     ditherer: Option<Box<dyn Ditherer>>,
+    output_format: Option<AudioFormat>,
 }
 
 impl Converter {
@@ -29,12 +31,28 @@ impl Converter {
                 info!("Converting with ditherer: {}", ditherer.name());
                 Self {
                     ditherer: Some(ditherer),
+                    output_format: None,
                 }
             }
-            None => Self { ditherer: None },
+            None => Self {
+                ditherer: None,
+                output_format: None,
+            },
         }
     }
 
+    /// The format the most recent samples were converted to. `None` until the
+    /// sink asks for a conversion, which it never does when it takes `F64`
+    /// samples or raw passthrough data as they are.
+    pub fn output_format(&self) -> Option<AudioFormat> {
+        self.output_format
+    }
+
+    /// The name of the ditherer applied when converting to integer formats.
+    pub fn ditherer_name(&self) -> Option<&'static str> {
+        self.ditherer.as_ref().map(|d| d.name())
+    }
+
     /// To convert PCM samples from floating point normalized as `-1.0..=1.0`
     /// to 32-bit signed integer, multiply by 2147483648 (0x80000000) and
     /// saturate at the bounds of `i32`.
@@ -80,10 +98,12 @@ impl Converter {
     }
 
     pub fn f64_to_f32(&mut self, samples: &[f64]) -> Vec<f32> {
+        self.output_format = Some(AudioFormat::F32);
         samples.iter().map(|sample| *sample as f32).collect()
     }
 
     pub fn f64_to_s32(&mut self, samples: &[f64]) -> Vec<i32> {
+        self.output_format = Some(AudioFormat::S32);
         samples
             .iter()
             .map(|sample| self.scale(*sample, Self::SCALE_S32) as i32)
@@ -92,6 +112,7 @@ impl Converter {
 
     // S24 is 24-bit PCM packed in an upper 32-bit word
     pub fn f64_to_s24(&mut self, samples: &[f64]) -> Vec<i32> {
+        self.output_format = Some(AudioFormat::S24);
         samples
             .iter()
             .map(|sample| self.clamping_scale(*sample, Self::SCALE_S24) as i32)
@@ -100,6 +121,7 @@ impl Converter {
 
     // S24_3 is 24-bit PCM in a 3-byte array
     pub fn f64_to_s24_3(&mut self, samples: &[f64]) -> Vec<i24> {
+        self.output_format = Some(AudioFormat::S24_3);
         samples
             .iter()
             .map(|sample| i24::from_s24(self.clamping_scale(*sample, Self::SCALE_S24) as i32))
@@ -107,6 +129,7 @@ impl Converter {
     }
 
     pub fn f64_to_s16(&mut self, samples: &[f64]) -> Vec<i16> {
+        self.output_format = Some(AudioFormat::S16);
         samples
             .iter()
             .map(|sample| self.scale(*sample, Self::SCALE_S16) as i16)
@@ -16,6 +16,8 @@ pub enum DecoderError {
     PassthroughDecoder(String),
     #[error("Symphonia Decoder Error: {0}")]
     SymphoniaDecoder(String),
+    #[error("Unsupported Codec: {0}")]
+    UnsupportedCodec(String),
 }
 
 pub type DecoderResult<T> = Result<T, DecoderError>;
@@ -30,6 +32,7 @@ pub enum AudioPacketError {
 
 pub type AudioPacketResult<T> = Result<T, AudioPacketError>;
 
+#[derive(Clone)]
 pub enum AudioPacket {
     Samples(Vec<f64>),
     Raw(Vec<u8>),
@@ -84,6 +87,11 @@ impl From<DecoderError> for librespot_core::error::Error {
 
 impl From<symphonia::core::errors::Error> for DecoderError {
     fn from(err: symphonia::core::errors::Error) -> Self {
-        Self::SymphoniaDecoder(err.to_string())
+        match err {
+            symphonia::core::errors::Error::Unsupported(feature) => {
+                Self::UnsupportedCodec(feature.to_string())
+            }
+            _ => Self::SymphoniaDecoder(err.to_string()),
+        }
     }
 }
@@ -48,7 +48,7 @@ impl<R: Read + Seek> PassthroughDecoder<R> {
     /// Constructs a new Decoder from a given implementation of `Read + Seek`.
     pub fn new(rdr: R, format: AudioFileFormat) -> DecoderResult<Self> {
         if !AudioFiles::is_ogg_vorbis(format) {
-            return Err(DecoderError::PassthroughDecoder(format!(
+            return Err(DecoderError::UnsupportedCodec(format!(
                 "Passthrough decoder is not implemented for format {format:?}"
             )));
         }
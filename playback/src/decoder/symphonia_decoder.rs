@@ -50,7 +50,7 @@ impl SymphoniaDecoder {
         } else if AudioFiles::is_mp3(file_format) {
             Box::new(MpaReader::try_new(mss, &format_opts)?)
         } else {
-            return Err(DecoderError::SymphoniaDecoder(format!(
+            return Err(DecoderError::UnsupportedCodec(format!(
                 "Unsupported format: {file_format:?}"
             )));
         };
@@ -65,7 +65,7 @@ impl SymphoniaDecoder {
         } else if AudioFiles::is_mp3(file_format) {
             Box::new(MpaDecoder::try_new(&track.codec_params, &decoder_opts)?)
         } else {
-            return Err(DecoderError::SymphoniaDecoder(format!(
+            return Err(DecoderError::UnsupportedCodec(format!(
                 "Unsupported decoder: {file_format:?}"
             )));
         };
@@ -114,7 +114,7 @@ impl SymphoniaDecoder {
         if tags.is_empty() {
             None
         } else {
-            let mut data = NormalisationData::default();
+            let mut data = NormalisationData::unknown();
 
             for tag in tags {
                 if let Value::Float(value) = tag.value {
@@ -0,0 +1,270 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+use parking_lot::{Condvar, Mutex};
+use tokio::sync::{mpsc, Notify};
+
+use crate::player::PlayerEvent;
+
+// Creates a channel for player events, unbounded without a capacity. A bounded channel
+// that is full makes room by dropping its oldest droppable event, see
+// `PlayerEvent::is_droppable`. Other events are always delivered, even beyond the
+// capacity, so a stalled subscriber only holds on to the events it can't do without.
+// Every event carries the monotonic time it was sent at, see `recv_timestamped`.
+pub fn player_event_channel(capacity: Option<usize>) -> (PlayerEventSender, PlayerEventChannel) {
+    match capacity {
+        None => {
+            let (sender, receiver) = mpsc::unbounded_channel();
+            (
+                PlayerEventSender::Unbounded(sender),
+                PlayerEventChannel::Unbounded(receiver),
+            )
+        }
+        Some(capacity) => {
+            let queue = Arc::new(BoundedQueue {
+                capacity,
+                events: Mutex::new(VecDeque::with_capacity(capacity)),
+                ready: Condvar::new(),
+                notify: Notify::new(),
+                sender_closed: AtomicBool::new(false),
+                receiver_closed: AtomicBool::new(false),
+            });
+            (
+                PlayerEventSender::Bounded(queue.clone()),
+                PlayerEventChannel::Bounded(queue),
+            )
+        }
+    }
+}
+
+pub struct BoundedQueue {
+    capacity: usize,
+    events: Mutex<VecDeque<(Instant, PlayerEvent)>>,
+    // Wakes blocking and async receivers respectively
+    ready: Condvar,
+    notify: Notify,
+    sender_closed: AtomicBool,
+    receiver_closed: AtomicBool,
+}
+
+impl BoundedQueue {
+    fn push(&self, emitted_at: Instant, event: PlayerEvent) {
+        let mut events = self.events.lock();
+        if events.len() >= self.capacity {
+            match events.iter().position(|(_, event)| event.is_droppable()) {
+                Some(index) => {
+                    events.remove(index);
+                }
+                None if event.is_droppable() => return,
+                None => (),
+            }
+        }
+        events.push_back((emitted_at, event));
+        drop(events);
+
+        self.ready.notify_one();
+        self.notify.notify_one();
+    }
+
+    fn close_sender(&self) {
+        // Under the lock, so that a blocking receiver can't miss it
+        let events = self.events.lock();
+        self.sender_closed.store(true, Ordering::Release);
+        drop(events);
+
+        self.ready.notify_all();
+        self.notify.notify_one();
+    }
+}
+
+pub enum PlayerEventSender {
+    Unbounded(mpsc::UnboundedSender<(Instant, PlayerEvent)>),
+    Bounded(Arc<BoundedQueue>),
+}
+
+impl PlayerEventSender {
+    // Fails with the event when the receiving end is gone
+    pub fn send(&self, event: PlayerEvent) -> Result<(), PlayerEvent> {
+        self.send_timestamped(Instant::now(), event)
+    }
+
+    // Same as `send`, for an event that was emitted earlier, e.g. by a player
+    // fanning out its events on another thread
+    pub fn send_timestamped(
+        &self,
+        emitted_at: Instant,
+        event: PlayerEvent,
+    ) -> Result<(), PlayerEvent> {
+        match self {
+            Self::Unbounded(sender) => sender.send((emitted_at, event)).map_err(|e| (e.0).1),
+            Self::Bounded(queue) => {
+                if queue.receiver_closed.load(Ordering::Acquire) {
+                    return Err(event);
+                }
+                queue.push(emitted_at, event);
+                Ok(())
+            }
+        }
+    }
+
+    // The receiving end is gone, further sends fail
+    pub fn is_closed(&self) -> bool {
+        match self {
+            Self::Unbounded(sender) => sender.is_closed(),
+            Self::Bounded(queue) => queue.receiver_closed.load(Ordering::Acquire),
+        }
+    }
+}
+
+impl Drop for PlayerEventSender {
+    fn drop(&mut self) {
+        if let Self::Bounded(queue) = self {
+            queue.close_sender();
+        }
+    }
+}
+
+pub enum PlayerEventChannel {
+    Unbounded(mpsc::UnboundedReceiver<(Instant, PlayerEvent)>),
+    Bounded(Arc<BoundedQueue>),
+}
+
+impl PlayerEventChannel {
+    // None once the player is gone and all events have been received
+    pub async fn recv(&mut self) -> Option<PlayerEvent> {
+        self.recv_timestamped().await.map(|(_, event)| event)
+    }
+
+    // Same as `recv`, along with the time the player emitted the event. Events may
+    // wait in the channel for a while, so this is what their positions were measured at.
+    pub async fn recv_timestamped(&mut self) -> Option<(Instant, PlayerEvent)> {
+        match self {
+            Self::Unbounded(receiver) => receiver.recv().await,
+            Self::Bounded(queue) => loop {
+                if let Some(event) = queue.events.lock().pop_front() {
+                    return Some(event);
+                }
+                if queue.sender_closed.load(Ordering::Acquire) {
+                    // Events sent right before closing
+                    return queue.events.lock().pop_front();
+                }
+                queue.notify.notified().await;
+            },
+        }
+    }
+
+    pub fn blocking_recv(&mut self) -> Option<PlayerEvent> {
+        let received = match self {
+            Self::Unbounded(receiver) => receiver.blocking_recv(),
+            Self::Bounded(queue) => {
+                let mut events = queue.events.lock();
+                loop {
+                    if let Some(event) = events.pop_front() {
+                        break Some(event);
+                    }
+                    if queue.sender_closed.load(Ordering::Acquire) {
+                        break None;
+                    }
+                    queue.ready.wait(&mut events);
+                }
+            }
+        };
+        received.map(|(_, event)| event)
+    }
+
+    // None if no event is waiting
+    pub fn try_recv(&mut self) -> Option<PlayerEvent> {
+        let received = match self {
+            Self::Unbounded(receiver) => receiver.try_recv().ok(),
+            Self::Bounded(queue) => queue.events.lock().pop_front(),
+        };
+        received.map(|(_, event)| event)
+    }
+}
+
+impl Drop for PlayerEventChannel {
+    fn drop(&mut self) {
+        if let Self::Bounded(queue) = self {
+            queue.receiver_closed.store(true, Ordering::Release);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::SpotifyId;
+
+    fn position(position_ms: u32) -> PlayerEvent {
+        PlayerEvent::PositionCorrection {
+            play_request_id: 0,
+            track_id: SpotifyId::from_uri("spotify:track:4uLU6hMCjMI75M1A2tKUQC").unwrap(),
+            position_ms,
+        }
+    }
+
+    fn volume(volume: u16) -> PlayerEvent {
+        PlayerEvent::VolumeChanged { volume }
+    }
+
+    #[test]
+    fn full_channel_drops_oldest_droppable_event() {
+        let (sender, mut channel) = player_event_channel(Some(2));
+        sender.send(position(1)).unwrap();
+        sender.send(volume(1)).unwrap();
+        sender.send(position(2)).unwrap();
+        sender.send(volume(2)).unwrap();
+        // Nothing left to drop but the new event itself
+        sender.send(position(3)).unwrap();
+        // Other events are kept beyond the capacity
+        sender.send(volume(3)).unwrap();
+        drop(sender);
+
+        let mut received = vec![];
+        while let Some(event) = channel.blocking_recv() {
+            received.push(event);
+        }
+        assert!(matches!(
+            received.as_slice(),
+            [
+                PlayerEvent::VolumeChanged { volume: 1 },
+                PlayerEvent::VolumeChanged { volume: 2 },
+                PlayerEvent::VolumeChanged { volume: 3 },
+            ]
+        ));
+    }
+
+    #[test]
+    fn events_keep_their_emission_time() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        for capacity in [None, Some(1)] {
+            let (sender, mut channel) = player_event_channel(capacity);
+            let emitted_at = Instant::now();
+            sender.send_timestamped(emitted_at, volume(1)).unwrap();
+            sender.send(volume(2)).unwrap();
+            drop(sender);
+
+            let (first, event) = rt.block_on(channel.recv_timestamped()).unwrap();
+            assert_eq!(first, emitted_at);
+            assert!(matches!(event, PlayerEvent::VolumeChanged { volume: 1 }));
+            let (second, _) = rt.block_on(channel.recv_timestamped()).unwrap();
+            assert!(second >= first);
+            assert!(rt.block_on(channel.recv_timestamped()).is_none());
+        }
+    }
+
+    #[test]
+    fn send_fails_once_receiver_is_dropped() {
+        let (sender, channel) = player_event_channel(Some(1));
+        drop(channel);
+        assert!(sender.send(position(0)).is_err());
+    }
+}
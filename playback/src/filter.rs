@@ -0,0 +1,415 @@
+use std::{f64::consts::PI, time::Duration};
+
+use crate::{config::PlayerConfig, NUM_CHANNELS, SAMPLE_RATE};
+
+// Filters shape the decoded signal before normalisation and volume control.
+// Samples are interleaved, filters keep state per channel so that consecutive
+// packets are processed as one continuous stream.
+pub trait AudioFilter {
+    fn modify_stream(&mut self, data: &mut [f64]);
+}
+
+// One band of a parametric equaliser, boosting or cutting `gain_db` around
+// `frequency` (Hz). Higher `q` gives a narrower band.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EqBand {
+    pub frequency: f64,
+    pub gain_db: f64,
+    pub q: f64,
+}
+
+impl EqBand {
+    pub fn is_valid(&self) -> bool {
+        self.frequency > 0.0
+            && self.frequency < SAMPLE_RATE as f64 / 2.0
+            && self.q > 0.0
+            && self.gain_db.is_finite()
+    }
+}
+
+// Peaking biquad from the Audio EQ Cookbook (R. Bristow-Johnson), in
+// transposed direct form II.
+struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+    state: [[f64; 2]; NUM_CHANNELS as usize],
+}
+
+impl Biquad {
+    fn peaking(band: &EqBand) -> Self {
+        let a = 10f64.powf(band.gain_db / 40.0);
+        let w0 = 2.0 * PI * band.frequency / SAMPLE_RATE as f64;
+        let alpha = w0.sin() / (2.0 * band.q);
+        let cos_w0 = w0.cos();
+
+        let a0 = 1.0 + alpha / a;
+        Self {
+            b0: (1.0 + alpha * a) / a0,
+            b1: -2.0 * cos_w0 / a0,
+            b2: (1.0 - alpha * a) / a0,
+            a1: -2.0 * cos_w0 / a0,
+            a2: (1.0 - alpha / a) / a0,
+            state: [[0.0; 2]; NUM_CHANNELS as usize],
+        }
+    }
+
+    fn process(&mut self, channel: usize, input: f64) -> f64 {
+        let [z1, z2] = &mut self.state[channel];
+        let output = self.b0 * input + *z1;
+        *z1 = self.b1 * input - self.a1 * output + *z2;
+        *z2 = self.b2 * input - self.a2 * output;
+        output
+    }
+}
+
+pub struct ParametricEq {
+    bands: Vec<Biquad>,
+}
+
+impl ParametricEq {
+    pub fn new(bands: &[EqBand]) -> Self {
+        let bands = bands
+            .iter()
+            .filter(|band| {
+                let valid = band.is_valid();
+                if !valid {
+                    warn!("Ignoring invalid equaliser band: {:?}", band);
+                }
+                valid
+            })
+            .map(Biquad::peaking)
+            .collect();
+
+        Self { bands }
+    }
+
+    // None when no bands are configured, so the player can skip filtering
+    pub fn from_config(config: &PlayerConfig) -> Option<Self> {
+        if config.equalizer.is_empty() {
+            return None;
+        }
+        Some(Self::new(&config.equalizer))
+    }
+}
+
+impl AudioFilter for ParametricEq {
+    fn modify_stream(&mut self, data: &mut [f64]) {
+        for frame in data.chunks_exact_mut(NUM_CHANNELS as usize) {
+            for (channel, sample) in frame.iter_mut().enumerate() {
+                for band in self.bands.iter_mut() {
+                    *sample = band.process(channel, *sample);
+                }
+            }
+        }
+    }
+}
+
+// Channel balance, from -1.0 (left only) through 0.0 (centre) to 1.0 (right
+// only). The louder side is kept at full level.
+pub struct BalanceFilter {
+    balance: f64,
+}
+
+impl BalanceFilter {
+    pub fn new(balance: f64) -> Self {
+        Self {
+            balance: balance.clamp(-1.0, 1.0),
+        }
+    }
+
+    pub fn set_balance(&mut self, balance: f64) {
+        self.balance = balance.clamp(-1.0, 1.0);
+    }
+}
+
+impl AudioFilter for BalanceFilter {
+    fn modify_stream(&mut self, data: &mut [f64]) {
+        if self.balance == 0.0 {
+            return;
+        }
+
+        let left = (1.0 - self.balance).min(1.0);
+        let right = (1.0 + self.balance).min(1.0);
+        for frame in data.chunks_exact_mut(NUM_CHANNELS as usize) {
+            frame[0] *= left;
+            frame[1] *= right;
+        }
+    }
+}
+
+// Lowers the level while ducked, e.g. while a voice assistant speaks over the music.
+// The gain changes linearly over the ramp time, so ducking doesn't click.
+pub struct DuckFilter {
+    ducked_gain: f64,
+    // gain change per frame
+    step: f64,
+    gain: f64,
+    target: f64,
+}
+
+impl DuckFilter {
+    pub fn new(attenuation_db: f64, ramp: Duration) -> Self {
+        let ducked_gain = 10f64.powf(-attenuation_db.abs() / 20.0);
+        let frames = (ramp.as_secs_f64() * SAMPLE_RATE as f64).max(1.0);
+        Self {
+            ducked_gain,
+            step: (1.0 - ducked_gain) / frames,
+            gain: 1.0,
+            target: 1.0,
+        }
+    }
+
+    pub fn from_config(config: &PlayerConfig) -> Self {
+        Self::new(config.duck_attenuation_db, config.duck_ramp)
+    }
+
+    pub fn set_ducked(&mut self, ducked: bool) {
+        self.target = if ducked { self.ducked_gain } else { 1.0 };
+    }
+}
+
+impl AudioFilter for DuckFilter {
+    fn modify_stream(&mut self, data: &mut [f64]) {
+        if self.gain == 1.0 && self.target == 1.0 {
+            return;
+        }
+
+        for frame in data.chunks_exact_mut(NUM_CHANNELS as usize) {
+            if self.gain < self.target {
+                self.gain = (self.gain + self.step).min(self.target);
+            } else if self.gain > self.target {
+                self.gain = (self.gain - self.step).max(self.target);
+            }
+            for sample in frame.iter_mut() {
+                *sample *= self.gain;
+            }
+        }
+    }
+}
+
+// Fades to silence over the fade time, e.g. so stopping doesn't click. Everything
+// after the fade is silent.
+pub struct FadeOut {
+    // gain change per frame
+    step: f64,
+    gain: f64,
+}
+
+impl FadeOut {
+    pub fn new(fade: Duration) -> Self {
+        let frames = (fade.as_secs_f64() * SAMPLE_RATE as f64).round().max(1.0);
+        Self {
+            step: 1.0 / frames,
+            gain: 1.0,
+        }
+    }
+
+    pub fn is_silent(&self) -> bool {
+        self.gain <= 0.0
+    }
+}
+
+impl AudioFilter for FadeOut {
+    fn modify_stream(&mut self, data: &mut [f64]) {
+        for frame in data.chunks_exact_mut(NUM_CHANNELS as usize) {
+            self.gain = (self.gain - self.step).max(0.0);
+            for sample in frame.iter_mut() {
+                *sample *= self.gain;
+            }
+        }
+    }
+}
+
+// Peak and RMS level per channel for level meters. Not a filter, the samples are
+// only read, and levels are reported once every interval of audio rather than
+// for every packet.
+pub struct LevelMeter {
+    interval_frames: usize,
+    frames: usize,
+    peak: [f64; NUM_CHANNELS as usize],
+    sum_squares: [f64; NUM_CHANNELS as usize],
+}
+
+impl LevelMeter {
+    pub fn new(interval: Duration) -> Self {
+        let interval_frames = (interval.as_secs_f64() * SAMPLE_RATE as f64).round();
+        Self {
+            interval_frames: interval_frames.max(1.0) as usize,
+            frames: 0,
+            peak: [0.0; NUM_CHANNELS as usize],
+            sum_squares: [0.0; NUM_CHANNELS as usize],
+        }
+    }
+
+    // Peak and RMS levels since the last report, once a full interval was measured
+    pub fn measure(&mut self, data: &[f64]) -> Option<(Vec<f64>, Vec<f64>)> {
+        for frame in data.chunks_exact(NUM_CHANNELS as usize) {
+            for (channel, sample) in frame.iter().enumerate() {
+                self.peak[channel] = self.peak[channel].max(sample.abs());
+                self.sum_squares[channel] += sample * sample;
+            }
+        }
+        self.frames += data.len() / NUM_CHANNELS as usize;
+
+        if self.frames < self.interval_frames {
+            return None;
+        }
+
+        let frames = self.frames as f64;
+        let peak = self.peak.to_vec();
+        let rms = self.sum_squares.iter().map(|sum| (sum / frames).sqrt());
+        let levels = (peak, rms.collect());
+
+        self.frames = 0;
+        self.peak = [0.0; NUM_CHANNELS as usize];
+        self.sum_squares = [0.0; NUM_CHANNELS as usize];
+        Some(levels)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RESPONSE_LEN: usize = 16384;
+
+    // Impulse response of the left channel, with the right channel kept silent
+    fn impulse_response(eq: &mut ParametricEq) -> (Vec<f64>, Vec<f64>) {
+        let mut data = vec![0.0; RESPONSE_LEN * NUM_CHANNELS as usize];
+        data[0] = 1.0;
+        eq.modify_stream(&mut data);
+
+        let left = data.iter().step_by(2).copied().collect();
+        let right = data.iter().skip(1).step_by(2).copied().collect();
+        (left, right)
+    }
+
+    // Magnitude of the frequency response at `frequency`, from the impulse response
+    fn magnitude_at(response: &[f64], frequency: f64) -> f64 {
+        let w = 2.0 * PI * frequency / SAMPLE_RATE as f64;
+        let (re, im) = response
+            .iter()
+            .enumerate()
+            .fold((0.0, 0.0), |(re, im), (n, h)| {
+                (re + h * (w * n as f64).cos(), im - h * (w * n as f64).sin())
+            });
+        (re * re + im * im).sqrt()
+    }
+
+    #[test]
+    fn flat_band_passes_impulse_unchanged() {
+        let mut eq = ParametricEq::new(&[EqBand {
+            frequency: 1000.0,
+            gain_db: 0.0,
+            q: 1.0,
+        }]);
+        let (left, right) = impulse_response(&mut eq);
+
+        assert!((left[0] - 1.0).abs() < 1e-12);
+        assert!(left[1..].iter().all(|h| h.abs() < 1e-12));
+        assert!(right.iter().all(|h| *h == 0.0));
+    }
+
+    #[test]
+    fn peaking_band_impulse_response() {
+        let mut eq = ParametricEq::new(&[EqBand {
+            frequency: 1000.0,
+            gain_db: 6.0,
+            q: 1.0,
+        }]);
+        let (left, right) = impulse_response(&mut eq);
+
+        // Full gain at the centre frequency, unity far away from it
+        let centre = magnitude_at(&left, 1000.0);
+        assert!((centre - 10f64.powf(6.0 / 20.0)).abs() < 1e-3);
+        assert!((magnitude_at(&left, 0.0) - 1.0).abs() < 1e-3);
+        assert!((magnitude_at(&left, 20000.0) - 1.0).abs() < 1e-2);
+
+        // Channels are filtered independently
+        assert!(right.iter().all(|h| *h == 0.0));
+    }
+
+    #[test]
+    fn full_left_balance_silences_right() {
+        let mut balance = BalanceFilter::new(-1.0);
+        let mut data = [0.5, 0.5, -0.25, -0.25];
+        balance.modify_stream(&mut data);
+        assert_eq!(data, [0.5, 0.0, -0.25, 0.0]);
+
+        balance.set_balance(0.5);
+        balance.modify_stream(&mut data);
+        assert_eq!(data, [0.25, 0.0, -0.125, 0.0]);
+    }
+
+    #[test]
+    fn duck_ramps_down_and_back() {
+        // 20 dB over 10 frames
+        let ramp = Duration::from_secs_f64(10.0 / SAMPLE_RATE as f64);
+        let mut duck = DuckFilter::new(20.0, ramp);
+        let frames = |duck: &mut DuckFilter, n: usize| {
+            let mut data = vec![1.0; n * NUM_CHANNELS as usize];
+            duck.modify_stream(&mut data);
+            data
+        };
+
+        duck.set_ducked(true);
+        let data = frames(&mut duck, 20);
+        assert!(data[0] < 1.0 && data[0] > 0.9);
+        assert!(data.windows(2).all(|w| w[1] <= w[0]));
+        assert!((data[data.len() - 1] - 0.1).abs() < 1e-9);
+
+        duck.set_ducked(false);
+        let data = frames(&mut duck, 20);
+        assert!(data[0] > 0.1);
+        assert_eq!(data[data.len() - 1], 1.0);
+        assert_eq!(frames(&mut duck, 1), [1.0, 1.0]);
+    }
+
+    #[test]
+    fn invalid_bands_are_ignored() {
+        let eq = ParametricEq::new(&[
+            EqBand {
+                frequency: 30000.0,
+                gain_db: 3.0,
+                q: 1.0,
+            },
+            EqBand {
+                frequency: 100.0,
+                gain_db: 3.0,
+                q: 0.0,
+            },
+        ]);
+        assert!(eq.bands.is_empty());
+    }
+
+    #[test]
+    fn level_meter_reports_once_per_interval() {
+        // 4 frames per report
+        let mut meter = LevelMeter::new(Duration::from_secs_f64(4.0 / SAMPLE_RATE as f64));
+        assert_eq!(meter.measure(&[0.5, 0.0, -0.5, 0.0]), None);
+
+        let (peak, rms) = meter.measure(&[0.5, 0.0, -1.0, 0.0]).unwrap();
+        assert_eq!(peak, [1.0, 0.0]);
+        assert!((rms[0] - (1.75f64 / 4.0).sqrt()).abs() < 1e-12);
+        assert_eq!(rms[1], 0.0);
+
+        // Starts over after a report
+        assert_eq!(meter.measure(&[0.25, 0.25]), None);
+    }
+
+    #[test]
+    fn fade_out_ramps_to_silence() {
+        // Silent after 4 frames
+        let mut fade = FadeOut::new(Duration::from_secs_f64(4.0 / SAMPLE_RATE as f64));
+        let mut data = vec![1.0; 6 * NUM_CHANNELS as usize];
+        fade.modify_stream(&mut data);
+        assert_eq!(
+            data,
+            [0.75, 0.75, 0.5, 0.5, 0.25, 0.25, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0]
+        );
+        assert!(fade.is_silent());
+    }
+}
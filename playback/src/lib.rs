@@ -10,6 +10,8 @@ pub mod config;
 pub mod convert;
 pub mod decoder;
 pub mod dither;
+pub mod event_channel;
+pub mod filter;
 pub mod mixer;
 pub mod player;
 
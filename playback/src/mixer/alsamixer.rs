@@ -1,7 +1,7 @@
 use crate::player::{db_to_ratio, ratio_to_db};
 
 use super::mappings::{LogMapping, MappedCtrl, VolumeMapping};
-use super::{Mixer, MixerConfig, VolumeCtrl};
+use super::{Mixer, MixerConfig, VolumeCallback, VolumeCtrl, VolumeNotifier};
 
 use alsa::ctl::{ElemId, ElemIface};
 use alsa::mixer::{MilliBel, SelemChannelId, SelemId};
@@ -22,6 +22,7 @@ pub struct AlsaMixer {
     has_switch: bool,
     is_softvol: bool,
     use_linear_in_db: bool,
+    volume_notifier: VolumeNotifier,
 }
 
 // min_db cannot be depended on to be mute. Also note that contrary to
@@ -161,6 +162,7 @@ impl Mixer for AlsaMixer {
             has_switch,
             is_softvol,
             use_linear_in_db,
+            volume_notifier: VolumeNotifier::default(),
         }
     }
 
@@ -205,6 +207,10 @@ impl Mixer for AlsaMixer {
     }
 
     fn set_volume(&self, volume: u16) {
+        if volume != self.volume() {
+            self.volume_notifier.notify(volume);
+        }
+
         let mixer =
             alsa::mixer::Mixer::new(&self.config.device, false).expect("Could not open Alsa mixer");
         let simple_element = mixer
@@ -260,6 +266,10 @@ impl Mixer for AlsaMixer {
             .set_playback_db_all(MilliBel::from_db(db_volume as f32), Round::Floor)
             .expect("Could not set Alsa dB volume");
     }
+
+    fn set_volume_callback(&self, callback: Option<VolumeCallback>) {
+        self.volume_notifier.set(callback);
+    }
 }
 
 impl AlsaMixer {
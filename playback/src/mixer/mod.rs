@@ -1,5 +1,7 @@
 use std::sync::Arc;
 
+use parking_lot::Mutex;
+
 use crate::config::VolumeCtrl;
 
 pub mod mappings;
@@ -7,6 +9,9 @@ use self::mappings::MappedCtrl;
 
 pub struct NoOpVolume;
 
+// Called with the new volume whenever the volume of a mixer is changed
+pub type VolumeCallback = Box<dyn Fn(u16) + Send>;
+
 pub trait Mixer: Send + Sync {
     fn open(config: MixerConfig) -> Self
     where
@@ -18,6 +23,25 @@ pub trait Mixer: Send + Sync {
     fn get_soft_volume(&self) -> Box<dyn VolumeGetter + Send> {
         Box::new(NoOpVolume)
     }
+
+    // Replaces the volume callback. Mixers that don't support it never call it.
+    fn set_volume_callback(&self, _callback: Option<VolumeCallback>) {}
+}
+
+// Volume callback of a mixer, shared between its clones
+#[derive(Clone, Default)]
+pub struct VolumeNotifier(Arc<Mutex<Option<VolumeCallback>>>);
+
+impl VolumeNotifier {
+    pub fn set(&self, callback: Option<VolumeCallback>) {
+        *self.0.lock() = callback;
+    }
+
+    pub fn notify(&self, volume: u16) {
+        if let Some(ref callback) = *self.0.lock() {
+            callback(volume);
+        }
+    }
 }
 
 pub trait VolumeGetter {
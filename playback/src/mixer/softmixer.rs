@@ -1,9 +1,9 @@
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 
-use super::VolumeGetter;
 use super::{MappedCtrl, VolumeCtrl};
 use super::{Mixer, MixerConfig};
+use super::{VolumeCallback, VolumeGetter, VolumeNotifier};
 
 #[derive(Clone)]
 pub struct SoftMixer {
@@ -11,6 +11,7 @@ pub struct SoftMixer {
     // It's much faster than a Mutex<f64>.
     volume: Arc<AtomicU64>,
     volume_ctrl: VolumeCtrl,
+    volume_notifier: VolumeNotifier,
 }
 
 impl Mixer for SoftMixer {
@@ -21,6 +22,7 @@ impl Mixer for SoftMixer {
         Self {
             volume: Arc::new(AtomicU64::new(f64::to_bits(0.5))),
             volume_ctrl,
+            volume_notifier: VolumeNotifier::default(),
         }
     }
 
@@ -31,13 +33,19 @@ impl Mixer for SoftMixer {
 
     fn set_volume(&self, volume: u16) {
         let mapped_volume = self.volume_ctrl.to_mapped(volume);
-        self.volume
-            .store(mapped_volume.to_bits(), Ordering::Relaxed)
+        let previous = self.volume.swap(mapped_volume.to_bits(), Ordering::Relaxed);
+        if previous != mapped_volume.to_bits() {
+            self.volume_notifier.notify(volume);
+        }
     }
 
     fn get_soft_volume(&self) -> Box<dyn VolumeGetter + Send> {
         Box::new(SoftVolume(self.volume.clone()))
     }
+
+    fn set_volume_callback(&self, callback: Option<VolumeCallback>) {
+        self.volume_notifier.set(callback);
+    }
 }
 
 impl SoftMixer {
@@ -1,11 +1,10 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, VecDeque},
     fmt,
     future::Future,
     io::{self, Read, Seek, SeekFrom},
     mem,
     pin::Pin,
-    process::exit,
     sync::{
         atomic::{AtomicUsize, Ordering},
         Arc,
@@ -16,8 +15,7 @@ use std::{
 };
 
 use futures_util::{
-    future, future::FusedFuture, stream::futures_unordered::FuturesUnordered, StreamExt,
-    TryFutureExt,
+    future, future::FusedFuture, stream::futures_unordered::FuturesUnordered, FutureExt, StreamExt,
 };
 use parking_lot::Mutex;
 use symphonia::core::io::MediaSource;
@@ -29,20 +27,49 @@ use crate::{
         READ_AHEAD_DURING_PLAYBACK,
     },
     audio_backend::Sink,
-    config::{Bitrate, NormalisationMethod, NormalisationType, PlayerConfig},
+    config::{
+        AudioFormat, Bitrate, NormalisationMethod, NormalisationType, PlayerConfig,
+        QueueEndBehavior,
+    },
     convert::Converter,
-    core::{util::SeqGenerator, Error, Session, SpotifyId},
-    decoder::{AudioDecoder, AudioPacket, AudioPacketPosition, SymphoniaDecoder},
-    metadata::audio::{AudioFileFormat, AudioFiles, AudioItem},
+    core::{util::SeqGenerator, Error, FileId, Session, SpotifyId},
+    decoder::{AudioDecoder, AudioPacket, AudioPacketPosition, DecoderError, SymphoniaDecoder},
+    dither::DithererBuilder,
+    event_channel::{player_event_channel, PlayerEventSender},
+    filter::{AudioFilter, BalanceFilter, DuckFilter, FadeOut, LevelMeter, ParametricEq},
+    metadata::{
+        audio::{AudioFileFormat, AudioFiles, AudioItem},
+        availability::UnavailabilityReason,
+    },
     mixer::VolumeGetter,
 };
 
 #[cfg(feature = "passthrough-decoder")]
 use crate::decoder::PassthroughDecoder;
 
-use crate::SAMPLES_PER_SECOND;
-
-const PRELOAD_NEXT_TRACK_BEFORE_END_DURATION_MS: u32 = 30000;
+use crate::{NUM_CHANNELS, SAMPLES_PER_SECOND};
+
+// Gain reduction below this is inaudible and treated as the limiter being released.
+const LIMITER_ACTIVE_THRESHOLD_DB: f64 = 0.01;
+// Wait before the first retry of a failed sink write, doubled for every further retry.
+const SINK_WRITE_RETRY_DELAY: Duration = Duration::from_millis(20);
+// Time a track that ended without a next one waits for a load before on_queue_end applies.
+const QUEUE_END_DELAY: Duration = Duration::from_millis(500);
+// Number of files whose normalisation data is kept, so that replaying a track doesn't
+// read its header again.
+const NORMALISATION_CACHE_SIZE: usize = 32;
+// Minimum time between BufferProgress events while the current track is downloading.
+const BUFFER_PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+// Audio covered by each Levels event, about 20 per second.
+const LEVELS_INTERVAL: Duration = Duration::from_millis(50);
+// Time covered by each DecodeStats event.
+const DECODE_STATS_INTERVAL: Duration = Duration::from_secs(5);
+// Time between PositionCheckpoint events while playing. Pausing and stopping send one
+// right away.
+const POSITION_CHECKPOINT_INTERVAL: Duration = Duration::from_secs(5);
+// Most that Peak normalisation raises a quiet track by, so that it doesn't bring up
+// the noise floor of near silent ones.
+const PEAK_NORMALISATION_MAX_GAIN_DB: f64 = 12.0;
 pub const DB_VOLTAGE_RATIO: f64 = 20.0;
 pub const PCM_AT_0DBFS: f64 = 1.0;
 
@@ -55,6 +82,7 @@ pub type PlayerResult = Result<(), Error>;
 pub struct Player {
     commands: Option<mpsc::UnboundedSender<PlayerCommand>>,
     thread_handle: Option<thread::JoinHandle<()>>,
+    event_channel_capacity: Option<usize>,
 }
 
 #[derive(PartialEq, Eq, Debug, Clone, Copy)]
@@ -64,21 +92,110 @@ pub enum SinkStatus {
     TemporarilyClosed,
 }
 
+impl SinkStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Running => "running",
+            Self::TemporarilyClosed => "temporarily_closed",
+            Self::Closed => "closed",
+        }
+    }
+}
+
+// Why a track could not be loaded, reported with PlayerEvent::Unavailable
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum UnavailableReason {
+    // Restricted in the user's country
+    Region,
+    // Not released yet
+    Embargo,
+    // No playable file or alternative, e.g. removed from the catalogue
+    Removed,
+    // Loading took longer than the configured timeout
+    Timeout,
+    // Fetching or decoding the file failed
+    LoadFailed,
+}
+
+impl UnavailableReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Region => "region",
+            Self::Embargo => "embargo",
+            Self::Removed => "removed",
+            Self::Timeout => "timeout",
+            Self::LoadFailed => "load_failed",
+        }
+    }
+}
+
+impl From<UnavailabilityReason> for UnavailableReason {
+    fn from(reason: UnavailabilityReason) -> Self {
+        match reason {
+            UnavailabilityReason::Blacklisted | UnavailabilityReason::NotWhitelisted => {
+                Self::Region
+            }
+            UnavailabilityReason::Embargo => Self::Embargo,
+            UnavailabilityReason::NoData => Self::Removed,
+        }
+    }
+}
+
 pub type SinkEventCallback = Box<dyn Fn(SinkStatus) + Send>;
 
+// Called with the stream position in ms of every packet written to the sink.
+pub type ClockCallback = Box<dyn Fn(u64) + Send>;
+
 struct PlayerInternal {
     session: Session,
     config: PlayerConfig,
     commands: mpsc::UnboundedReceiver<PlayerCommand>,
     load_handles: Arc<Mutex<HashMap<thread::ThreadId, thread::JoinHandle<()>>>>,
+    normalisation_cache: Arc<Mutex<NormalisationCache>>,
 
     state: PlayerState,
     preload: PlayerPreload,
     sink: Box<dyn Sink>,
     sink_status: SinkStatus,
     sink_event_callback: Option<SinkEventCallback>,
+    clock_callback: Option<ClockCallback>,
+    output_format_reported: bool,
+    // Set by handle_fatal_error, ends the poll loop
+    failed: bool,
+    // (from, to) of a gapless transition that has started but not finished
+    transition: Option<(SpotifyId, SpotifyId)>,
+    // Seek requested while loading, applied once the track is loaded
+    pending_seek_ms: Option<u32>,
+    // Packet the sink failed to write, holds back playback until written again
+    sink_retry: Option<SinkRetry>,
+    // Play request id of a track that ended the queue, and when on_queue_end applies to it
+    queue_end: Option<(u64, Pin<Box<tokio::time::Sleep>>)>,
+    // Replaces the parsed normalisation gain of the current track, cleared on the next one
+    gain_override_db: Option<f64>,
+    // When the download progress of the current track was last reported, and what it was
+    buffer_progress_reported: Option<(Instant, f64)>,
+    // When the last PositionCheckpoint of the current track was sent
+    position_checkpoint_sent: Option<Instant>,
+    // Bitrate the next tracks load with instead of the configured one, the recent stalls
+    // of the current track and the last stall of any track, see
+    // `PlayerConfig::adaptive_bitrate`
+    reduced_bitrate: Option<Bitrate>,
+    stalls: VecDeque<Instant>,
+    last_stall: Option<Instant>,
+    // Decoder timing since the last DecodeStats event
+    decode_stats: DecodeStats,
+    // Last volume and Stopped event sent, so that resync can repeat them
+    reported_volume: Option<u16>,
+    stopped_track: Option<(SpotifyId, u64)>,
+    // First track of the current queue, where `QueueEndBehavior::RepeatAll` starts over
+    queue_start: Option<SpotifyId>,
     volume_getter: Box<dyn VolumeGetter + Send>,
-    event_senders: Vec<mpsc::UnboundedSender<PlayerEvent>>,
+    audio_filter: Option<Box<dyn AudioFilter + Send>>,
+    balance_filter: BalanceFilter,
+    duck_filter: DuckFilter,
+    // Only measures while someone asked for levels
+    level_meter: Option<LevelMeter>,
+    event_fanout: EventFanout,
     converter: Converter,
 
     normalisation_integrator: f64,
@@ -105,11 +222,28 @@ enum PlayerCommand {
     Pause,
     Stop,
     Seek(u32),
+    Restart,
     SetSession(Session),
-    AddEventSender(mpsc::UnboundedSender<PlayerEvent>),
+    AddEventSender(PlayerEventSender),
     SetSinkEventCallback(Option<SinkEventCallback>),
+    RegisterClock(ClockCallback),
+    GetState(oneshot::Sender<PlayerStateSnapshot>),
+    GetTimeUntilPreload(oneshot::Sender<Option<Duration>>),
+    GetConfig(oneshot::Sender<PlayerConfig>),
+    GetSubscriberCount(oneshot::Sender<usize>),
     EmitVolumeChangedEvent(u16),
+    EmitMixerVolumeChangedEvent(u16),
     SetAutoNormaliseAsAlbum(bool),
+    SetGapless(bool),
+    SetPreloadLeadTime(Duration),
+    SetBalance(f64),
+    SetDuck(bool),
+    SetLevels(bool),
+    SetGainOverride(Option<f64>),
+    SetNormalisationMethod(NormalisationMethod),
+    SetDitherer(Option<DithererBuilder>),
+    ResetLimiter,
+    ResyncState,
     EmitSessionDisconnectedEvent {
         connection_id: String,
         user_name: String,
@@ -128,6 +262,7 @@ enum PlayerCommand {
     EmitShuffleChangedEvent(bool),
     EmitRepeatChangedEvent(bool),
     EmitAutoPlayChangedEvent(bool),
+    EmitPlaybackCompleteEvent(String),
 }
 
 #[derive(Debug, Clone)]
@@ -185,6 +320,7 @@ pub enum PlayerEvent {
     Unavailable {
         play_request_id: u64,
         track_id: SpotifyId,
+        reason: UnavailableReason,
     },
     // The mixer volume was set to a new level.
     VolumeChanged {
@@ -202,6 +338,8 @@ pub enum PlayerEvent {
     },
     TrackChanged {
         audio_item: Box<AudioItem>,
+        // Where playback of the track starts, non-zero when resuming
+        position_ms: u32,
     },
     SessionConnected {
         connection_id: String,
@@ -226,12 +364,129 @@ pub enum PlayerEvent {
     AutoPlayChanged {
         auto_play: bool,
     },
+    // The last track of the context ended and playback doesn't continue, unlike
+    // EndOfTrack which is sent for every track. Spirc loads the first track again,
+    // paused, after this.
+    PlaybackComplete {
+        context_uri: String,
+    },
     FilterExplicitContentChanged {
         filter: bool,
     },
+    // The current track was skipped because the user doesn't allow explicit content.
+    // Followed by EndOfTrack for the same track.
+    ExplicitContentSkipped {
+        track_id: SpotifyId,
+    },
+    // The audio key of the track could not be fetched, so it plays without decryption.
+    // Sent when playback starts. An encrypted file then fails to decode or plays as noise.
+    AudioKeyUnavailable {
+        play_request_id: u64,
+        track_id: SpotifyId,
+    },
+    // The format samples were delivered to the sink in, sent once per track after
+    // its first packet was written. `format` is None when samples were not
+    // converted, i.e. F64 output or passthrough.
+    OutputFormat {
+        track_id: SpotifyId,
+        format: Option<AudioFormat>,
+        ditherer: Option<&'static str>,
+    },
+    // The sink was started or stopped, i.e. the audio device was opened or closed.
+    SinkStatusChanged {
+        status: SinkStatus,
+    },
+    // The current track ended while the next one is preloaded, so playback is about to
+    // continue gaplessly. Followed by TransitionFinished once the next track plays,
+    // unless a different track is loaded instead.
+    Transitioning {
+        from_track_id: SpotifyId,
+        to_track_id: SpotifyId,
+    },
+    TransitionFinished {
+        from_track_id: SpotifyId,
+        to_track_id: SpotifyId,
+    },
+    // The sink couldn't be started, or writing to it kept failing after all retries.
+    // Playback was paused.
+    SinkError {
+        message: String,
+    },
+    // The player ran into an error it can't recover from. Playback is stopped and
+    // the player shuts down, further commands are ignored.
+    FatalError {
+        message: String,
+    },
+    // An internal error left the player in an invalid state. Playback was stopped and
+    // the player reset, it keeps running and accepts further commands.
+    StateReset {
+        message: String,
+    },
+    // Download progress of the current track, sent periodically until it is fully
+    // downloaded.
+    BufferProgress {
+        play_request_id: u64,
+        track_id: SpotifyId,
+        // Fraction of the file that has been downloaded, from 0.0 to 1.0.
+        fraction: f64,
+        // Everything from the current read position to the end is downloaded.
+        range_to_end_available: bool,
+    },
+    // The dynamic limiter engaged or released.
+    LimiterState {
+        active: bool,
+        // Current gain reduction in dB.
+        strength_db: f64,
+        // Current gain reduction as a ratio applied to the samples.
+        factor: f64,
+    },
+    // Peak and RMS level of the output per channel, from 0.0 to 1.0, sent about 20
+    // times per second while playing and enabled with `Player::set_levels`.
+    Levels {
+        peak: Vec<f64>,
+        rms: Vec<f64>,
+    },
+    // Position to resume the current track from after a restart. Sent when a track
+    // starts playing, every 5 seconds while playing, and when it's paused or stopped.
+    PositionCheckpoint {
+        play_request_id: u64,
+        track_id: SpotifyId,
+        position_ms: u32,
+    },
+    // The current track kept stalling, the next tracks load at a lower bitrate. Enabled
+    // with `PlayerConfig::adaptive_bitrate`.
+    QualityReduced {
+        play_request_id: u64,
+        track_id: SpotifyId,
+        bitrate: Bitrate,
+    },
+    // Decoder performance over the last few seconds of playback, sent periodically
+    // while decoding.
+    DecodeStats {
+        // Average time spent in the decoder per packet, in microseconds.
+        avg_decode_us: u64,
+        // Packets decoded since the previous event.
+        packets: u64,
+        // Packets the decoder reported as skipped since the previous event.
+        skipped: u64,
+    },
 }
 
 impl PlayerEvent {
+    // Events that only report a value, superseded by the next event of the same kind.
+    // Bounded event channels drop these first when a subscriber falls behind, all
+    // other events are always delivered.
+    pub fn is_droppable(&self) -> bool {
+        matches!(
+            self,
+            PlayerEvent::PositionCorrection { .. }
+                | PlayerEvent::BufferProgress { .. }
+                | PlayerEvent::LimiterState { .. }
+                | PlayerEvent::Levels { .. }
+                | PlayerEvent::DecodeStats { .. }
+        )
+    }
+
     pub fn get_play_request_id(&self) -> Option<u64> {
         use PlayerEvent::*;
         match self {
@@ -261,13 +516,157 @@ impl PlayerEvent {
             }
             | Seeked {
                 play_request_id, ..
+            }
+            | AudioKeyUnavailable {
+                play_request_id, ..
+            }
+            | BufferProgress {
+                play_request_id, ..
+            }
+            | PositionCheckpoint {
+                play_request_id, ..
+            }
+            | QualityReduced {
+                play_request_id, ..
             } => Some(*play_request_id),
             _ => None,
         }
     }
 }
 
-pub type PlayerEventChannel = mpsc::UnboundedReceiver<PlayerEvent>;
+pub use crate::event_channel::PlayerEventChannel;
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum PlaybackStatus {
+    #[default]
+    Stopped,
+    Loading,
+    Playing,
+    Paused,
+    EndOfTrack,
+}
+
+// What the player is doing at the moment it handles a `get_state` request.
+#[derive(Debug, Clone, Default)]
+pub struct PlayerStateSnapshot {
+    pub status: PlaybackStatus,
+    pub track_id: Option<SpotifyId>,
+    pub position_ms: u32,
+    pub duration_ms: u32,
+    pub is_explicit: bool,
+    // Whether the current track was decrypted with its audio key
+    pub decrypted: bool,
+    // Track whose file is played, an alternative of `track_id` if that is unavailable,
+    // e.g. in the user's region
+    pub source_track_id: Option<SpotifyId>,
+    // Alternatives looked up to find it, zero when `track_id` itself is played
+    pub alternatives_evaluated: usize,
+    pub normalisation_data: Option<NormalisationData>,
+    // Name of the ditherer used when converting to integer formats
+    pub ditherer: Option<&'static str>,
+    pub normalisation_method: NormalisationMethod,
+    // Fraction of the current track that has been downloaded
+    pub buffered: f64,
+    pub range_to_end_available: bool,
+}
+
+// Fails with Timeout when `load` takes longer than `timeout`. The poll loop then sees the
+// loader fail and reports the track as unavailable.
+async fn with_load_timeout<T>(
+    load: impl Future<Output = Result<T, UnavailableReason>>,
+    timeout: Duration,
+) -> Result<T, UnavailableReason> {
+    match tokio::time::timeout(timeout, load).await {
+        Ok(data) => data,
+        Err(_) => {
+            error!("Loading track timed out after {:?}", timeout);
+            Err(UnavailableReason::Timeout)
+        }
+    }
+}
+
+// A packet to write again once `backoff` has passed, after the sink failed to write it
+struct SinkRetry {
+    play_request_id: u64,
+    position_ms: u32,
+    packet: AudioPacket,
+    retries_left: u32,
+    delay: Duration,
+    backoff: Pin<Box<tokio::time::Sleep>>,
+}
+
+// Packets decoded and time spent decoding them since `since`
+struct DecodeStats {
+    packets: u64,
+    skipped: u64,
+    decode_time: Duration,
+    since: Instant,
+}
+
+impl DecodeStats {
+    fn new() -> Self {
+        Self {
+            packets: 0,
+            skipped: 0,
+            decode_time: Duration::ZERO,
+            since: Instant::now(),
+        }
+    }
+
+    fn record(&mut self, decode_time: Duration, skipped: bool) {
+        self.packets += 1;
+        self.skipped += skipped as u64;
+        self.decode_time += decode_time;
+    }
+
+    // Event for everything recorded so far, None if nothing was decoded. Starts over
+    // either way.
+    fn take(&mut self) -> Option<PlayerEvent> {
+        let stats = std::mem::replace(self, Self::new());
+        if stats.packets == 0 {
+            return None;
+        }
+        Some(PlayerEvent::DecodeStats {
+            avg_decode_us: stats.decode_time.as_micros() as u64 / stats.packets,
+            packets: stats.packets,
+            skipped: stats.skipped,
+        })
+    }
+}
+
+// Fraction of a file that has been downloaded, cached files are complete.
+fn buffer_progress(stream_loader_controller: &StreamLoaderController) -> f64 {
+    if stream_loader_controller.is_empty() {
+        return 1.0;
+    }
+    stream_loader_controller.downloaded_len() as f64 / stream_loader_controller.len() as f64
+}
+
+// Time until the end of the track comes within the preload lead time
+fn time_until_preload(duration_ms: u32, position_ms: u32, lead_time: Duration) -> Duration {
+    let remaining = Duration::from_millis(duration_ms.saturating_sub(position_ms) as u64);
+    remaining.saturating_sub(lead_time)
+}
+
+// Drops the packets starting within `trim_end_ms` of the end of the track, so
+// trailing padding doesn't delay the next one
+fn trim_end(
+    packet: Option<(AudioPacketPosition, AudioPacket)>,
+    duration_ms: u32,
+    trim_end_ms: u32,
+) -> Option<(AudioPacketPosition, AudioPacket)> {
+    packet.filter(|(position, _)| {
+        trim_end_ms == 0 || position.position_ms.saturating_add(trim_end_ms) < duration_ms
+    })
+}
+
+// Replaces both channels of interleaved stereo samples with their average.
+fn downmix_to_mono(samples: &mut [f64]) {
+    for frame in samples.chunks_exact_mut(NUM_CHANNELS as usize) {
+        let mono = frame.iter().sum::<f64>() / NUM_CHANNELS as f64;
+        frame.fill(mono);
+    }
+}
 
 pub fn db_to_ratio(db: f64) -> f64 {
     f64::powf(10.0, db / DB_VOLTAGE_RATIO)
@@ -293,6 +692,8 @@ pub struct NormalisationData {
     pub track_peak: f64,
     pub album_gain_db: f64,
     pub album_peak: f64,
+    // The header bytes the values were parsed from, kept for diagnostics.
+    pub raw: Option<[u8; NormalisationData::SIZE]>,
 }
 
 impl Default for NormalisationData {
@@ -302,41 +703,88 @@ impl Default for NormalisationData {
             track_peak: 1.0,
             album_gain_db: 0.0,
             album_peak: 1.0,
+            raw: None,
         }
     }
 }
 
 impl NormalisationData {
-    fn parse_from_ogg<T: Read + Seek>(mut file: T) -> io::Result<NormalisationData> {
-        const SPOTIFY_NORMALIZATION_HEADER_START_OFFSET: u64 = 144;
-        const NORMALISATION_DATA_SIZE: usize = 16;
-
-        let newpos = file.seek(SeekFrom::Start(SPOTIFY_NORMALIZATION_HEADER_START_OFFSET))?;
-        if newpos != SPOTIFY_NORMALIZATION_HEADER_START_OFFSET {
-            error!(
-                "NormalisationData::parse_from_file seeking to {} but position is now {}",
-                SPOTIFY_NORMALIZATION_HEADER_START_OFFSET, newpos
-            );
+    pub const SIZE: usize = 16;
 
-            error!("Falling back to default (non-track and non-album) normalisation data.");
+    // For tracks without normalisation data. Neutral gains like the defaults, but
+    // the peak levels are unknown, so Peak normalisation leaves the track as is.
+    pub(crate) fn unknown() -> Self {
+        Self {
+            track_peak: 0.0,
+            album_peak: 0.0,
+            ..Self::default()
+        }
+    }
 
-            return Ok(NormalisationData::default());
+    fn parse_from_ogg<T: Read + Seek>(mut file: T) -> io::Result<NormalisationData> {
+        const SPOTIFY_NORMALIZATION_HEADER_START_OFFSET: usize = 144;
+        // Bytes before and after the usual offset to look for the values in, for files
+        // with a slightly different header layout
+        const SEARCH_DISTANCE: usize = 16;
+
+        let window_start = SPOTIFY_NORMALIZATION_HEADER_START_OFFSET - SEARCH_DISTANCE;
+        file.seek(SeekFrom::Start(window_start as u64))?;
+        let mut window = Vec::with_capacity(2 * SEARCH_DISTANCE + Self::SIZE);
+        (&mut file)
+            .take((2 * SEARCH_DISTANCE + Self::SIZE) as u64)
+            .read_to_end(&mut window)?;
+
+        // Closest to the usual offset first
+        let mut offsets: Vec<usize> = (0..=window.len().saturating_sub(Self::SIZE)).collect();
+        offsets.sort_by_key(|offset| offset.abs_diff(SEARCH_DISTANCE));
+
+        for offset in offsets {
+            let mut buf = [0u8; Self::SIZE];
+            buf.copy_from_slice(&window[offset..offset + Self::SIZE]);
+            if let Some(data) = Self::parse(buf) {
+                if offset != SEARCH_DISTANCE {
+                    debug!(
+                        "Found normalisation data at offset {}",
+                        window_start + offset
+                    );
+                }
+                return Ok(data);
+            }
         }
 
-        let mut buf = [0u8; NORMALISATION_DATA_SIZE];
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "no plausible normalisation data in Ogg header",
+        ))
+    }
 
-        file.read_exact(&mut buf)?;
+    // None unless all values are in the range real tracks have, so that other header
+    // bytes aren't mistaken for normalisation data
+    fn parse(buf: [u8; Self::SIZE]) -> Option<Self> {
+        const MAX_GAIN_DB: f64 = 60.0;
+        const MAX_PEAK: f64 = 8.0;
 
         let track_gain_db = f32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as f64;
         let track_peak = f32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]) as f64;
         let album_gain_db = f32::from_le_bytes([buf[8], buf[9], buf[10], buf[11]]) as f64;
         let album_peak = f32::from_le_bytes([buf[12], buf[13], buf[14], buf[15]]) as f64;
 
-        Ok(Self {
+        let valid_gain = |gain_db: f64| gain_db.abs() <= MAX_GAIN_DB;
+        let valid_peak = |peak: f64| peak > 0.0 && peak <= MAX_PEAK;
+        if !(valid_gain(track_gain_db)
+            && valid_gain(album_gain_db)
+            && valid_peak(track_peak)
+            && valid_peak(album_peak))
+        {
+            return None;
+        }
+
+        Some(Self {
             track_gain_db,
             track_peak,
             album_gain_db,
             album_peak,
+            raw: Some(buf),
         })
     }
 
@@ -374,6 +822,20 @@ impl NormalisationData {
             } else {
                 factor
             }
+        } else if config.normalisation_method == NormalisationMethod::Peak {
+            // For Peak Normalisation, factor = ratio of threshold to peak level, so that the
+            // peak hits the threshold. ReplayGain and PreGain are ignored, so quiet tracks are
+            // raised as far as their peak allows and loud ones lowered, but perceived loudness
+            // may still differ between tracks. Tracks with an unknown (zero) peak level are
+            // left as is, and quiet ones are raised by at most PEAK_NORMALISATION_MAX_GAIN_DB.
+            if gain_peak > 0.0 {
+                f64::min(
+                    db_to_ratio(config.normalisation_threshold_dbfs) / gain_peak,
+                    db_to_ratio(PEAK_NORMALISATION_MAX_GAIN_DB),
+                )
+            } else {
+                1.0
+            }
         } else {
             // For Dynamic Normalisation it's up to the player to decide,
             // factor = ratio of (ReplayGain + PreGain).
@@ -452,25 +914,53 @@ impl Player {
             }
         }
 
+        let event_channel_capacity = config.event_channel_capacity;
         let handle = thread::spawn(move || {
             let player_id = PLAYER_COUNTER.fetch_add(1, Ordering::AcqRel);
             debug!("new Player [{}]", player_id);
 
             let converter = Converter::new(config.ditherer);
+            let audio_filter = ParametricEq::from_config(&config)
+                .map(|eq| Box::new(eq) as Box<dyn AudioFilter + Send>);
+            let balance_filter = BalanceFilter::new(config.balance);
+            let duck_filter = DuckFilter::from_config(&config);
+            let event_fanout = EventFanout::new(config.offload_events, player_id);
 
             let internal = PlayerInternal {
                 session,
                 config,
                 commands: cmd_rx,
                 load_handles: Arc::new(Mutex::new(HashMap::new())),
+                normalisation_cache: Arc::new(Mutex::new(NormalisationCache::default())),
 
                 state: PlayerState::Stopped,
                 preload: PlayerPreload::None,
                 sink: sink_builder(),
                 sink_status: SinkStatus::Closed,
                 sink_event_callback: None,
+                clock_callback: None,
+                output_format_reported: false,
+                failed: false,
+                transition: None,
+                pending_seek_ms: None,
+                sink_retry: None,
+                queue_end: None,
+                gain_override_db: None,
+                buffer_progress_reported: None,
+                position_checkpoint_sent: None,
+                reduced_bitrate: None,
+                stalls: VecDeque::new(),
+                last_stall: None,
+                decode_stats: DecodeStats::new(),
+                reported_volume: None,
+                stopped_track: None,
+                queue_start: None,
                 volume_getter,
-                event_senders: vec![],
+                audio_filter,
+                balance_filter,
+                duck_filter,
+                level_meter: None,
+                event_fanout,
                 converter,
 
                 normalisation_peak: 0.0,
@@ -493,6 +983,7 @@ impl Player {
         Arc::new(Self {
             commands: Some(cmd_tx),
             thread_handle: Some(handle),
+            event_channel_capacity,
         })
     }
 
@@ -539,16 +1030,54 @@ impl Player {
         self.command(PlayerCommand::Seek(position_ms));
     }
 
+    /// Starts the current track over from the beginning, also when paused.
+    pub fn restart(&self) {
+        self.command(PlayerCommand::Restart);
+    }
+
     pub fn set_session(&self, session: Session) {
         self.command(PlayerCommand::SetSession(session));
     }
 
     pub fn get_player_event_channel(&self) -> PlayerEventChannel {
-        let (event_sender, event_receiver) = mpsc::unbounded_channel();
+        let (event_sender, event_receiver) = player_event_channel(self.event_channel_capacity);
         self.command(PlayerCommand::AddEventSender(event_sender));
         event_receiver
     }
 
+    /// Asks the player thread for a snapshot of its current state. Returns a
+    /// stopped snapshot if the player has shut down.
+    pub async fn get_state(&self) -> PlayerStateSnapshot {
+        let (tx, rx) = oneshot::channel();
+        self.command(PlayerCommand::GetState(tx));
+        rx.await.unwrap_or_default()
+    }
+
+    /// Time left until the player suggests preloading the next track, zero
+    /// once it is due. `None` when not playing or already suggested.
+    pub async fn time_until_preload(&self) -> Option<Duration> {
+        let (tx, rx) = oneshot::channel();
+        self.command(PlayerCommand::GetTimeUntilPreload(tx));
+        rx.await.unwrap_or_default()
+    }
+
+    /// The config the player currently uses, including changes made while playing.
+    /// Returns `None` if the player has shut down.
+    pub async fn get_config(&self) -> Option<PlayerConfig> {
+        let (tx, rx) = oneshot::channel();
+        self.command(PlayerCommand::GetConfig(tx));
+        rx.await.ok()
+    }
+
+    /// Number of live event subscribers, such as channels from
+    /// `get_player_event_channel`. Subscribers whose channel was dropped are not
+    /// counted. Returns `None` if the player has shut down.
+    pub async fn subscriber_count(&self) -> Option<usize> {
+        let (tx, rx) = oneshot::channel();
+        self.command(PlayerCommand::GetSubscriberCount(tx));
+        rx.await.ok()
+    }
+
     pub async fn await_end_of_track(&self) {
         let mut channel = self.get_player_event_channel();
         while let Some(event) = channel.recv().await {
@@ -565,14 +1094,84 @@ impl Player {
         self.command(PlayerCommand::SetSinkEventCallback(callback));
     }
 
+    /// Registers a callback that receives the precise stream position in ms of
+    /// every packet handed to the sink. Replaces any previously registered clock.
+    pub fn register_clock(&self, callback: ClockCallback) {
+        self.command(PlayerCommand::RegisterClock(callback));
+    }
+
+    /// Sends the events describing the current state again, without changing playback.
+    /// For event consumers that have lost track of the player.
+    pub fn resync(&self) {
+        self.command(PlayerCommand::ResyncState);
+    }
+
     pub fn emit_volume_changed_event(&self, volume: u16) {
         self.command(PlayerCommand::EmitVolumeChangedEvent(volume));
     }
 
+    /// Reports a volume change seen by the mixer. Unlike `emit_volume_changed_event`,
+    /// nothing is sent if the volume was already reported.
+    pub fn emit_mixer_volume_changed_event(&self, volume: u16) {
+        self.command(PlayerCommand::EmitMixerVolumeChangedEvent(volume));
+    }
+
     pub fn set_auto_normalise_as_album(&self, setting: bool) {
         self.command(PlayerCommand::SetAutoNormaliseAsAlbum(setting));
     }
 
+    /// Enable or disable gapless playback. The setting is checked when the next track is
+    /// loaded, a transition that is already in progress is not affected.
+    pub fn set_gapless(&self, gapless: bool) {
+        self.command(PlayerCommand::SetGapless(gapless));
+    }
+
+    /// Change how long before the end of a track `TimeToPreloadNextTrack` is sent,
+    /// e.g. to preload earlier on a slow network. Applies to the current track too.
+    pub fn set_preload_lead_time(&self, lead_time: Duration) {
+        self.command(PlayerCommand::SetPreloadLeadTime(lead_time));
+    }
+
+    /// -1.0 is left only, 1.0 right only, values outside are clamped.
+    pub fn set_balance(&self, balance: f64) {
+        self.command(PlayerCommand::SetBalance(balance));
+    }
+
+    /// Lower the output by the configured `duck_attenuation_db` while playback continues,
+    /// fading over `duck_ramp`.
+    pub fn set_duck(&self, ducked: bool) {
+        self.command(PlayerCommand::SetDuck(ducked));
+    }
+
+    /// Send Levels events for a level meter. Measuring costs a pass over every packet,
+    /// so it is off until a listener turns it on.
+    pub fn set_levels(&self, enabled: bool) {
+        self.command(PlayerCommand::SetLevels(enabled));
+    }
+
+    /// Normalisation gain in dB to use instead of the one of the current track,
+    /// applied immediately. None goes back to the track's own gain.
+    pub fn set_gain_override(&self, gain_db: Option<f64>) {
+        self.command(PlayerCommand::SetGainOverride(gain_db));
+    }
+
+    /// Switches between Basic, Dynamic and Peak normalisation from the next packet,
+    /// the limiter starts over.
+    pub fn set_normalisation_method(&self, method: NormalisationMethod) {
+        self.command(PlayerCommand::SetNormalisationMethod(method));
+    }
+
+    /// Takes effect from the next packet, None disables dithering.
+    pub fn set_ditherer(&self, ditherer: Option<DithererBuilder>) {
+        self.command(PlayerCommand::SetDitherer(ditherer));
+    }
+
+    /// Clears the dynamic limiter's attack and release state from the next packet,
+    /// sending a `LimiterState` event if the limiter was active.
+    pub fn reset_limiter(&self) {
+        self.command(PlayerCommand::ResetLimiter);
+    }
+
     pub fn emit_filter_explicit_content_changed_event(&self, filter: bool) {
         self.command(PlayerCommand::EmitFilterExplicitContentChangedEvent(filter));
     }
@@ -617,6 +1216,10 @@ impl Player {
     pub fn emit_auto_play_changed_event(&self, auto_play: bool) {
         self.command(PlayerCommand::EmitAutoPlayChangedEvent(auto_play));
     }
+
+    pub fn emit_playback_complete_event(&self, context_uri: String) {
+        self.command(PlayerCommand::EmitPlaybackCompleteEvent(context_uri));
+    }
 }
 
 impl Drop for Player {
@@ -631,6 +1234,9 @@ impl Drop for Player {
     }
 }
 
+// Result of loading a track, by PlayerTrackLoader or a load thread
+type LoadResult = Result<PlayerLoadedTrackData, UnavailableReason>;
+
 struct PlayerLoadedTrackData {
     decoder: Decoder,
     normalisation_data: NormalisationData,
@@ -640,13 +1246,102 @@ struct PlayerLoadedTrackData {
     duration_ms: u32,
     stream_position_ms: u32,
     is_explicit: bool,
+    decrypted: bool,
+    // Alternatives looked up because the requested track is unavailable
+    alternatives_evaluated: usize,
+}
+
+enum EventFanoutMessage {
+    AddSender(PlayerEventSender),
+    Event(Instant, PlayerEvent),
+    SubscriberCount(oneshot::Sender<usize>),
+}
+
+// Delivers player events to all subscribers, either right from the player thread or,
+// when offloaded, from a thread of its own. Then the player thread only ever queues
+// one message per event, no matter how many subscribers there are.
+enum EventFanout {
+    Inline(Vec<PlayerEventSender>),
+    Offloaded(mpsc::UnboundedSender<EventFanoutMessage>),
+}
+
+impl EventFanout {
+    fn new(offload: bool, player_id: usize) -> Self {
+        if !offload {
+            return Self::Inline(vec![]);
+        }
+
+        let (fanout_tx, mut fanout_rx) = mpsc::unbounded_channel();
+        let spawned = thread::Builder::new()
+            .name(format!("player-events-{}", player_id))
+            .spawn(move || {
+                let mut senders: Vec<PlayerEventSender> = vec![];
+                // Ends when the player drops its end of the channel
+                while let Some(message) = fanout_rx.blocking_recv() {
+                    match message {
+                        EventFanoutMessage::AddSender(sender) => senders.push(sender),
+                        EventFanoutMessage::Event(emitted_at, event) => senders.retain(|sender| {
+                            sender.send_timestamped(emitted_at, event.clone()).is_ok()
+                        }),
+                        EventFanoutMessage::SubscriberCount(reply) => {
+                            senders.retain(|sender| !sender.is_closed());
+                            let _ = reply.send(senders.len());
+                        }
+                    }
+                }
+            });
+
+        match spawned {
+            Ok(_) => Self::Offloaded(fanout_tx),
+            Err(e) => {
+                warn!("Unable to start event thread, sending events inline: {}", e);
+                Self::Inline(vec![])
+            }
+        }
+    }
+
+    fn add_sender(&mut self, sender: PlayerEventSender) {
+        match self {
+            Self::Inline(senders) => senders.push(sender),
+            Self::Offloaded(fanout) => {
+                let _ = fanout.send(EventFanoutMessage::AddSender(sender));
+            }
+        }
+    }
+
+    // Timestamped here, so that offloaded events don't age on the way to the fanout thread
+    fn send(&mut self, event: PlayerEvent) {
+        let emitted_at = Instant::now();
+        match self {
+            Self::Inline(senders) => {
+                senders.retain(|sender| sender.send_timestamped(emitted_at, event.clone()).is_ok())
+            }
+            Self::Offloaded(fanout) => {
+                let _ = fanout.send(EventFanoutMessage::Event(emitted_at, event));
+            }
+        }
+    }
+
+    // Replies with the number of senders whose receiver is still there, dropping the
+    // others right away instead of on the next event
+    fn subscriber_count(&mut self, reply: oneshot::Sender<usize>) {
+        match self {
+            Self::Inline(senders) => {
+                senders.retain(|sender| !sender.is_closed());
+                let _ = reply.send(senders.len());
+            }
+            Self::Offloaded(fanout) => {
+                let _ = fanout.send(EventFanoutMessage::SubscriberCount(reply));
+            }
+        }
+    }
 }
 
 enum PlayerPreload {
     None,
     Loading {
         track_id: SpotifyId,
-        loader: Pin<Box<dyn FusedFuture<Output = Result<PlayerLoadedTrackData, ()>> + Send>>,
+        loader: Pin<Box<dyn FusedFuture<Output = LoadResult> + Send>>,
     },
     Ready {
         track_id: SpotifyId,
@@ -662,7 +1357,7 @@ enum PlayerState {
         track_id: SpotifyId,
         play_request_id: u64,
         start_playback: bool,
-        loader: Pin<Box<dyn FusedFuture<Output = Result<PlayerLoadedTrackData, ()>> + Send>>,
+        loader: Pin<Box<dyn FusedFuture<Output = LoadResult> + Send>>,
     },
     Paused {
         track_id: SpotifyId,
@@ -677,6 +1372,8 @@ enum PlayerState {
         stream_position_ms: u32,
         suggested_to_preload_next_track: bool,
         is_explicit: bool,
+        decrypted: bool,
+        alternatives_evaluated: usize,
     },
     Playing {
         track_id: SpotifyId,
@@ -692,6 +1389,8 @@ enum PlayerState {
         reported_nominal_start_time: Option<Instant>,
         suggested_to_preload_next_track: bool,
         is_explicit: bool,
+        decrypted: bool,
+        alternatives_evaluated: usize,
     },
     EndOfTrack {
         track_id: SpotifyId,
@@ -709,7 +1408,7 @@ impl PlayerState {
             Playing { .. } => true,
             Invalid => {
                 error!("PlayerState::is_playing in invalid state");
-                exit(1);
+                false
             }
         }
     }
@@ -726,20 +1425,90 @@ impl PlayerState {
         matches!(self, Loading { .. })
     }
 
-    fn decoder(&mut self) -> Option<&mut Decoder> {
+    fn snapshot(&self) -> Result<PlayerStateSnapshot, Error> {
+        use self::PlayerState::*;
+        let snapshot = match *self {
+            Stopped => PlayerStateSnapshot::default(),
+            Loading { track_id, .. } => PlayerStateSnapshot {
+                status: PlaybackStatus::Loading,
+                track_id: Some(track_id),
+                ..Default::default()
+            },
+            Paused {
+                track_id,
+                duration_ms,
+                stream_position_ms,
+                is_explicit,
+                decrypted,
+                alternatives_evaluated,
+                normalisation_data,
+                ref stream_loader_controller,
+                ref audio_item,
+                ..
+            }
+            | Playing {
+                track_id,
+                duration_ms,
+                stream_position_ms,
+                is_explicit,
+                decrypted,
+                alternatives_evaluated,
+                normalisation_data,
+                ref stream_loader_controller,
+                ref audio_item,
+                ..
+            } => PlayerStateSnapshot {
+                status: if self.is_playing() {
+                    PlaybackStatus::Playing
+                } else {
+                    PlaybackStatus::Paused
+                },
+                track_id: Some(track_id),
+                position_ms: stream_position_ms,
+                duration_ms,
+                is_explicit,
+                decrypted,
+                source_track_id: Some(audio_item.track_id),
+                alternatives_evaluated,
+                normalisation_data: Some(normalisation_data),
+                buffered: buffer_progress(stream_loader_controller),
+                range_to_end_available: stream_loader_controller.range_to_end_available(),
+                ..Default::default()
+            },
+            EndOfTrack {
+                track_id,
+                ref loaded_track,
+                ..
+            } => PlayerStateSnapshot {
+                status: PlaybackStatus::EndOfTrack,
+                track_id: Some(track_id),
+                position_ms: loaded_track.duration_ms,
+                duration_ms: loaded_track.duration_ms,
+                is_explicit: loaded_track.is_explicit,
+                decrypted: loaded_track.decrypted,
+                source_track_id: Some(loaded_track.audio_item.track_id),
+                alternatives_evaluated: loaded_track.alternatives_evaluated,
+                normalisation_data: Some(loaded_track.normalisation_data),
+                buffered: 1.0,
+                range_to_end_available: true,
+                ..Default::default()
+            },
+            Invalid => return Err(Error::internal("PlayerState::snapshot in invalid state")),
+        };
+        Ok(snapshot)
+    }
+
+    fn decoder(&mut self) -> Result<Option<&mut Decoder>, Error> {
         use self::PlayerState::*;
         match *self {
-            Stopped | EndOfTrack { .. } | Loading { .. } => None,
+            Stopped | EndOfTrack { .. } | Loading { .. } => Ok(None),
             Paused {
                 ref mut decoder, ..
             }
             | Playing {
                 ref mut decoder, ..
-            } => Some(decoder),
-            Invalid => {
-                error!("PlayerState::decoder in invalid state");
-                exit(1);
-            }
+            } => Ok(Some(decoder)),
+            Invalid => Err(Error::internal("PlayerState::decoder in invalid state")),
         }
     }
 
@@ -757,6 +1526,8 @@ impl PlayerState {
                 stream_loader_controller,
                 stream_position_ms,
                 is_explicit,
+                decrypted,
+                alternatives_evaluated,
                 audio_item,
                 ..
             } => {
@@ -772,6 +1543,8 @@ impl PlayerState {
                         duration_ms,
                         stream_position_ms,
                         is_explicit,
+                        decrypted,
+                        alternatives_evaluated,
                     },
                 };
             }
@@ -780,7 +1553,7 @@ impl PlayerState {
                     "Called playing_to_end_of_track in non-playing state: {:?}",
                     new_state
                 );
-                exit(1);
+                // Left invalid, the poll loop resets the player
             }
         }
     }
@@ -802,6 +1575,8 @@ impl PlayerState {
                 stream_position_ms,
                 suggested_to_preload_next_track,
                 is_explicit,
+                decrypted,
+                alternatives_evaluated,
             } => {
                 *self = Playing {
                     track_id,
@@ -818,6 +1593,8 @@ impl PlayerState {
                         .checked_sub(Duration::from_millis(stream_position_ms as u64)),
                     suggested_to_preload_next_track,
                     is_explicit,
+                    decrypted,
+                    alternatives_evaluated,
                 };
             }
             _ => {
@@ -825,7 +1602,7 @@ impl PlayerState {
                     "PlayerState::paused_to_playing in invalid state: {:?}",
                     new_state
                 );
-                exit(1);
+                // Left invalid, the poll loop resets the player
             }
         }
     }
@@ -847,6 +1624,8 @@ impl PlayerState {
                 stream_position_ms,
                 suggested_to_preload_next_track,
                 is_explicit,
+                decrypted,
+                alternatives_evaluated,
                 ..
             } => {
                 *self = Paused {
@@ -862,6 +1641,8 @@ impl PlayerState {
                     stream_position_ms,
                     suggested_to_preload_next_track,
                     is_explicit,
+                    decrypted,
+                    alternatives_evaluated,
                 };
             }
             _ => {
@@ -869,38 +1650,84 @@ impl PlayerState {
                     "PlayerState::playing_to_paused in invalid state: {:?}",
                     new_state
                 );
-                exit(1);
+                // Left invalid, the poll loop resets the player
             }
         }
     }
 }
 
+// Normalisation data parsed from Ogg headers, by file. Only the most recently used
+// files are kept.
+#[derive(Default)]
+struct NormalisationCache {
+    // least recently used first
+    entries: VecDeque<(FileId, NormalisationData)>,
+}
+
+impl NormalisationCache {
+    fn get(&mut self, file_id: FileId) -> Option<NormalisationData> {
+        let index = self.entries.iter().position(|(id, _)| *id == file_id)?;
+        let entry = self.entries.remove(index)?;
+        let data = entry.1;
+        self.entries.push_back(entry);
+        Some(data)
+    }
+
+    fn insert(&mut self, file_id: FileId, data: NormalisationData) {
+        self.remove(file_id);
+        if self.entries.len() >= NORMALISATION_CACHE_SIZE {
+            self.entries.pop_front();
+        }
+        self.entries.push_back((file_id, data));
+    }
+
+    fn remove(&mut self, file_id: FileId) {
+        self.entries.retain(|(id, _)| *id != file_id);
+    }
+}
+
 struct PlayerTrackLoader {
     session: Session,
     config: PlayerConfig,
+    normalisation_cache: Arc<Mutex<NormalisationCache>>,
 }
 
 impl PlayerTrackLoader {
-    async fn find_available_alternative(&self, audio_item: AudioItem) -> Option<AudioItem> {
+    // The item itself if it can be played, otherwise the first available of its
+    // alternatives, along with the number of alternatives looked up to find it
+    async fn find_available_alternative(
+        &self,
+        audio_item: AudioItem,
+    ) -> Result<(AudioItem, usize), UnavailableReason> {
         if let Err(e) = audio_item.availability {
             error!("Track is unavailable: {}", e);
-            None
+            Err(e.into())
         } else if !audio_item.files.is_empty() {
-            Some(audio_item)
+            Ok((audio_item, 0))
         } else if let Some(alternatives) = &audio_item.alternatives {
+            let total = alternatives.len();
             let alternatives: FuturesUnordered<_> = alternatives
                 .iter()
                 .map(|alt_id| AudioItem::get_file(&self.session, *alt_id))
                 .collect();
 
-            alternatives
+            let mut evaluated = 0;
+            let alternative = alternatives
+                .inspect(|_| evaluated += 1)
                 .filter_map(|x| future::ready(x.ok()))
                 .filter(|x| future::ready(x.availability.is_ok()))
                 .next()
                 .await
+                .ok_or(UnavailableReason::Removed)?;
+
+            info!(
+                "Playing alternative <{}> of <{}>, found after looking up {} of {} alternatives",
+                alternative.uri, audio_item.uri, evaluated, total
+            );
+            Ok((alternative, evaluated))
         } else {
             error!("Track should be available, but no alternatives found.");
-            None
+            Err(UnavailableReason::Removed)
         }
     }
 
@@ -921,28 +1748,37 @@ impl PlayerTrackLoader {
         kbps * 1024
     }
 
-    async fn load_track(
+    async fn load_track(&self, spotify_id: SpotifyId, position_ms: u32) -> LoadResult {
+        let (audio_item, alternatives_evaluated) =
+            match AudioItem::get_file(&self.session, spotify_id).await {
+                Ok(audio) => match self.find_available_alternative(audio).await {
+                    Ok(found) => found,
+                    Err(reason) => {
+                        warn!(
+                            "<{}> is not available",
+                            spotify_id.to_uri().unwrap_or_default()
+                        );
+                        return Err(reason);
+                    }
+                },
+                Err(e) => {
+                    error!("Unable to load audio item: {:?}", e);
+                    return Err(UnavailableReason::LoadFailed);
+                }
+            };
+
+        self.load_audio_item(spotify_id, audio_item, alternatives_evaluated, position_ms)
+            .await
+    }
+
+    // Opens the file of the first format in order of preference that can be decoded
+    async fn load_audio_item(
         &self,
         spotify_id: SpotifyId,
+        audio_item: AudioItem,
+        alternatives_evaluated: usize,
         position_ms: u32,
-    ) -> Option<PlayerLoadedTrackData> {
-        let audio_item = match AudioItem::get_file(&self.session, spotify_id).await {
-            Ok(audio) => match self.find_available_alternative(audio).await {
-                Some(audio) => audio,
-                None => {
-                    warn!(
-                        "<{}> is not available",
-                        spotify_id.to_uri().unwrap_or_default()
-                    );
-                    return None;
-                }
-            },
-            Err(e) => {
-                error!("Unable to load audio item: {:?}", e);
-                return None;
-            }
-        };
-
+    ) -> LoadResult {
         info!(
             "Loading <{}> with Spotify URI <{}>",
             audio_item.name, audio_item.uri
@@ -979,176 +1815,208 @@ impl PlayerTrackLoader {
             ],
         };
 
-        let (format, file_id) =
-            match formats
-                .iter()
-                .find_map(|format| match audio_item.files.get(format) {
-                    Some(&file_id) => Some((*format, file_id)),
-                    _ => None,
-                }) {
-                Some(t) => t,
-                None => {
-                    warn!(
-                        "<{}> is not available in any supported format",
-                        audio_item.name
-                    );
-                    return None;
-                }
-            };
-
-        let bytes_per_second = self.stream_data_rate(format);
+        let candidates: Vec<(AudioFileFormat, FileId)> = formats
+            .iter()
+            .filter_map(|format| {
+                audio_item
+                    .files
+                    .get(format)
+                    .map(|&file_id| (*format, file_id))
+            })
+            .collect();
+
+        if candidates.is_empty() {
+            warn!(
+                "<{}> is not available in any supported format",
+                audio_item.name
+            );
+            return Err(UnavailableReason::Removed);
+        }
 
-        // This is only a loop to be able to reload the file if an error occurred
-        // while opening a cached file.
-        loop {
-            let encrypted_file = AudioFile::open(&self.session, file_id, bytes_per_second);
+        // Formats are tried in order of preference. If the codec for a format is not
+        // available in this build, fall through to the next one.
+        'formats: for (format, file_id) in candidates {
+            let bytes_per_second = self.stream_data_rate(format);
 
-            let encrypted_file = match encrypted_file.await {
-                Ok(encrypted_file) => encrypted_file,
-                Err(e) => {
-                    error!("Unable to load encrypted file: {:?}", e);
-                    return None;
-                }
-            };
+            // This is only a loop to be able to reload the file if an error occurred
+            // while opening a cached file.
+            loop {
+                let encrypted_file = AudioFile::open(&self.session, file_id, bytes_per_second);
 
-            let is_cached = encrypted_file.is_cached();
+                let encrypted_file = match encrypted_file.await {
+                    Ok(encrypted_file) => encrypted_file,
+                    Err(e) => {
+                        error!("Unable to load encrypted file: {:?}", e);
+                        return Err(UnavailableReason::LoadFailed);
+                    }
+                };
 
-            let stream_loader_controller = encrypted_file.get_stream_loader_controller().ok()?;
+                let is_cached = encrypted_file.is_cached();
 
-            // Not all audio files are encrypted. If we can't get a key, try loading the track
-            // without decryption. If the file was encrypted after all, the decoder will fail
-            // parsing and bail out, so we should be safe from outputting ear-piercing noise.
-            let key = match self.session.audio_key().request(spotify_id, file_id).await {
-                Ok(key) => Some(key),
-                Err(e) => {
-                    warn!("Unable to load key, continuing without decryption: {}", e);
-                    None
-                }
-            };
-            let mut decrypted_file = AudioDecrypt::new(key, encrypted_file);
-
-            let is_ogg_vorbis = AudioFiles::is_ogg_vorbis(format);
-            let (offset, mut normalisation_data) = if is_ogg_vorbis {
-                // Spotify stores normalisation data in a custom Ogg packet instead of Vorbis comments.
-                let normalisation_data =
-                    NormalisationData::parse_from_ogg(&mut decrypted_file).ok();
-                (SPOTIFY_OGG_HEADER_END, normalisation_data)
-            } else {
-                (0, None)
-            };
+                let stream_loader_controller = encrypted_file
+                    .get_stream_loader_controller()
+                    .map_err(|_| UnavailableReason::LoadFailed)?;
 
-            let audio_file = match Subfile::new(
-                decrypted_file,
-                offset,
-                stream_loader_controller.len() as u64,
-            ) {
-                Ok(audio_file) => audio_file,
-                Err(e) => {
-                    error!("PlayerTrackLoader::load_track error opening subfile: {}", e);
-                    return None;
-                }
-            };
+                // Not all audio files are encrypted. If we can't get a key, try loading the track
+                // without decryption. If the file was encrypted after all, the decoder will fail
+                // parsing and bail out, so we should be safe from outputting ear-piercing noise.
+                let key = match self.session.audio_key().request(spotify_id, file_id).await {
+                    Ok(key) => Some(key),
+                    Err(e) => {
+                        warn!("Unable to load key, continuing without decryption: {}", e);
+                        None
+                    }
+                };
+                let decrypted = key.is_some();
+                let mut decrypted_file = AudioDecrypt::new(key, encrypted_file);
+
+                let is_ogg_vorbis = AudioFiles::is_ogg_vorbis(format);
+                let (offset, mut normalisation_data) = if is_ogg_vorbis {
+                    // Spotify stores normalisation data in a custom Ogg packet instead of Vorbis comments.
+                    let cached = self.normalisation_cache.lock().get(file_id);
+                    let normalisation_data = cached.or_else(|| {
+                        let data = NormalisationData::parse_from_ogg(&mut decrypted_file).ok()?;
+                        self.normalisation_cache.lock().insert(file_id, data);
+                        Some(data)
+                    });
+                    (SPOTIFY_OGG_HEADER_END, normalisation_data)
+                } else {
+                    (0, None)
+                };
 
-            let mut symphonia_decoder = |audio_file, format| {
-                SymphoniaDecoder::new(audio_file, format).map(|mut decoder| {
-                    // For formats other that Vorbis, we'll try getting normalisation data from
-                    // ReplayGain metadata fields, if present.
-                    if normalisation_data.is_none() {
-                        normalisation_data = decoder.normalisation_data();
+                let audio_file = match Subfile::new(
+                    decrypted_file,
+                    offset,
+                    stream_loader_controller.len() as u64,
+                ) {
+                    Ok(audio_file) => audio_file,
+                    Err(e) => {
+                        error!("PlayerTrackLoader::load_track error opening subfile: {}", e);
+                        return Err(UnavailableReason::LoadFailed);
                     }
-                    Box::new(decoder) as Decoder
-                })
-            };
+                };
 
-            #[cfg(feature = "passthrough-decoder")]
-            let decoder_type = if self.config.passthrough {
-                PassthroughDecoder::new(audio_file, format).map(|x| Box::new(x) as Decoder)
-            } else {
-                symphonia_decoder(audio_file, format)
-            };
+                let mut symphonia_decoder = |audio_file, format| {
+                    SymphoniaDecoder::new(audio_file, format).map(|mut decoder| {
+                        // For formats other that Vorbis, we'll try getting normalisation data from
+                        // ReplayGain metadata fields, if present.
+                        if normalisation_data.is_none() {
+                            normalisation_data = decoder.normalisation_data();
+                        }
+                        Box::new(decoder) as Decoder
+                    })
+                };
 
-            #[cfg(not(feature = "passthrough-decoder"))]
-            let decoder_type = symphonia_decoder(audio_file, format);
+                #[cfg(feature = "passthrough-decoder")]
+                let decoder_type = if self.config.passthrough {
+                    PassthroughDecoder::new(audio_file, format).map(|x| Box::new(x) as Decoder)
+                } else {
+                    symphonia_decoder(audio_file, format)
+                };
 
-            let normalisation_data = normalisation_data.unwrap_or_else(|| {
-                warn!("Unable to get normalisation data, continuing with defaults.");
-                NormalisationData::default()
-            });
+                #[cfg(not(feature = "passthrough-decoder"))]
+                let decoder_type = symphonia_decoder(audio_file, format);
 
-            let mut decoder = match decoder_type {
-                Ok(decoder) => decoder,
-                Err(e) if is_cached => {
-                    warn!(
-                        "Unable to read cached audio file: {}. Trying to download it.",
-                        e
-                    );
+                let normalisation_data = normalisation_data.unwrap_or_else(|| {
+                    warn!("Unable to get normalisation data, continuing with defaults.");
+                    NormalisationData::unknown()
+                });
 
-                    match self.session.cache() {
-                        Some(cache) => {
-                            if cache.remove_file(file_id).is_err() {
-                                error!("Error removing file from cache");
-                                return None;
+                let mut decoder = match decoder_type {
+                    Ok(decoder) => decoder,
+                    Err(DecoderError::UnsupportedCodec(e)) => {
+                        warn!(
+                            "Unable to decode <{}> as {:?}, codec unavailable: {}",
+                            audio_item.name, format, e
+                        );
+                        continue 'formats;
+                    }
+                    Err(e) if is_cached => {
+                        warn!(
+                            "Unable to read cached audio file: {}. Trying to download it.",
+                            e
+                        );
+
+                        // The header may have been read from the broken file as well
+                        self.normalisation_cache.lock().remove(file_id);
+
+                        match self.session.cache() {
+                            Some(cache) => {
+                                if cache.remove_file(file_id).is_err() {
+                                    error!("Error removing file from cache");
+                                    return Err(UnavailableReason::LoadFailed);
+                                }
+                            }
+                            None => {
+                                error!("If the audio file is cached, a cache should exist");
+                                return Err(UnavailableReason::LoadFailed);
                             }
                         }
-                        None => {
-                            error!("If the audio file is cached, a cache should exist");
-                            return None;
-                        }
-                    }
 
-                    // Just try it again
-                    continue;
-                }
-                Err(e) => {
-                    error!("Unable to read audio file: {}", e);
-                    return None;
-                }
-            };
+                        // Just try it again
+                        continue;
+                    }
+                    Err(e) => {
+                        error!("Unable to read audio file: {}", e);
+                        return Err(UnavailableReason::LoadFailed);
+                    }
+                };
 
-            let duration_ms = audio_item.duration_ms;
-            // Don't try to seek past the track's duration.
-            // If the position is invalid just start from
-            // the beginning of the track.
-            let position_ms = if position_ms > duration_ms {
-                warn!("Invalid start position of {} ms exceeds track's duration of {} ms, starting track from the beginning", position_ms, duration_ms);
-                0
-            } else {
-                position_ms
-            };
+                let duration_ms = audio_item.duration_ms;
+                // Preloads always start at the beginning, so apply the trim here as well.
+                let position_ms = position_ms.max(self.config.trim_start_ms);
+                // Don't try to seek past the track's duration.
+                // If the position is invalid just start from
+                // the beginning of the track.
+                let position_ms = if position_ms > duration_ms {
+                    warn!("Invalid start position of {} ms exceeds track's duration of {} ms, starting track from the beginning", position_ms, duration_ms);
+                    0
+                } else {
+                    position_ms
+                };
 
-            // Ensure the starting position. Even when we want to play from the beginning,
-            // the cursor may have been moved by parsing normalisation data. This may not
-            // matter for playback (but won't hurt either), but may be useful for the
-            // passthrough decoder.
-            let stream_position_ms = match decoder.seek(position_ms) {
-                Ok(new_position_ms) => new_position_ms,
-                Err(e) => {
-                    error!(
-                        "PlayerTrackLoader::load_track error seeking to starting position {}: {}",
-                        position_ms, e
-                    );
-                    return None;
-                }
-            };
+                // Ensure the starting position. Even when we want to play from the beginning,
+                // the cursor may have been moved by parsing normalisation data. This may not
+                // matter for playback (but won't hurt either), but may be useful for the
+                // passthrough decoder.
+                let stream_position_ms = match decoder.seek(position_ms) {
+                    Ok(new_position_ms) => new_position_ms,
+                    Err(e) => {
+                        error!(
+                            "PlayerTrackLoader::load_track error seeking to starting position {}: {}",
+                            position_ms, e
+                        );
+                        return Err(UnavailableReason::LoadFailed);
+                    }
+                };
 
-            // Ensure streaming mode now that we are ready to play from the requested position.
-            stream_loader_controller.set_stream_mode();
+                // Ensure streaming mode now that we are ready to play from the requested position.
+                stream_loader_controller.set_stream_mode();
 
-            let is_explicit = audio_item.is_explicit;
+                let is_explicit = audio_item.is_explicit;
 
-            info!("<{}> ({} ms) loaded", audio_item.name, duration_ms);
+                info!("<{}> ({} ms) loaded", audio_item.name, duration_ms);
 
-            return Some(PlayerLoadedTrackData {
-                decoder,
-                normalisation_data,
-                stream_loader_controller,
-                audio_item,
-                bytes_per_second,
-                duration_ms,
-                stream_position_ms,
-                is_explicit,
-            });
+                return Ok(PlayerLoadedTrackData {
+                    decoder,
+                    normalisation_data,
+                    stream_loader_controller,
+                    audio_item,
+                    bytes_per_second,
+                    duration_ms,
+                    stream_position_ms,
+                    is_explicit,
+                    decrypted,
+                    alternatives_evaluated,
+                });
+            }
         }
+
+        warn!(
+            "<{}> is not available in any format that can be decoded",
+            audio_item.name
+        );
+        Err(UnavailableReason::LoadFailed)
     }
 }
 
@@ -1161,6 +2029,10 @@ impl Future for PlayerInternal {
         let passthrough = self.config.passthrough;
 
         loop {
+            if self.failed {
+                return Poll::Ready(());
+            }
+
             let mut all_futures_completed_or_not_ready = true;
 
             // process commands that were sent to us
@@ -1199,18 +2071,20 @@ impl Future for PlayerInternal {
                                 start_playback,
                             );
                             if let PlayerState::Loading { .. } = self.state {
-                                error!("The state wasn't changed by start_playback()");
-                                exit(1);
+                                self.handle_fatal_error(
+                                    "The state wasn't changed by start_playback()".to_string(),
+                                );
                             }
                         }
-                        Poll::Ready(Err(e)) => {
+                        Poll::Ready(Err(reason)) => {
                             error!(
                                 "Skipping to next track, unable to load track <{:?}>: {:?}",
-                                track_id, e
+                                track_id, reason
                             );
                             self.send_event(PlayerEvent::Unavailable {
                                 track_id,
                                 play_request_id,
+                                reason,
                             })
                         }
                         Poll::Pending => (),
@@ -1232,8 +2106,8 @@ impl Future for PlayerInternal {
                             loaded_track: Box::new(loaded_track),
                         };
                     }
-                    Poll::Ready(Err(_)) => {
-                        debug!("Unable to preload {:?}", track_id);
+                    Poll::Ready(Err(reason)) => {
+                        debug!("Unable to preload {:?}: {:?}", track_id, reason);
                         self.preload = PlayerPreload::None;
                         // Let Spirc know that the track was unavailable.
                         if let PlayerState::Playing {
@@ -1246,6 +2120,7 @@ impl Future for PlayerInternal {
                             self.send_event(PlayerEvent::Unavailable {
                                 track_id,
                                 play_request_id,
+                                reason,
                             });
                         }
                     }
@@ -1253,21 +2128,30 @@ impl Future for PlayerInternal {
                 }
             }
 
-            if self.state.is_playing() {
-                self.ensure_sink_running();
+            let retry_waiting = self.poll_sink_retry(cx);
+            // A sink that fails to start pauses playback, there is nothing to decode for
+            if self.state.is_playing() && !retry_waiting && self.ensure_sink_running() {
 
+                let trim_end_ms = self.config.trim_end_ms;
                 if let PlayerState::Playing {
                     track_id,
                     play_request_id,
                     ref mut decoder,
+                    duration_ms,
                     normalisation_factor,
                     ref mut stream_position_ms,
                     ref mut reported_nominal_start_time,
                     ..
                 } = self.state
                 {
-                    match decoder.next_packet() {
+                    let mut stalled = false;
+                    let decode_start = Instant::now();
+                    let decoded = decoder.next_packet();
+                    let decode_time = decode_start.elapsed();
+                    match decoded {
                         Ok(result) => {
+                            let skipped = result.as_ref().map_or(false, |(p, _)| p.skipped);
+                            let result = trim_end(result, duration_ms, trim_end_ms);
                             if let Some((ref packet_position, ref packet)) = result {
                                 let new_stream_position_ms = packet_position.position_ms;
                                 let expected_position_ms = std::mem::replace(
@@ -1312,8 +2196,11 @@ impl Future for PlayerInternal {
                                                             if let Some(lag) =
                                                                 lag.checked_sub(new_stream_position)
                                                             {
-                                                                notify |=
-                                                                    lag >= Duration::from_secs(1)
+                                                                // Fell behind, the decoder
+                                                                // waited for data
+                                                                stalled =
+                                                                    lag >= Duration::from_secs(1);
+                                                                notify |= stalled
                                                             }
                                                         }
 
@@ -1342,6 +2229,10 @@ impl Future for PlayerInternal {
                                 }
                             }
 
+                            self.decode_stats.record(decode_time, skipped);
+                            if stalled {
+                                self.handle_stall(track_id, play_request_id);
+                            }
                             self.handle_packet(result, normalisation_factor);
                         }
                         Err(e) => {
@@ -1353,11 +2244,13 @@ impl Future for PlayerInternal {
                         }
                     }
                 } else {
-                    error!("PlayerInternal poll: Invalid PlayerState");
-                    exit(1);
+                    self.reset_invalid_state(
+                        "PlayerInternal poll: Invalid PlayerState".to_string(),
+                    );
                 };
             }
 
+            let lead_time = self.config.preload_lead_time;
             if let PlayerState::Playing {
                 track_id,
                 play_request_id,
@@ -1378,8 +2271,7 @@ impl Future for PlayerInternal {
             } = self.state
             {
                 if (!*suggested_to_preload_next_track)
-                    && ((duration_ms as i64 - stream_position_ms as i64)
-                        < PRELOAD_NEXT_TRACK_BEFORE_END_DURATION_MS as i64)
+                    && time_until_preload(duration_ms, stream_position_ms, lead_time).is_zero()
                     && stream_loader_controller.range_to_end_available()
                 {
                     *suggested_to_preload_next_track = true;
@@ -1390,7 +2282,24 @@ impl Future for PlayerInternal {
                 }
             }
 
-            if (!self.state.is_playing()) && all_futures_completed_or_not_ready {
+            self.send_buffer_progress();
+            // Runs after the packets, so that it sees a track that just ended
+            if self.poll_queue_end(cx) {
+                all_futures_completed_or_not_ready = false;
+            }
+            self.send_decode_stats();
+            self.send_position_checkpoint(false);
+            self.restore_bitrate();
+
+            if let PlayerState::Invalid = self.state {
+                self.reset_invalid_state("PlayerInternal poll: Invalid PlayerState".to_string());
+            }
+
+            if self.failed {
+                return Poll::Ready(());
+            }
+
+            if (!self.state.is_playing() || retry_waiting) && all_futures_completed_or_not_ready {
                 return Poll::Pending;
             }
         }
@@ -1398,18 +2307,36 @@ impl Future for PlayerInternal {
 }
 
 impl PlayerInternal {
-    fn ensure_sink_running(&mut self) {
-        if self.sink_status != SinkStatus::Running {
-            trace!("== Starting sink ==");
-            if let Some(callback) = &mut self.sink_event_callback {
-                callback(SinkStatus::Running);
-            }
-            match self.sink.start() {
-                Ok(()) => self.sink_status = SinkStatus::Running,
-                Err(e) => {
-                    error!("{}", e);
-                    self.handle_pause();
+    // Returns whether the sink is running. When it can't be started, e.g. because
+    // another application holds the device, playback is paused so that it can be
+    // resumed once the device is free.
+    fn ensure_sink_running(&mut self) -> bool {
+        if self.sink_status == SinkStatus::Running {
+            return true;
+        }
+
+        trace!("== Starting sink ==");
+        if let Some(callback) = &mut self.sink_event_callback {
+            callback(SinkStatus::Running);
+        }
+        match self.sink.start() {
+            Ok(()) => {
+                self.sink_status = SinkStatus::Running;
+                self.send_event(PlayerEvent::SinkStatusChanged {
+                    status: SinkStatus::Running,
+                });
+                true
+            }
+            Err(e) => {
+                error!("{}", e);
+                if let Some(callback) = &mut self.sink_event_callback {
+                    callback(self.sink_status);
                 }
+                self.send_event(PlayerEvent::SinkError {
+                    message: e.to_string(),
+                });
+                self.handle_pause();
+                false
             }
         }
     }
@@ -1428,10 +2355,14 @@ impl PlayerInternal {
                         if let Some(callback) = &mut self.sink_event_callback {
                             callback(self.sink_status);
                         }
+                        self.send_event(PlayerEvent::SinkStatusChanged {
+                            status: self.sink_status,
+                        });
                     }
                     Err(e) => {
-                        error!("{}", e);
-                        exit(1);
+                        // Nothing left to do with a sink that can't be stopped
+                        self.sink_status = SinkStatus::Closed;
+                        self.handle_fatal_error(format!("Unable to stop sink: {}", e));
                     }
                 }
             }
@@ -1441,6 +2372,9 @@ impl PlayerInternal {
                     if let Some(callback) = &mut self.sink_event_callback {
                         callback(SinkStatus::Closed);
                     }
+                    self.send_event(PlayerEvent::SinkStatusChanged {
+                        status: SinkStatus::Closed,
+                    });
                 }
             }
             SinkStatus::Closed => (),
@@ -1469,7 +2403,13 @@ impl PlayerInternal {
                 play_request_id,
                 ..
             } => {
+                self.send_position_checkpoint(true);
+                if self.state.is_playing() {
+                    self.fade_out();
+                }
                 self.ensure_sink_stopped(false);
+                self.transition = None;
+                self.stopped_track = Some((track_id, play_request_id));
                 self.send_event(PlayerEvent::Stopped {
                     track_id,
                     play_request_id,
@@ -1477,10 +2417,109 @@ impl PlayerInternal {
                 self.state = PlayerState::Stopped;
             }
             PlayerState::Stopped => (),
-            PlayerState::Invalid => {
-                error!("PlayerInternal::handle_player_stop in invalid state");
-                exit(1);
+            PlayerState::Invalid => self.reset_invalid_state(
+                "PlayerInternal::handle_player_stop in invalid state".to_string(),
+            ),
+        }
+    }
+
+    // Plays the next packets fading to silence over `stop_fade_ms`, so stopping
+    // doesn't click. Passthrough packets can't be faded.
+    fn fade_out(&mut self) {
+        if self.config.stop_fade_ms == 0 {
+            return;
+        }
+
+        let mut fade = FadeOut::new(Duration::from_millis(self.config.stop_fade_ms as u64));
+        while !fade.is_silent() {
+            let (packet, normalisation_factor) = match self.state {
+                PlayerState::Playing {
+                    ref mut decoder,
+                    normalisation_factor,
+                    ..
+                } => (decoder.next_packet(), normalisation_factor),
+                _ => return,
+            };
+            match packet {
+                Ok(Some((position, AudioPacket::Samples(mut data)))) => {
+                    fade.modify_stream(&mut data);
+                    let packet = AudioPacket::Samples(data);
+                    self.handle_packet(Some((position, packet)), normalisation_factor);
+                }
+                _ => return,
+            }
+        }
+    }
+
+    // Repeat the events that led to the current state. A track that is still loading
+    // reports itself once it is loaded.
+    fn handle_resync_state(&mut self) {
+        if let Some(volume) = self.reported_volume {
+            self.send_event(PlayerEvent::VolumeChanged { volume });
+        }
+
+        match self.state {
+            PlayerState::Playing {
+                track_id,
+                play_request_id,
+                ref audio_item,
+                stream_position_ms,
+                ..
+            }
+            | PlayerState::Paused {
+                track_id,
+                play_request_id,
+                ref audio_item,
+                stream_position_ms,
+                ..
+            } => {
+                let audio_item = Box::new(audio_item.clone());
+                let playing = self.state.is_playing();
+                self.send_event(PlayerEvent::TrackChanged {
+                    audio_item,
+                    position_ms: stream_position_ms,
+                });
+                if playing {
+                    self.send_event(PlayerEvent::Playing {
+                        track_id,
+                        play_request_id,
+                        position_ms: stream_position_ms,
+                    });
+                } else {
+                    self.send_event(PlayerEvent::Paused {
+                        track_id,
+                        play_request_id,
+                        position_ms: stream_position_ms,
+                    });
+                }
+            }
+            PlayerState::EndOfTrack {
+                track_id,
+                play_request_id,
+                ref loaded_track,
+            } => {
+                let audio_item = Box::new(loaded_track.audio_item.clone());
+                let position_ms = loaded_track.duration_ms;
+                self.send_event(PlayerEvent::TrackChanged {
+                    audio_item,
+                    position_ms,
+                });
+                // Not EndOfTrack again, that would make spirc skip another track
+                self.send_event(PlayerEvent::Paused {
+                    track_id,
+                    play_request_id,
+                    position_ms,
+                });
+            }
+            PlayerState::Stopped => {
+                if let Some((track_id, play_request_id)) = self.stopped_track {
+                    self.send_event(PlayerEvent::Stopped {
+                        track_id,
+                        play_request_id,
+                    });
+                }
             }
+            PlayerState::Loading { .. } | PlayerState::Invalid => (),
         }
     }
 
@@ -1492,13 +2531,23 @@ impl PlayerInternal {
                 stream_position_ms,
                 ..
             } => {
+                // Stays paused if the sink can't be started, and says so, as the
+                // client is waiting for an answer to its play
+                if !self.ensure_sink_running() {
+                    self.send_event(PlayerEvent::Paused {
+                        track_id,
+                        play_request_id,
+                        position_ms: stream_position_ms,
+                    });
+                    return;
+                }
+
                 self.state.paused_to_playing();
                 self.send_event(PlayerEvent::Playing {
                     track_id,
                     play_request_id,
                     position_ms: stream_position_ms,
                 });
-                self.ensure_sink_running();
             }
             PlayerState::Loading {
                 ref mut start_playback,
@@ -1527,6 +2576,7 @@ impl PlayerInternal {
                     play_request_id,
                     position_ms: stream_position_ms,
                 });
+                self.send_position_checkpoint(true);
             }
             PlayerState::Loading {
                 ref mut start_playback,
@@ -1544,17 +2594,28 @@ impl PlayerInternal {
         normalisation_factor: f64,
     ) {
         match packet {
-            Some((_, mut packet)) => {
+            Some((position, mut packet)) => {
                 if !packet.is_empty() {
                     if let AudioPacket::Samples(ref mut data) = packet {
+                        // Downmix and filter first, so the limiter sees the signal that is
+                        // actually played.
+                        if self.config.downmix_mono {
+                            downmix_to_mono(data);
+                        }
+                        if let Some(ref mut filter) = self.audio_filter {
+                            filter.modify_stream(data);
+                        }
+                        self.balance_filter.modify_stream(data);
+
                         // Get the volume for the packet.
                         // In the case of hardware volume control this will
                         // always be 1.0 (no change).
                         let volume = self.volume_getter.attenuation_factor();
 
-                        // For the basic normalisation method, a normalisation factor of 1.0 indicates that
-                        // there is nothing to normalise (all samples should pass unaltered). For the
-                        // dynamic method, there may still be peaks that we want to shave off.
+                        // For the basic and peak normalisation methods, a normalisation factor of 1.0
+                        // indicates that there is nothing to normalise (all samples should pass
+                        // unaltered). For the dynamic method, there may still be peaks that we want
+                        // to shave off.
 
                         // No matter the case we apply volume attenuation last if there is any.
                         if !self.config.normalisation {
@@ -1563,8 +2624,10 @@ impl PlayerInternal {
                                     *sample *= volume;
                                 }
                             }
-                        } else if self.config.normalisation_method == NormalisationMethod::Basic
-                            && (normalisation_factor < 1.0 || volume < 1.0)
+                        } else if matches!(
+                            self.config.normalisation_method,
+                            NormalisationMethod::Basic | NormalisationMethod::Peak
+                        ) && (normalisation_factor != 1.0 || volume < 1.0)
                         {
                             for sample in data.iter_mut() {
                                 *sample *= normalisation_factor * volume;
@@ -1575,6 +2638,7 @@ impl PlayerInternal {
                             let knee_db = self.config.normalisation_knee_db;
                             let attack_cf = self.config.normalisation_attack_cf;
                             let release_cf = self.config.normalisation_release_cf;
+                            let was_limiting = self.is_limiting();
 
                             for sample in data.iter_mut() {
                                 *sample *= normalisation_factor;
@@ -1654,13 +2718,26 @@ impl PlayerInternal {
 
                                 *sample *= volume;
                             }
+
+                            if was_limiting != self.is_limiting() {
+                                self.send_limiter_state();
+                            }
                         }
-                    }
 
-                    if let Err(e) = self.sink.write(packet, &mut self.converter) {
-                        error!("{}", e);
-                        self.handle_pause();
+                        self.duck_filter.modify_stream(data);
+
+                        let meter = self.level_meter.as_mut();
+                        if let Some((peak, rms)) = meter.and_then(|meter| meter.measure(data)) {
+                            self.send_event(PlayerEvent::Levels { peak, rms });
+                        }
                     }
+
+                    self.write_to_sink(
+                        position.position_ms,
+                        packet,
+                        self.config.sink_write_retries,
+                        SINK_WRITE_RETRY_DELAY,
+                    );
                 }
             }
 
@@ -1675,101 +2752,417 @@ impl PlayerInternal {
                     self.send_event(PlayerEvent::EndOfTrack {
                         track_id,
                         play_request_id,
-                    })
+                    });
+                    self.start_transition(track_id);
+                    // Gives the controller a chance to load the next track first
+                    let has_behavior = self.config.on_queue_end != QueueEndBehavior::Stop;
+                    if has_behavior && matches!(self.preload, PlayerPreload::None) {
+                        let delay = Box::pin(tokio::time::sleep(QUEUE_END_DELAY));
+                        self.queue_end = Some((play_request_id, delay));
+                    }
                 } else {
-                    error!("PlayerInternal handle_packet: Invalid PlayerState");
-                    exit(1);
+                    self.reset_invalid_state(
+                        "PlayerInternal handle_packet: Invalid PlayerState".to_string(),
+                    );
                 }
             }
         }
     }
 
-    fn start_playback(
-        &mut self,
-        track_id: SpotifyId,
-        play_request_id: u64,
-        loaded_track: PlayerLoadedTrackData,
-        start_playback: bool,
-    ) {
-        let audio_item = Box::new(loaded_track.audio_item.clone());
+    // Applies on_queue_end once its delay has passed, unless a load or anything else
+    // replaced the ended track in the meantime. Returns whether it was applied.
+    fn poll_queue_end(&mut self, cx: &mut Context<'_>) -> bool {
+        let (ended_request_id, mut delay) = match self.queue_end.take() {
+            Some(queue_end) => queue_end,
+            None => return false,
+        };
+        let track_id = match self.state {
+            PlayerState::EndOfTrack {
+                track_id,
+                play_request_id,
+                ..
+            } if play_request_id == ended_request_id => track_id,
+            _ => return false,
+        };
+        if delay.as_mut().poll(cx).is_pending() {
+            self.queue_end = Some((ended_request_id, delay));
+            return false;
+        }
+
+        self.handle_queue_end(track_id);
+        true
+    }
 
-        self.send_event(PlayerEvent::TrackChanged { audio_item });
+    fn handle_queue_end(&mut self, last_track_id: SpotifyId) {
+        let result = match self.config.on_queue_end {
+            QueueEndBehavior::Stop => return,
+            QueueEndBehavior::RepeatAll => match self.queue_start {
+                Some(track_id) => self.handle_command_load(track_id, None, true, 0),
+                None => return,
+            },
+            QueueEndBehavior::Pause => self.handle_command_load(last_track_id, None, false, 0),
+        };
 
-        let position_ms = loaded_track.stream_position_ms;
+        if let Err(e) = result {
+            error!("Unable to handle end of queue: {}", e);
+        }
+    }
 
-        let mut config = self.config.clone();
-        if config.normalisation_type == NormalisationType::Auto {
-            if self.auto_normalise_as_album {
-                config.normalisation_type = NormalisationType::Album;
-            } else {
-                config.normalisation_type = NormalisationType::Track;
+    fn start_transition(&mut self, from_track_id: SpotifyId) {
+        if !self.config.gapless {
+            return;
+        }
+
+        let to_track_id = match self.preload {
+            PlayerPreload::Loading { track_id, .. } | PlayerPreload::Ready { track_id, .. } => {
+                track_id
             }
+            PlayerPreload::None => return,
         };
-        let normalisation_factor =
-            NormalisationData::get_factor(&config, loaded_track.normalisation_data);
 
-        if start_playback {
-            self.ensure_sink_running();
-            self.send_event(PlayerEvent::Playing {
+        self.transition = Some((from_track_id, to_track_id));
+        self.send_event(PlayerEvent::Transitioning {
+            from_track_id,
+            to_track_id,
+        });
+    }
+
+    fn send_output_format(&mut self) {
+        if let PlayerState::Playing { track_id, .. } = self.state {
+            self.output_format_reported = true;
+            self.send_event(PlayerEvent::OutputFormat {
                 track_id,
-                play_request_id,
-                position_ms,
+                format: self.converter.output_format(),
+                ditherer: self.converter.ditherer_name(),
             });
+        }
+    }
 
-            self.state = PlayerState::Playing {
-                track_id,
-                play_request_id,
-                decoder: loaded_track.decoder,
-                audio_item: loaded_track.audio_item,
-                normalisation_data: loaded_track.normalisation_data,
-                normalisation_factor,
-                stream_loader_controller: loaded_track.stream_loader_controller,
-                duration_ms: loaded_track.duration_ms,
-                bytes_per_second: loaded_track.bytes_per_second,
-                stream_position_ms: loaded_track.stream_position_ms,
-                reported_nominal_start_time: Instant::now()
-                    .checked_sub(Duration::from_millis(position_ms as u64)),
-                suggested_to_preload_next_track: false,
-                is_explicit: loaded_track.is_explicit,
-            };
-        } else {
-            self.ensure_sink_stopped(false);
+    // Lowers the bitrate of the next tracks a step once the current one stalled often
+    // enough within the window
+    fn handle_stall(&mut self, track_id: SpotifyId, play_request_id: u64) {
+        let adaptive = match self.config.adaptive_bitrate {
+            Some(adaptive) => adaptive,
+            None => return,
+        };
 
-            self.state = PlayerState::Paused {
-                track_id,
-                play_request_id,
-                decoder: loaded_track.decoder,
-                audio_item: loaded_track.audio_item,
-                normalisation_data: loaded_track.normalisation_data,
-                normalisation_factor,
-                stream_loader_controller: loaded_track.stream_loader_controller,
-                duration_ms: loaded_track.duration_ms,
-                bytes_per_second: loaded_track.bytes_per_second,
-                stream_position_ms: loaded_track.stream_position_ms,
-                suggested_to_preload_next_track: false,
-                is_explicit: loaded_track.is_explicit,
-            };
+        let now = Instant::now();
+        self.last_stall = Some(now);
+        self.stalls
+            .retain(|stall| now.duration_since(*stall) < adaptive.window);
+        self.stalls.push_back(now);
+        if self.stalls.len() < adaptive.stalls as usize {
+            return;
+        }
 
-            self.send_event(PlayerEvent::Paused {
-                track_id,
+        let bitrate = self.reduced_bitrate.unwrap_or(self.config.bitrate);
+        if let Some(lower) = bitrate.lower() {
+            warn!(
+                "Playback keeps stalling, loading next tracks at {} kbps",
+                lower.kbps()
+            );
+            self.reduced_bitrate = Some(lower);
+            self.stalls.clear();
+            self.send_event(PlayerEvent::QualityReduced {
                 play_request_id,
-                position_ms,
+                track_id,
+                bitrate: lower,
             });
         }
     }
 
-    fn handle_command_load(
+    // Back to the configured bitrate once nothing stalled for the recovery time
+    fn restore_bitrate(&mut self) {
+        if self.reduced_bitrate.is_none() {
+            return;
+        }
+        // Also when adaptive bitrate was turned off since
+        let recovered = match (self.config.adaptive_bitrate, self.last_stall) {
+            (Some(adaptive), Some(stall)) => stall.elapsed() >= adaptive.recovery,
+            _ => true,
+        };
+        if recovered {
+            info!("No stalls for a while, back to the configured bitrate");
+            self.reduced_bitrate = None;
+        }
+    }
+
+    // Sends the position of a playing or paused track, when due or when forced.
+    fn send_position_checkpoint(&mut self, force: bool) {
+        if let PlayerState::Playing {
+            track_id,
+            play_request_id,
+            stream_position_ms,
+            ..
+        }
+        | PlayerState::Paused {
+            track_id,
+            play_request_id,
+            stream_position_ms,
+            ..
+        } = self.state
+        {
+            let due = self.state.is_playing()
+                && self
+                    .position_checkpoint_sent
+                    .map_or(true, |at| at.elapsed() >= POSITION_CHECKPOINT_INTERVAL);
+            if force || due {
+                self.position_checkpoint_sent = Some(Instant::now());
+                self.send_event(PlayerEvent::PositionCheckpoint {
+                    play_request_id,
+                    track_id,
+                    position_ms: stream_position_ms,
+                });
+            }
+        }
+    }
+
+    fn send_decode_stats(&mut self) {
+        if self.decode_stats.since.elapsed() < DECODE_STATS_INTERVAL {
+            return;
+        }
+        if let Some(event) = self.decode_stats.take() {
+            self.send_event(event);
+        }
+    }
+
+    fn send_buffer_progress(&mut self) {
+        let due = match self.buffer_progress_reported {
+            None => true,
+            Some((at, fraction)) => fraction < 1.0 && at.elapsed() >= BUFFER_PROGRESS_INTERVAL,
+        };
+        if !due {
+            return;
+        }
+
+        if let PlayerState::Playing {
+            track_id,
+            play_request_id,
+            ref stream_loader_controller,
+            ..
+        }
+        | PlayerState::Paused {
+            track_id,
+            play_request_id,
+            ref stream_loader_controller,
+            ..
+        } = self.state
+        {
+            let fraction = buffer_progress(stream_loader_controller);
+            let changed = self
+                .buffer_progress_reported
+                .map_or(true, |(_, reported)| reported != fraction);
+            self.buffer_progress_reported = Some((Instant::now(), fraction));
+
+            if changed {
+                let range_to_end_available = stream_loader_controller.range_to_end_available();
+                self.send_event(PlayerEvent::BufferProgress {
+                    play_request_id,
+                    track_id,
+                    fraction,
+                    range_to_end_available,
+                });
+            }
+        }
+    }
+
+    fn is_limiting(&self) -> bool {
+        self.normalisation_peak > LIMITER_ACTIVE_THRESHOLD_DB
+    }
+
+    fn send_limiter_state(&mut self) {
+        self.send_event(PlayerEvent::LimiterState {
+            active: self.is_limiting(),
+            strength_db: self.normalisation_peak,
+            factor: db_to_ratio(-self.normalisation_peak),
+        });
+    }
+
+    fn reset_limiter(&mut self) {
+        let was_limiting = self.is_limiting();
+
+        self.normalisation_integrator = 0.0;
+        self.normalisation_peak = 0.0;
+
+        if was_limiting {
+            self.send_limiter_state();
+        }
+    }
+
+    fn normalisation_factor(&self, mut data: NormalisationData) -> f64 {
+        let mut config = self.config.clone();
+        if config.normalisation_type == NormalisationType::Auto {
+            if self.auto_normalise_as_album {
+                config.normalisation_type = NormalisationType::Album;
+            } else {
+                config.normalisation_type = NormalisationType::Track;
+            }
+        };
+
+        if let Some(gain_db) = self.gain_override_db {
+            data.track_gain_db = gain_db;
+            data.album_gain_db = gain_db;
+        }
+
+        NormalisationData::get_factor(&config, data)
+    }
+
+    // Recomputes the factor of the current track after a normalisation setting changed
+    fn update_normalisation_factor(&mut self) {
+        let data = match self.state {
+            PlayerState::Playing {
+                normalisation_data, ..
+            }
+            | PlayerState::Paused {
+                normalisation_data, ..
+            } => normalisation_data,
+            _ => return,
+        };
+
+        let factor = self.normalisation_factor(data);
+        if let PlayerState::Playing {
+            ref mut normalisation_factor,
+            ..
+        }
+        | PlayerState::Paused {
+            ref mut normalisation_factor,
+            ..
+        } = self.state
+        {
+            *normalisation_factor = factor;
+        }
+    }
+
+    fn start_playback(
+        &mut self,
+        track_id: SpotifyId,
+        play_request_id: u64,
+        mut loaded_track: PlayerLoadedTrackData,
+        start_playback: bool,
+    ) {
+        if let Some(position_ms) = self.pending_seek_ms.take() {
+            // This may be blocking.
+            match loaded_track.decoder.seek(position_ms) {
+                Ok(new_position_ms) => loaded_track.stream_position_ms = new_position_ms,
+                Err(e) => error!("PlayerInternal::start_playback error seeking: {}", e),
+            }
+        }
+
+        let audio_item = Box::new(loaded_track.audio_item.clone());
+        let position_ms = loaded_track.stream_position_ms;
+
+        self.send_event(PlayerEvent::TrackChanged {
+            audio_item,
+            position_ms,
+        });
+        if let Some((from_track_id, to_track_id)) = self.transition.take() {
+            if to_track_id == track_id {
+                self.send_event(PlayerEvent::TransitionFinished {
+                    from_track_id,
+                    to_track_id,
+                });
+            }
+        }
+        self.output_format_reported = false;
+
+        self.gain_override_db = None;
+        self.buffer_progress_reported = None;
+        self.position_checkpoint_sent = None;
+        self.stalls.clear();
+        let normalisation_factor = self.normalisation_factor(loaded_track.normalisation_data);
+
+        if !loaded_track.decrypted {
+            self.send_event(PlayerEvent::AudioKeyUnavailable {
+                play_request_id,
+                track_id,
+            });
+        }
+
+        // Falls back to paused if the sink can't be started
+        if start_playback && self.ensure_sink_running() {
+            self.send_event(PlayerEvent::Playing {
+                track_id,
+                play_request_id,
+                position_ms,
+            });
+
+            self.state = PlayerState::Playing {
+                track_id,
+                play_request_id,
+                decoder: loaded_track.decoder,
+                audio_item: loaded_track.audio_item,
+                normalisation_data: loaded_track.normalisation_data,
+                normalisation_factor,
+                stream_loader_controller: loaded_track.stream_loader_controller,
+                duration_ms: loaded_track.duration_ms,
+                bytes_per_second: loaded_track.bytes_per_second,
+                stream_position_ms: loaded_track.stream_position_ms,
+                reported_nominal_start_time: Instant::now()
+                    .checked_sub(Duration::from_millis(position_ms as u64)),
+                suggested_to_preload_next_track: false,
+                is_explicit: loaded_track.is_explicit,
+                decrypted: loaded_track.decrypted,
+                alternatives_evaluated: loaded_track.alternatives_evaluated,
+            };
+        } else {
+            self.ensure_sink_stopped(false);
+
+            self.state = PlayerState::Paused {
+                track_id,
+                play_request_id,
+                decoder: loaded_track.decoder,
+                audio_item: loaded_track.audio_item,
+                normalisation_data: loaded_track.normalisation_data,
+                normalisation_factor,
+                stream_loader_controller: loaded_track.stream_loader_controller,
+                duration_ms: loaded_track.duration_ms,
+                bytes_per_second: loaded_track.bytes_per_second,
+                stream_position_ms: loaded_track.stream_position_ms,
+                suggested_to_preload_next_track: false,
+                is_explicit: loaded_track.is_explicit,
+                decrypted: loaded_track.decrypted,
+                alternatives_evaluated: loaded_track.alternatives_evaluated,
+            };
+
+            self.send_event(PlayerEvent::Paused {
+                track_id,
+                play_request_id,
+                position_ms,
+            });
+        }
+    }
+
+    fn handle_command_load(
         &mut self,
         track_id: SpotifyId,
         play_request_id_option: Option<u64>,
         play: bool,
         position_ms: u32,
     ) -> PlayerResult {
+        self.pending_seek_ms = None;
+        self.queue_end = None;
+        let position_ms = position_ms.max(self.config.trim_start_ms);
+
+        let loaded = self.is_loaded_at(track_id, play, position_ms);
+
+        // Loading the next track once the previous one ended continues the queue,
+        // any other load starts a new one
+        if !loaded && !matches!(self.state, PlayerState::EndOfTrack { .. }) {
+            self.queue_start = Some(track_id);
+        }
+
         let play_request_id =
             play_request_id_option.unwrap_or(self.play_request_id_generator.get());
 
         self.send_event(PlayerEvent::PlayRequestIdChanged { play_request_id });
 
+        // Some controllers repeat a load for what is already playing. Restarting playback
+        // would only cause an audible glitch, so only confirm the load for its request id.
+        if loaded {
+            debug!("Not reloading track that is already loaded at the requested position");
+            return self.confirm_load(play_request_id);
+        }
+
         if !self.config.gapless {
             self.ensure_sink_stopped(play);
         }
@@ -1846,6 +3239,8 @@ impl PlayerInternal {
                     duration_ms,
                     normalisation_data,
                     is_explicit,
+                    decrypted,
+                    alternatives_evaluated,
                     ..
                 }
                 | PlayerState::Paused {
@@ -1857,6 +3252,8 @@ impl PlayerInternal {
                     duration_ms,
                     normalisation_data,
                     is_explicit,
+                    decrypted,
+                    alternatives_evaluated,
                     ..
                 } = old_state
                 {
@@ -1869,6 +3266,8 @@ impl PlayerInternal {
                         duration_ms,
                         stream_position_ms,
                         is_explicit,
+                        decrypted,
+                        alternatives_evaluated,
                     };
 
                     self.preload = PlayerPreload::None;
@@ -1892,6 +3291,10 @@ impl PlayerInternal {
         } = self.preload
         {
             if track_id == loaded_track_id {
+                debug!(
+                    "Starting preloaded track <{}>",
+                    track_id.to_uri().unwrap_or_default()
+                );
                 let preload = std::mem::replace(&mut self.preload, PlayerPreload::None);
                 if let PlayerPreload::Ready {
                     track_id,
@@ -1953,6 +3356,61 @@ impl PlayerInternal {
         Ok(())
     }
 
+    fn is_loaded_at(&self, track_id: SpotifyId, play: bool, position_ms: u32) -> bool {
+        match self.state {
+            PlayerState::Playing {
+                track_id: current_track_id,
+                stream_position_ms,
+                ..
+            } => play && current_track_id == track_id && stream_position_ms == position_ms,
+            PlayerState::Paused {
+                track_id: current_track_id,
+                stream_position_ms,
+                ..
+            } => !play && current_track_id == track_id && stream_position_ms == position_ms,
+            _ => false,
+        }
+    }
+
+    // Moves the current track over to a new play request, as if it had just been loaded
+    fn confirm_load(&mut self, new_play_request_id: u64) -> PlayerResult {
+        match self.state {
+            PlayerState::Playing {
+                track_id,
+                ref mut play_request_id,
+                stream_position_ms,
+                ..
+            } => {
+                *play_request_id = new_play_request_id;
+                self.send_event(PlayerEvent::Playing {
+                    track_id,
+                    play_request_id: new_play_request_id,
+                    position_ms: stream_position_ms,
+                });
+            }
+            PlayerState::Paused {
+                track_id,
+                ref mut play_request_id,
+                stream_position_ms,
+                ..
+            } => {
+                *play_request_id = new_play_request_id;
+                self.send_event(PlayerEvent::Paused {
+                    track_id,
+                    play_request_id: new_play_request_id,
+                    position_ms: stream_position_ms,
+                });
+            }
+            _ => {
+                return Err(Error::internal(format!(
+                    "Player::confirm_load called from invalid state: {:?}",
+                    self.state
+                )))
+            }
+        }
+        Ok(())
+    }
+
     fn handle_command_preload(&mut self, track_id: SpotifyId) {
         debug!("Preloading track");
         let mut preload_track = true;
@@ -2007,24 +3465,19 @@ impl PlayerInternal {
     fn handle_command_seek(&mut self, position_ms: u32) -> PlayerResult {
         // When we are still loading, the user may immediately ask to
         // seek to another position yet the decoder won't be ready for
-        // that. In this case just restart the loading process but
-        // with the requested position.
-        if let PlayerState::Loading {
-            track_id,
-            play_request_id,
-            start_playback,
-            ..
-        } = self.state
-        {
-            return self.handle_command_load(
-                track_id,
-                Some(play_request_id),
-                start_playback,
-                position_ms,
+        // that. In this case remember the position and seek as soon as
+        // the track is loaded.
+        if let PlayerState::Loading { .. } = self.state {
+            debug!(
+                "Queueing seek to {} ms until the track is loaded",
+                position_ms
             );
+            self.pending_seek_ms = Some(position_ms);
+            return Ok(());
         }
+        self.sink_retry = None;
 
-        if let Some(decoder) = self.state.decoder() {
+        if let Some(decoder) = self.state.decoder()? {
             match decoder.seek(position_ms) {
                 Ok(new_position_ms) => {
                     if let PlayerState::Playing {
@@ -2070,6 +3523,26 @@ impl PlayerInternal {
         Ok(())
     }
 
+    // Seek to the start and play, reporting the track as playing from there even
+    // if it already was
+    fn handle_command_restart(&mut self) -> PlayerResult {
+        self.handle_command_seek(0)?;
+        match self.state {
+            PlayerState::Playing {
+                track_id,
+                play_request_id,
+                stream_position_ms,
+                ..
+            } => self.send_event(PlayerEvent::Playing {
+                track_id,
+                play_request_id,
+                position_ms: stream_position_ms,
+            }),
+            _ => self.handle_play(),
+        }
+        Ok(())
+    }
+
     fn handle_command(&mut self, cmd: PlayerCommand) -> PlayerResult {
         debug!("command={:?}", cmd);
         match cmd {
@@ -2083,6 +3556,8 @@ impl PlayerInternal {
 
             PlayerCommand::Seek(position_ms) => self.handle_command_seek(position_ms)?,
 
+            PlayerCommand::Restart => self.handle_command_restart()?,
+
             PlayerCommand::Play => self.handle_play(),
 
             PlayerCommand::Pause => self.handle_pause(),
@@ -2091,14 +3566,58 @@ impl PlayerInternal {
 
             PlayerCommand::SetSession(session) => self.session = session,
 
-            PlayerCommand::AddEventSender(sender) => self.event_senders.push(sender),
+            PlayerCommand::AddEventSender(sender) => self.event_fanout.add_sender(sender),
 
             PlayerCommand::SetSinkEventCallback(callback) => self.sink_event_callback = callback,
 
+            PlayerCommand::RegisterClock(callback) => self.clock_callback = Some(callback),
+
+            PlayerCommand::GetState(reply) => {
+                // An invalid state drops the reply, which reads as stopped
+                let mut snapshot = self.state.snapshot()?;
+                snapshot.ditherer = self.converter.ditherer_name();
+                snapshot.normalisation_method = self.config.normalisation_method;
+                let _ = reply.send(snapshot);
+            }
+
+            PlayerCommand::GetConfig(reply) => {
+                let _ = reply.send(self.config.clone());
+            }
+
+            PlayerCommand::GetSubscriberCount(reply) => self.event_fanout.subscriber_count(reply),
+
+            PlayerCommand::GetTimeUntilPreload(reply) => {
+                let time = match self.state {
+                    PlayerState::Playing {
+                        duration_ms,
+                        stream_position_ms,
+                        suggested_to_preload_next_track: false,
+                        ..
+                    } => Some(time_until_preload(
+                        duration_ms,
+                        stream_position_ms,
+                        self.config.preload_lead_time,
+                    )),
+                    _ => None,
+                };
+                let _ = reply.send(time);
+            }
+
             PlayerCommand::EmitVolumeChangedEvent(volume) => {
+                self.reported_volume = Some(volume);
                 self.send_event(PlayerEvent::VolumeChanged { volume })
             }
 
+            PlayerCommand::EmitMixerVolumeChangedEvent(volume) => {
+                // Spirc may have reported the same change already
+                if self.reported_volume != Some(volume) {
+                    self.reported_volume = Some(volume);
+                    self.send_event(PlayerEvent::VolumeChanged { volume })
+                }
+            }
+
+            PlayerCommand::ResyncState => self.handle_resync_state(),
+
             PlayerCommand::EmitRepeatChangedEvent(repeat) => {
                 self.send_event(PlayerEvent::RepeatChanged { repeat })
             }
@@ -2111,6 +3630,10 @@ impl PlayerInternal {
                 self.send_event(PlayerEvent::AutoPlayChanged { auto_play })
             }
 
+            PlayerCommand::EmitPlaybackCompleteEvent(context_uri) => {
+                self.send_event(PlayerEvent::PlaybackComplete { context_uri })
+            }
+
             PlayerCommand::EmitSessionClientChangedEvent {
                 client_id,
                 client_name,
@@ -2143,13 +3666,72 @@ impl PlayerInternal {
                 self.auto_normalise_as_album = setting
             }
 
-            PlayerCommand::EmitFilterExplicitContentChangedEvent(filter) => {
-                self.send_event(PlayerEvent::FilterExplicitContentChanged { filter });
+            PlayerCommand::SetGapless(gapless) => self.config.gapless = gapless,
 
-                if filter {
-                    if let PlayerState::Playing {
-                        track_id,
-                        play_request_id,
+            PlayerCommand::SetPreloadLeadTime(lead_time) => {
+                self.config.preload_lead_time = lead_time;
+                // Suggest the next track again once the new window is reached, if the
+                // current track is now outside of it
+                if let PlayerState::Playing {
+                    duration_ms,
+                    stream_position_ms,
+                    ref mut suggested_to_preload_next_track,
+                    ..
+                }
+                | PlayerState::Paused {
+                    duration_ms,
+                    stream_position_ms,
+                    ref mut suggested_to_preload_next_track,
+                    ..
+                } = self.state
+                {
+                    let remaining_ms = duration_ms.saturating_sub(stream_position_ms);
+                    if remaining_ms as u128 >= lead_time.as_millis() {
+                        *suggested_to_preload_next_track = false;
+                    }
+                }
+            }
+
+            PlayerCommand::SetBalance(balance) => {
+                self.config.balance = balance;
+                self.balance_filter.set_balance(balance);
+            }
+
+            PlayerCommand::SetDuck(ducked) => self.duck_filter.set_ducked(ducked),
+
+            PlayerCommand::SetLevels(enabled) => {
+                self.level_meter = enabled.then(|| LevelMeter::new(LEVELS_INTERVAL));
+            }
+
+            PlayerCommand::SetGainOverride(gain_db) => {
+                self.gain_override_db = gain_db;
+                self.update_normalisation_factor();
+            }
+
+            PlayerCommand::SetNormalisationMethod(method) => {
+                if method != self.config.normalisation_method {
+                    self.config.normalisation_method = method;
+                    self.reset_limiter();
+                    self.update_normalisation_factor();
+                }
+            }
+
+            PlayerCommand::SetDitherer(ditherer) => {
+                self.config.ditherer = ditherer;
+                self.converter = Converter::new(ditherer);
+                // Report the new ditherer with the next packet
+                self.output_format_reported = false;
+            }
+
+            PlayerCommand::ResetLimiter => self.reset_limiter(),
+
+            PlayerCommand::EmitFilterExplicitContentChangedEvent(filter) => {
+                self.send_event(PlayerEvent::FilterExplicitContentChanged { filter });
+
+                if filter {
+                    if let PlayerState::Playing {
+                        track_id,
+                        play_request_id,
                         is_explicit,
                         ..
                     }
@@ -2162,6 +3744,7 @@ impl PlayerInternal {
                     {
                         if is_explicit {
                             warn!("Currently loaded track is explicit, which client setting forbids -- skipping to next track.");
+                            self.send_event(PlayerEvent::ExplicitContentSkipped { track_id });
                             self.send_event(PlayerEvent::EndOfTrack {
                                 track_id,
                                 play_request_id,
@@ -2175,36 +3758,161 @@ impl PlayerInternal {
         Ok(())
     }
 
+    // Transient device errors are retried with backoff, restarting the sink in between.
+    // On failure the sink is stopped and the packet written again after `delay`, by
+    // the poll loop. Playback pauses once there are no retries left.
+    fn write_to_sink(
+        &mut self,
+        position_ms: u32,
+        packet: AudioPacket,
+        retries_left: u32,
+        delay: Duration,
+    ) {
+        match self.sink.write(&packet, &mut self.converter) {
+            Ok(()) => {
+                if let Some(clock) = &self.clock_callback {
+                    clock(position_ms as u64);
+                }
+                if !self.output_format_reported {
+                    self.send_output_format();
+                }
+            }
+            Err(e) if retries_left == 0 => {
+                error!("{}", e);
+                self.send_event(PlayerEvent::SinkError {
+                    message: e.to_string(),
+                });
+                self.handle_pause();
+            }
+            Err(e) => {
+                warn!("{}, retrying in {:?}", e, delay);
+                if let PlayerState::Playing {
+                    play_request_id, ..
+                } = self.state
+                {
+                    self.sink_retry = Some(SinkRetry {
+                        play_request_id,
+                        position_ms,
+                        packet,
+                        retries_left: retries_left - 1,
+                        delay: delay * 2,
+                        backoff: Box::pin(tokio::time::sleep(delay)),
+                    });
+                }
+                // Started again for the retry
+                self.ensure_sink_stopped(true);
+            }
+        }
+    }
+
+    // Writes the packet of a failed write again once its backoff has passed. Returns
+    // whether it is still waiting, which holds back the following packets.
+    fn poll_sink_retry(&mut self, cx: &mut Context<'_>) -> bool {
+        let mut retry = match self.sink_retry.take() {
+            Some(retry) => retry,
+            None => return false,
+        };
+        // Dropped when playback was paused, seeked or replaced since
+        match self.state {
+            PlayerState::Playing {
+                play_request_id, ..
+            } if play_request_id == retry.play_request_id => (),
+            _ => return false,
+        }
+        if retry.backoff.as_mut().poll(cx).is_pending() {
+            self.sink_retry = Some(retry);
+            return true;
+        }
+
+        if !self.ensure_sink_running() {
+            return false;
+        }
+        self.write_to_sink(
+            retry.position_ms,
+            retry.packet,
+            retry.retries_left,
+            retry.delay,
+        );
+        // Waits for the backoff of the next retry if this one failed as well
+        self.poll_sink_retry(cx)
+    }
+
+    // Something went wrong that the player can't recover from. Stop playback, tell
+    // listeners and let the poll loop end the player thread, rather than exiting the
+    // host process.
+    fn handle_fatal_error(&mut self, message: String) {
+        error!("{}", message);
+
+        if self.sink_status != SinkStatus::Closed {
+            if let Err(e) = self.sink.stop() {
+                error!("{}", e);
+            }
+            self.sink_status = SinkStatus::Closed;
+        }
+
+        self.state = PlayerState::Stopped;
+        self.preload = PlayerPreload::None;
+        self.send_event(PlayerEvent::FatalError { message });
+        self.failed = true;
+    }
+
+    // A transition failed halfway and left the state invalid. Rather than shutting
+    // down, drop whatever was loaded and carry on stopped.
+    fn reset_invalid_state(&mut self, message: String) {
+        error!("{}", message);
+
+        self.ensure_sink_stopped(false);
+        self.state = PlayerState::Stopped;
+        self.preload = PlayerPreload::None;
+        self.transition = None;
+        self.send_event(PlayerEvent::StateReset { message });
+    }
+
     fn send_event(&mut self, event: PlayerEvent) {
-        self.event_senders
-            .retain(|sender| sender.send(event.clone()).is_ok());
+        self.event_fanout.send(event);
     }
 
     fn load_track(
         &mut self,
         spotify_id: SpotifyId,
         position_ms: u32,
-    ) -> impl FusedFuture<Output = Result<PlayerLoadedTrackData, ()>> + Send + 'static {
+    ) -> impl FusedFuture<Output = LoadResult> + Send + 'static {
         // This method creates a future that returns the loaded stream and associated info.
         // Ideally all work should be done using asynchronous code. However, seek() on the
         // audio stream is implemented in a blocking fashion. Thus, we can't turn it into future
         // easily. Instead we spawn a thread to do the work and return a one-shot channel as the
         // future to work with.
 
+        let mut config = self.config.clone();
+        if let Some(bitrate) = self.reduced_bitrate {
+            config.bitrate = bitrate;
+        }
         let loader = PlayerTrackLoader {
             session: self.session.clone(),
-            config: self.config.clone(),
+            config,
+            normalisation_cache: self.normalisation_cache.clone(),
         };
 
+        self.spawn_load(move || async move { loader.load_track(spotify_id, position_ms).await })
+    }
+
+    // Runs a load on its own thread, giving up after the load timeout
+    fn spawn_load<F, L>(
+        &mut self,
+        load: L,
+    ) -> impl FusedFuture<Output = LoadResult> + Send + 'static
+    where
+        F: Future<Output = LoadResult>,
+        L: FnOnce() -> F + Send + 'static,
+    {
         let (result_tx, result_rx) = oneshot::channel();
 
+        let load_timeout = self.config.load_timeout;
         let load_handles_clone = self.load_handles.clone();
         let handle = tokio::runtime::Handle::current();
         let load_handle = thread::spawn(move || {
-            let data = handle.block_on(loader.load_track(spotify_id, position_ms));
-            if let Some(data) = data {
-                let _ = result_tx.send(data);
-            }
+            let data = handle.block_on(with_load_timeout(load(), load_timeout));
+            let _ = result_tx.send(data);
 
             let mut load_handles = load_handles_clone.lock();
             load_handles.remove(&thread::current().id());
@@ -2213,7 +3921,7 @@ impl PlayerInternal {
         let mut load_handles = self.load_handles.lock();
         load_handles.insert(load_handle.thread().id(), load_handle);
 
-        result_rx.map_err(|_| ())
+        result_rx.map(|data| data.unwrap_or(Err(UnavailableReason::LoadFailed)))
     }
 
     fn preload_data_before_playback(&mut self) -> PlayerResult {
@@ -2244,6 +3952,9 @@ impl Drop for PlayerInternal {
     fn drop(&mut self) {
         debug!("drop PlayerInternal[{}]", self.player_id);
 
+        // Release the device now rather than whenever the sink itself is dropped
+        self.ensure_sink_stopped(false);
+
         let handles: Vec<thread::JoinHandle<()>> = {
             // waiting for the thread while holding the mutex would result in a deadlock
             let mut load_handles = self.load_handles.lock();
@@ -2281,19 +3992,53 @@ impl fmt::Debug for PlayerCommand {
             PlayerCommand::Pause => f.debug_tuple("Pause").finish(),
             PlayerCommand::Stop => f.debug_tuple("Stop").finish(),
             PlayerCommand::Seek(position) => f.debug_tuple("Seek").field(&position).finish(),
+            PlayerCommand::Restart => f.debug_tuple("Restart").finish(),
             PlayerCommand::SetSession(_) => f.debug_tuple("SetSession").finish(),
             PlayerCommand::AddEventSender(_) => f.debug_tuple("AddEventSender").finish(),
             PlayerCommand::SetSinkEventCallback(_) => {
                 f.debug_tuple("SetSinkEventCallback").finish()
             }
+            PlayerCommand::RegisterClock(_) => f.debug_tuple("RegisterClock").finish(),
+            PlayerCommand::GetState(_) => f.debug_tuple("GetState").finish(),
+            PlayerCommand::GetTimeUntilPreload(_) => f.debug_tuple("GetTimeUntilPreload").finish(),
+            PlayerCommand::GetConfig(_) => f.debug_tuple("GetConfig").finish(),
+            PlayerCommand::GetSubscriberCount(_) => f.debug_tuple("GetSubscriberCount").finish(),
             PlayerCommand::EmitVolumeChangedEvent(volume) => f
                 .debug_tuple("EmitVolumeChangedEvent")
                 .field(&volume)
                 .finish(),
+            PlayerCommand::EmitMixerVolumeChangedEvent(volume) => f
+                .debug_tuple("EmitMixerVolumeChangedEvent")
+                .field(&volume)
+                .finish(),
             PlayerCommand::SetAutoNormaliseAsAlbum(setting) => f
                 .debug_tuple("SetAutoNormaliseAsAlbum")
                 .field(&setting)
                 .finish(),
+            PlayerCommand::SetGapless(gapless) => {
+                f.debug_tuple("SetGapless").field(&gapless).finish()
+            }
+            PlayerCommand::SetPreloadLeadTime(lead_time) => f
+                .debug_tuple("SetPreloadLeadTime")
+                .field(&lead_time)
+                .finish(),
+            PlayerCommand::SetGainOverride(gain_db) => {
+                f.debug_tuple("SetGainOverride").field(&gain_db).finish()
+            }
+            PlayerCommand::SetNormalisationMethod(method) => f
+                .debug_tuple("SetNormalisationMethod")
+                .field(&method)
+                .finish(),
+            PlayerCommand::SetBalance(balance) => {
+                f.debug_tuple("SetBalance").field(&balance).finish()
+            }
+            PlayerCommand::SetDuck(ducked) => f.debug_tuple("SetDuck").field(&ducked).finish(),
+            PlayerCommand::SetLevels(enabled) => {
+                f.debug_tuple("SetLevels").field(&enabled).finish()
+            }
+            PlayerCommand::SetDitherer(_) => f.debug_tuple("SetDitherer").finish(),
+            PlayerCommand::ResetLimiter => f.debug_tuple("ResetLimiter").finish(),
+            PlayerCommand::ResyncState => f.debug_tuple("ResyncState").finish(),
             PlayerCommand::EmitFilterExplicitContentChangedEvent(filter) => f
                 .debug_tuple("EmitFilterExplicitContentChangedEvent")
                 .field(&filter)
@@ -2338,6 +4083,10 @@ impl fmt::Debug for PlayerCommand {
                 .debug_tuple("EmitAutoPlayChangedEvent")
                 .field(&auto_play)
                 .finish(),
+            PlayerCommand::EmitPlaybackCompleteEvent(context_uri) => f
+                .debug_tuple("EmitPlaybackCompleteEvent")
+                .field(&context_uri)
+                .finish(),
         }
     }
 }
@@ -2446,3 +4195,1755 @@ where
         Some(self.length)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        audio_backend::{MockSink, MockSinkFaults, MockSinkLog},
+        config::AdaptiveBitrate,
+        core::{cache::Cache, SessionConfig},
+        decoder::DecoderResult,
+        metadata::{artist::ArtistsWithRole, audio::UniqueFields},
+        mixer::NoOpVolume,
+    };
+
+    const TRACK_DURATION_MS: u32 = 10_000;
+    const PACKET_DURATION_MS: u32 = 20;
+
+    // Produces silent packets at a fixed rate, so tests never touch the network or a codec.
+    struct MockDecoder {
+        position_ms: u32,
+    }
+
+    impl AudioDecoder for MockDecoder {
+        fn seek(&mut self, position_ms: u32) -> Result<u32, DecoderError> {
+            self.position_ms = position_ms.min(TRACK_DURATION_MS);
+            Ok(self.position_ms)
+        }
+
+        fn next_packet(&mut self) -> DecoderResult<Option<(AudioPacketPosition, AudioPacket)>> {
+            if self.position_ms >= TRACK_DURATION_MS {
+                return Ok(None);
+            }
+            let position = AudioPacketPosition {
+                position_ms: self.position_ms,
+                skipped: false,
+            };
+            self.position_ms += PACKET_DURATION_MS;
+            let samples = (SAMPLES_PER_SECOND * PACKET_DURATION_MS / 1000) as usize * 2;
+            Ok(Some((position, AudioPacket::Samples(vec![0.0; samples]))))
+        }
+    }
+
+    struct Harness {
+        internal: PlayerInternal,
+        sink: MockSink,
+        events: PlayerEventChannel,
+        // The poll loop stops once no one can send commands anymore
+        _commands: mpsc::UnboundedSender<PlayerCommand>,
+        // `Session` captures the runtime handle on creation, so keep it alive.
+        runtime: tokio::runtime::Runtime,
+    }
+
+    impl Harness {
+        fn new() -> Self {
+            let runtime = tokio::runtime::Runtime::new().expect("Failed to create Tokio runtime");
+            let session = {
+                let _guard = runtime.enter();
+                Session::new(SessionConfig::default(), None)
+            };
+            let sink = MockSink::new();
+            let (command_sender, commands) = mpsc::unbounded_channel();
+            let (event_sender, events) = player_event_channel(None);
+            let config = PlayerConfig::default();
+
+            let internal = PlayerInternal {
+                session,
+                converter: Converter::new(config.ditherer),
+                config,
+                commands,
+                load_handles: Arc::new(Mutex::new(HashMap::new())),
+                normalisation_cache: Arc::new(Mutex::new(NormalisationCache::default())),
+
+                state: PlayerState::Stopped,
+                preload: PlayerPreload::None,
+                sink: Box::new(sink.clone()),
+                sink_status: SinkStatus::Closed,
+                sink_event_callback: None,
+                clock_callback: None,
+                output_format_reported: false,
+                failed: false,
+                transition: None,
+                pending_seek_ms: None,
+                sink_retry: None,
+                queue_end: None,
+                gain_override_db: None,
+                buffer_progress_reported: None,
+                position_checkpoint_sent: None,
+                reduced_bitrate: None,
+                stalls: VecDeque::new(),
+                last_stall: None,
+                decode_stats: DecodeStats::new(),
+                reported_volume: None,
+                stopped_track: None,
+                queue_start: None,
+                volume_getter: Box::new(NoOpVolume),
+                audio_filter: None,
+                balance_filter: BalanceFilter::new(0.0),
+                duck_filter: DuckFilter::from_config(&PlayerConfig::default()),
+                level_meter: None,
+                event_fanout: EventFanout::Inline(vec![event_sender]),
+
+                normalisation_peak: 0.0,
+                normalisation_integrator: 0.0,
+
+                auto_normalise_as_album: false,
+
+                player_id: 0,
+                play_request_id_generator: SeqGenerator::new(0),
+            };
+
+            Self {
+                internal,
+                sink,
+                events,
+                _commands: command_sender,
+                runtime,
+            }
+        }
+
+        // Hands the player a fully loaded track, as if `PlayerTrackLoader` had finished preloading it.
+        fn inject_track(&mut self, track_id: SpotifyId) {
+            self.internal.preload = PlayerPreload::Ready {
+                track_id,
+                loaded_track: Box::new(loaded_track(track_id)),
+            };
+        }
+
+        fn command(&mut self, cmd: PlayerCommand) {
+            self.internal.handle_command(cmd).unwrap();
+        }
+
+        fn drain_events(&mut self) -> Vec<PlayerEvent> {
+            let mut events = vec![];
+            while let Some(event) = self.events.try_recv() {
+                events.push(event);
+            }
+            events
+        }
+
+        fn sink_log(&self) -> MockSinkLog {
+            self.sink.log()
+        }
+    }
+
+    // A fully loaded track, as `PlayerTrackLoader` would produce it.
+    fn loaded_track(track_id: SpotifyId) -> PlayerLoadedTrackData {
+        // Any readable file gives a controller that reports all data as available.
+        let file = std::fs::File::open(std::env::current_exe().unwrap()).unwrap();
+        let stream_loader_controller = AudioFile::Cached(file)
+            .get_stream_loader_controller()
+            .unwrap();
+
+        let audio_item = AudioItem {
+            track_id,
+            uri: track_id.to_uri().unwrap(),
+            files: AudioFiles::default(),
+            name: "Test Track".to_string(),
+            covers: vec![],
+            language: vec![],
+            duration_ms: TRACK_DURATION_MS,
+            is_explicit: false,
+            availability: Ok(()),
+            alternatives: None,
+            unique_fields: UniqueFields::Track {
+                artists: ArtistsWithRole::default(),
+                album: "Test Album".to_string(),
+                album_artists: vec![],
+                popularity: 0,
+                number: 1,
+                disc_number: 1,
+            },
+        };
+
+        PlayerLoadedTrackData {
+            decoder: Box::new(MockDecoder { position_ms: 0 }),
+            normalisation_data: NormalisationData::default(),
+            stream_loader_controller,
+            audio_item,
+            bytes_per_second: 40_000,
+            duration_ms: TRACK_DURATION_MS,
+            stream_position_ms: 0,
+            is_explicit: false,
+            decrypted: true,
+            alternatives_evaluated: 0,
+        }
+    }
+
+    fn test_track() -> SpotifyId {
+        SpotifyId::from_uri("spotify:track:4uLU6hMCjMI75M1A2tKUQC").unwrap()
+    }
+
+    fn load(harness: &mut Harness, play: bool) -> SpotifyId {
+        let track_id = test_track();
+        harness.inject_track(track_id);
+        harness.command(PlayerCommand::Load {
+            track_id,
+            play,
+            position_ms: 0,
+        });
+        track_id
+    }
+
+    #[test]
+    fn downmix_averages_channels() {
+        let mut samples = [1.0, 0.0, -0.5, 0.5, 0.25, 0.75];
+        downmix_to_mono(&mut samples);
+        assert_eq!(samples, [0.5, 0.5, 0.0, 0.0, 0.5, 0.5]);
+    }
+
+    #[test]
+    fn load_and_play_starts_sink() {
+        let mut harness = Harness::new();
+        let track_id = load(&mut harness, true);
+
+        assert!(harness.internal.state.is_playing());
+        let events = harness.drain_events();
+        assert!(matches!(
+            events[..],
+            [
+                PlayerEvent::PlayRequestIdChanged { .. },
+                PlayerEvent::TrackChanged { .. },
+                PlayerEvent::SinkStatusChanged {
+                    status: SinkStatus::Running,
+                },
+                PlayerEvent::Playing {
+                    track_id: id,
+                    position_ms: 0,
+                    ..
+                },
+            ] if id == track_id
+        ));
+        assert!(harness.sink_log().running);
+    }
+
+    #[test]
+    fn load_paused_keeps_sink_stopped() {
+        let mut harness = Harness::new();
+        load(&mut harness, false);
+
+        assert!(matches!(harness.internal.state, PlayerState::Paused { .. }));
+        let events = harness.drain_events();
+        assert!(matches!(events.last(), Some(PlayerEvent::Paused { .. })));
+        assert_eq!(harness.sink_log().starts, 0);
+    }
+
+    // An Ogg page that doesn't start a stream, which the Ogg reader doesn't support
+    fn unsupported_ogg_file() -> Vec<u8> {
+        let body = b"not vorbis";
+        let mut page = b"OggS".to_vec();
+        page.extend_from_slice(&[0; 22]);
+        page.push(1);
+        page.push(body.len() as u8);
+        page.extend_from_slice(body);
+
+        let mut crc: u32 = 0;
+        for byte in &page {
+            crc ^= (*byte as u32) << 24;
+            for _ in 0..8 {
+                crc = if crc & 0x8000_0000 != 0 {
+                    (crc << 1) ^ 0x04c1_1db7
+                } else {
+                    crc << 1
+                };
+            }
+        }
+        page[22..26].copy_from_slice(&crc.to_le_bytes());
+
+        // Spotify's own header comes first
+        let mut file = vec![0; SPOTIFY_OGG_HEADER_END as usize];
+        file.extend(page);
+        file
+    }
+
+    // Silent 128 kbps MPEG-1 Layer III frames
+    fn mp3_file() -> Vec<u8> {
+        let mut frame = vec![0xff, 0xfb, 0x90, 0x64];
+        frame.resize(417, 0);
+        frame.repeat(20)
+    }
+
+    #[test]
+    fn load_track_falls_through_unsupported_codecs() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let cache_dir =
+            std::env::temp_dir().join(format!("librespot-playback-test-{}", std::process::id()));
+        let cache = Cache::new(None, None, Some(&cache_dir), None).unwrap();
+        let ogg_id = FileId([1; 20]);
+        let mp3_id = FileId([2; 20]);
+        cache
+            .save_file(ogg_id, &mut &unsupported_ogg_file()[..])
+            .unwrap();
+        cache.save_file(mp3_id, &mut &mp3_file()[..]).unwrap();
+
+        let session = {
+            let _guard = runtime.enter();
+            Session::new(SessionConfig::default(), Some(cache))
+        };
+        let loader = PlayerTrackLoader {
+            session,
+            config: PlayerConfig {
+                bitrate: Bitrate::Bitrate320,
+                ..Default::default()
+            },
+            normalisation_cache: Arc::new(Mutex::new(NormalisationCache::default())),
+        };
+
+        // Ogg Vorbis 320 is preferred, but can't be decoded
+        let track_id = test_track();
+        let mut audio_item = loaded_track(track_id).audio_item;
+        audio_item
+            .files
+            .insert(AudioFileFormat::OGG_VORBIS_320, ogg_id);
+        audio_item.files.insert(AudioFileFormat::MP3_256, mp3_id);
+        let loaded = runtime.block_on(loader.load_audio_item(track_id, audio_item, 0, 0));
+        std::fs::remove_dir_all(&cache_dir).ok();
+
+        let loaded = loaded.unwrap();
+        assert_eq!(
+            loaded.bytes_per_second,
+            loader.stream_data_rate(AudioFileFormat::MP3_256)
+        );
+    }
+
+    #[test]
+    fn repeated_load_is_only_confirmed() {
+        let mut harness = Harness::new();
+        let track_id = load(&mut harness, true);
+        harness.drain_events();
+
+        harness.command(PlayerCommand::Load {
+            track_id,
+            play: true,
+            position_ms: 0,
+        });
+        // Only confirmed for the new request
+        let play_request_id = match harness.drain_events()[..] {
+            [PlayerEvent::PlayRequestIdChanged { play_request_id }, PlayerEvent::Playing {
+                play_request_id: playing_id,
+                position_ms: 0,
+                ..
+            }] if playing_id == play_request_id => play_request_id,
+            ref events => panic!("Unexpected events {events:?}"),
+        };
+        assert!(matches!(
+            harness.internal.state,
+            PlayerState::Playing { play_request_id: id, .. } if id == play_request_id
+        ));
+        assert_eq!(harness.sink_log().starts, 1);
+
+        // A different position is a real seek.
+        harness.command(PlayerCommand::Load {
+            track_id,
+            play: true,
+            position_ms: 2_000,
+        });
+        assert!(matches!(
+            harness.drain_events().last(),
+            Some(PlayerEvent::Playing {
+                position_ms: 2_000,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn stop_fades_out_while_playing() {
+        let stop = |stop_fade_ms, play| {
+            let mut harness = Harness::new();
+            harness.internal.config.stop_fade_ms = stop_fade_ms;
+            load(&mut harness, play);
+            harness.command(PlayerCommand::Stop);
+            assert!(matches!(harness.internal.state, PlayerState::Stopped));
+            harness.sink_log().samples
+        };
+
+        // Packets are written until the fade is complete
+        let fade_samples = SAMPLES_PER_SECOND as usize / 10;
+        let faded = stop(100, true);
+        assert!(faded >= fade_samples);
+        assert!(faded < 2 * fade_samples);
+        assert_eq!(stop(0, true), 0);
+        assert_eq!(stop(100, false), 0);
+    }
+
+    #[test]
+    fn restart_plays_from_start() {
+        for play in [true, false] {
+            let mut harness = Harness::new();
+            load(&mut harness, play);
+            harness.command(PlayerCommand::Seek(4_000));
+            harness.drain_events();
+
+            harness.command(PlayerCommand::Restart);
+            assert!(matches!(
+                harness.internal.state,
+                PlayerState::Playing {
+                    stream_position_ms: 0,
+                    ..
+                }
+            ));
+            assert!(matches!(
+                harness.drain_events().last(),
+                Some(PlayerEvent::Playing { position_ms: 0, .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn pause_and_resume() {
+        let mut harness = Harness::new();
+        load(&mut harness, true);
+        harness.drain_events();
+
+        harness.command(PlayerCommand::Pause);
+        assert!(matches!(harness.internal.state, PlayerState::Paused { .. }));
+        assert!(!harness.sink_log().running);
+        assert!(matches!(
+            harness.drain_events()[..],
+            [
+                PlayerEvent::SinkStatusChanged {
+                    status: SinkStatus::Closed,
+                },
+                PlayerEvent::Paused { .. },
+                PlayerEvent::PositionCheckpoint { .. },
+            ]
+        ));
+
+        harness.command(PlayerCommand::Play);
+        assert!(harness.internal.state.is_playing());
+        assert!(harness.sink_log().running);
+        assert!(matches!(
+            harness.drain_events()[..],
+            [
+                PlayerEvent::SinkStatusChanged {
+                    status: SinkStatus::Running,
+                },
+                PlayerEvent::Playing { .. },
+            ]
+        ));
+    }
+
+    #[test]
+    fn position_checkpoints_follow_playback() {
+        let mut harness = Harness::new();
+        load(&mut harness, true);
+        harness.drain_events();
+
+        let checkpoints = |harness: &mut Harness| -> Vec<u32> {
+            harness
+                .drain_events()
+                .into_iter()
+                .filter_map(|e| match e {
+                    PlayerEvent::PositionCheckpoint { position_ms, .. } => Some(position_ms),
+                    _ => None,
+                })
+                .collect()
+        };
+
+        // The first one right away, then once per interval
+        harness.command(PlayerCommand::Seek(3_000));
+        harness.internal.send_position_checkpoint(false);
+        harness.internal.send_position_checkpoint(false);
+        assert_eq!(checkpoints(&mut harness), [3_000]);
+
+        harness.internal.position_checkpoint_sent =
+            Some(Instant::now() - POSITION_CHECKPOINT_INTERVAL);
+        harness.internal.send_position_checkpoint(false);
+        assert_eq!(checkpoints(&mut harness), [3_000]);
+
+        // While paused only pausing and stopping send one
+        harness.command(PlayerCommand::Seek(4_000));
+        harness.command(PlayerCommand::Pause);
+        assert_eq!(checkpoints(&mut harness), [4_000]);
+        harness.internal.position_checkpoint_sent =
+            Some(Instant::now() - POSITION_CHECKPOINT_INTERVAL);
+        harness.internal.send_position_checkpoint(false);
+        assert!(checkpoints(&mut harness).is_empty());
+        harness.command(PlayerCommand::Stop);
+        assert_eq!(checkpoints(&mut harness), [4_000]);
+    }
+
+    #[test]
+    fn repeated_stalls_lower_bitrate() {
+        let mut harness = Harness::new();
+        harness.internal.config.bitrate = Bitrate::Bitrate320;
+        harness.internal.config.adaptive_bitrate = Some(AdaptiveBitrate {
+            stalls: 2,
+            ..Default::default()
+        });
+        let track_id = load(&mut harness, true);
+        harness.drain_events();
+        let reduced = |harness: &mut Harness| -> Vec<Bitrate> {
+            harness
+                .drain_events()
+                .into_iter()
+                .filter_map(|e| match e {
+                    PlayerEvent::QualityReduced { bitrate, .. } => Some(bitrate),
+                    _ => None,
+                })
+                .collect()
+        };
+
+        harness.internal.handle_stall(track_id, 1);
+        assert!(reduced(&mut harness).is_empty());
+        harness.internal.handle_stall(track_id, 1);
+        assert_eq!(reduced(&mut harness), [Bitrate::Bitrate160]);
+        for _ in 0..4 {
+            harness.internal.handle_stall(track_id, 1);
+        }
+        assert_eq!(reduced(&mut harness), [Bitrate::Bitrate96]);
+        harness.internal.handle_stall(track_id, 1);
+        harness.internal.handle_stall(track_id, 1);
+        assert!(reduced(&mut harness).is_empty());
+
+        // Recovered after a while without stalls
+        harness.internal.restore_bitrate();
+        assert_eq!(harness.internal.reduced_bitrate, Some(Bitrate::Bitrate96));
+        harness.internal.last_stall = Some(Instant::now() - AdaptiveBitrate::default().recovery);
+        harness.internal.restore_bitrate();
+        assert_eq!(harness.internal.reduced_bitrate, None);
+
+        // Off by default
+        let mut harness = Harness::new();
+        for _ in 0..10 {
+            harness.internal.handle_stall(track_id, 1);
+        }
+        assert!(reduced(&mut harness).is_empty());
+    }
+
+    #[test]
+    fn seek_reports_new_position() {
+        let mut harness = Harness::new();
+        load(&mut harness, false);
+        harness.drain_events();
+
+        harness.command(PlayerCommand::Seek(4_000));
+        assert!(matches!(
+            harness.drain_events()[..],
+            [PlayerEvent::Seeked {
+                position_ms: 4_000,
+                ..
+            }]
+        ));
+        assert!(matches!(
+            harness.internal.state,
+            PlayerState::Paused {
+                stream_position_ms: 4_000,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn stop_closes_sink() {
+        let mut harness = Harness::new();
+        load(&mut harness, true);
+        harness.drain_events();
+
+        harness.command(PlayerCommand::Stop);
+        assert!(harness.internal.state.is_stopped());
+        assert!(matches!(
+            harness.drain_events()[..],
+            [
+                PlayerEvent::PositionCheckpoint { .. },
+                PlayerEvent::SinkStatusChanged {
+                    status: SinkStatus::Closed,
+                },
+                PlayerEvent::Stopped { .. },
+            ]
+        ));
+        let log = harness.sink_log();
+        assert!(!log.running);
+        assert_eq!(log.stops, 1);
+    }
+
+    #[test]
+    fn clock_follows_written_packets() {
+        let mut harness = Harness::new();
+        let ticks = Arc::new(Mutex::new(Vec::new()));
+        let clock_ticks = ticks.clone();
+        harness.command(PlayerCommand::RegisterClock(Box::new(move |position_ms| {
+            clock_ticks.lock().push(position_ms)
+        })));
+        load(&mut harness, true);
+
+        for _ in 0..3 {
+            let packet = match &mut harness.internal.state {
+                PlayerState::Playing { decoder, .. } => decoder.next_packet().unwrap(),
+                _ => unreachable!(),
+            };
+            harness.internal.handle_packet(packet, 1.0);
+        }
+
+        assert_eq!(*ticks.lock(), [0, 20, 40]);
+        assert_eq!(harness.sink_log().packets, 3);
+    }
+
+    #[test]
+    fn output_format_reported_once_per_track() {
+        let mut harness = Harness::new();
+        load(&mut harness, true);
+        harness.drain_events();
+
+        for _ in 0..2 {
+            let packet = match &mut harness.internal.state {
+                PlayerState::Playing { decoder, .. } => decoder.next_packet().unwrap(),
+                _ => unreachable!(),
+            };
+            harness.internal.handle_packet(packet, 1.0);
+        }
+
+        // The mock sink takes samples as they are, without conversion.
+        assert!(matches!(
+            harness.drain_events()[..],
+            [PlayerEvent::OutputFormat { format: None, .. }]
+        ));
+    }
+
+    #[test]
+    fn state_snapshot() {
+        let mut harness = Harness::new();
+        let (tx, mut rx) = oneshot::channel();
+        harness.command(PlayerCommand::GetState(tx));
+        assert_eq!(rx.try_recv().unwrap().status, PlaybackStatus::Stopped);
+
+        let track_id = load(&mut harness, false);
+        harness.command(PlayerCommand::Seek(1_000));
+        let (tx, mut rx) = oneshot::channel();
+        harness.command(PlayerCommand::GetState(tx));
+        let snapshot = rx.try_recv().unwrap();
+        assert_eq!(snapshot.status, PlaybackStatus::Paused);
+        assert_eq!(snapshot.track_id, Some(track_id));
+        assert_eq!(snapshot.position_ms, 1_000);
+        assert_eq!(snapshot.duration_ms, TRACK_DURATION_MS);
+    }
+
+    #[test]
+    fn track_without_audio_key_is_reported() {
+        let mut harness = Harness::new();
+        let track_id = test_track();
+        let loaded_track = PlayerLoadedTrackData {
+            decrypted: false,
+            ..loaded_track(track_id)
+        };
+        harness
+            .internal
+            .start_playback(track_id, 1, loaded_track, true);
+
+        assert!(harness.drain_events().iter().any(|event| matches!(
+            event,
+            PlayerEvent::AudioKeyUnavailable { track_id: id, .. } if *id == track_id
+        )));
+        let (tx, mut rx) = oneshot::channel();
+        harness.command(PlayerCommand::GetState(tx));
+        assert!(!rx.try_recv().unwrap().decrypted);
+    }
+
+    #[test]
+    fn snapshot_reports_alternative_played() {
+        let mut harness = Harness::new();
+        let track_id = test_track();
+        let alternative_id = SpotifyId::from_uri("spotify:track:1301WleyT98MSxVHPZCA6M").unwrap();
+        let loaded_track = PlayerLoadedTrackData {
+            alternatives_evaluated: 2,
+            ..loaded_track(alternative_id)
+        };
+        harness
+            .internal
+            .start_playback(track_id, 1, loaded_track, true);
+
+        let (tx, mut rx) = oneshot::channel();
+        harness.command(PlayerCommand::GetState(tx));
+        let snapshot = rx.try_recv().unwrap();
+        assert_eq!(snapshot.track_id, Some(track_id));
+        assert_eq!(snapshot.source_track_id, Some(alternative_id));
+        assert_eq!(snapshot.alternatives_evaluated, 2);
+    }
+
+    #[test]
+    fn explicit_track_is_skipped_when_filtered() {
+        let mut harness = Harness::new();
+        let track_id = load(&mut harness, true);
+        if let PlayerState::Playing {
+            ref mut is_explicit,
+            ..
+        } = harness.internal.state
+        {
+            *is_explicit = true;
+        }
+        harness.drain_events();
+
+        harness.command(PlayerCommand::EmitFilterExplicitContentChangedEvent(true));
+        assert!(matches!(
+            harness.drain_events()[..],
+            [
+                PlayerEvent::FilterExplicitContentChanged { filter: true },
+                PlayerEvent::ExplicitContentSkipped { track_id: skipped },
+                PlayerEvent::EndOfTrack { .. },
+            ] if skipped == track_id
+        ));
+    }
+
+    #[test]
+    fn seek_while_loading_is_applied_on_start() {
+        let mut harness = Harness::new();
+        let track_id = test_track();
+        harness.internal.state = PlayerState::Loading {
+            track_id,
+            play_request_id: 1,
+            start_playback: true,
+            loader: Box::pin(future::pending()),
+        };
+
+        harness.command(PlayerCommand::Seek(4_000));
+        assert!(matches!(
+            harness.internal.state,
+            PlayerState::Loading { .. }
+        ));
+
+        // As if the loader finished
+        harness
+            .internal
+            .start_playback(track_id, 1, loaded_track(track_id), true);
+
+        assert!(matches!(
+            harness.internal.state,
+            PlayerState::Playing {
+                stream_position_ms: 4_000,
+                ..
+            }
+        ));
+        assert!(harness.drain_events().iter().any(|event| matches!(
+            event,
+            PlayerEvent::Playing {
+                position_ms: 4_000,
+                ..
+            }
+        )));
+    }
+
+    #[test]
+    fn pause_while_loading_starts_paused() {
+        let mut harness = Harness::new();
+        let track_id = test_track();
+        harness.internal.state = PlayerState::Loading {
+            track_id,
+            play_request_id: 1,
+            start_playback: true,
+            loader: Box::pin(future::pending()),
+        };
+
+        harness.command(PlayerCommand::Pause);
+        let start_playback = match harness.internal.state {
+            PlayerState::Loading { start_playback, .. } => start_playback,
+            _ => panic!("Pause ended the load"),
+        };
+
+        // As if the loader finished
+        harness
+            .internal
+            .start_playback(track_id, 1, loaded_track(track_id), start_playback);
+
+        assert!(matches!(harness.internal.state, PlayerState::Paused { .. }));
+        assert!(harness
+            .drain_events()
+            .iter()
+            .all(|event| !matches!(event, PlayerEvent::Playing { .. })));
+    }
+
+    #[test]
+    fn stalled_load_times_out() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let data = runtime.block_on(with_load_timeout(
+            future::pending::<LoadResult>(),
+            Duration::from_millis(10),
+        ));
+        assert!(matches!(data, Err(UnavailableReason::Timeout)));
+    }
+
+    #[test]
+    fn stalled_load_is_reported_unavailable() {
+        let mut harness = Harness::new();
+        harness.internal.config.load_timeout = Duration::from_millis(10);
+        let track_id = test_track();
+        let loader = {
+            let _guard = harness.runtime.enter();
+            harness.internal.spawn_load(future::pending)
+        };
+        harness.internal.state = PlayerState::Loading {
+            track_id,
+            play_request_id: 1,
+            start_playback: true,
+            loader: Box::pin(loader),
+        };
+
+        let Harness {
+            internal,
+            events,
+            runtime,
+            ..
+        } = &mut harness;
+        let unavailable = runtime.block_on(async {
+            let poll_loop = future::poll_fn(|cx| {
+                assert!(Pin::new(&mut *internal).poll(cx).is_pending());
+                match events.try_recv() {
+                    Some(event @ PlayerEvent::Unavailable { .. }) => Poll::Ready(event),
+                    _ => Poll::Pending,
+                }
+            });
+            tokio::time::timeout(Duration::from_secs(5), poll_loop).await
+        });
+        assert!(matches!(
+            unavailable,
+            Ok(PlayerEvent::Unavailable {
+                track_id: id,
+                play_request_id: 1,
+                reason: UnavailableReason::Timeout,
+            }) if id == track_id
+        ));
+    }
+
+    #[test]
+    fn market_restrictions_are_reported_as_region() {
+        for reason in [
+            UnavailabilityReason::Blacklisted,
+            UnavailabilityReason::NotWhitelisted,
+        ] {
+            assert_eq!(UnavailableReason::from(reason), UnavailableReason::Region);
+        }
+        assert_eq!(
+            UnavailableReason::from(UnavailabilityReason::Embargo),
+            UnavailableReason::Embargo
+        );
+    }
+
+    #[test]
+    fn set_ditherer_rebuilds_converter() {
+        let mut harness = Harness::new();
+        load(&mut harness, true);
+
+        harness.command(PlayerCommand::SetDitherer(Some(
+            crate::dither::mk_ditherer::<crate::dither::GaussianDitherer>,
+        )));
+        assert_eq!(harness.internal.converter.ditherer_name(), Some("gpdf"));
+        assert!(!harness.internal.output_format_reported);
+
+        harness.command(PlayerCommand::SetDitherer(None));
+        assert_eq!(harness.internal.converter.ditherer_name(), None);
+    }
+
+    fn write_next_packet(harness: &mut Harness) {
+        let trim_end_ms = harness.internal.config.trim_end_ms;
+        let packet = match &mut harness.internal.state {
+            PlayerState::Playing {
+                decoder,
+                duration_ms,
+                ..
+            } => trim_end(decoder.next_packet().unwrap(), *duration_ms, trim_end_ms),
+            _ => unreachable!(),
+        };
+        // A failed write sets a timer for the retry
+        let _guard = harness.runtime.enter();
+        harness.internal.handle_packet(packet, 1.0);
+    }
+
+    #[test]
+    fn levels_are_sent_only_while_enabled() {
+        let mut harness = Harness::new();
+        load(&mut harness, true);
+        let levels = |events: Vec<PlayerEvent>| {
+            events
+                .into_iter()
+                .filter(|event| matches!(event, PlayerEvent::Levels { .. }))
+                .count()
+        };
+
+        for _ in 0..4 {
+            write_next_packet(&mut harness);
+        }
+        assert_eq!(levels(harness.drain_events()), 0);
+
+        harness.command(PlayerCommand::SetLevels(true));
+        for _ in 0..4 {
+            write_next_packet(&mut harness);
+        }
+        assert!(levels(harness.drain_events()) > 0);
+
+        harness.command(PlayerCommand::SetLevels(false));
+        for _ in 0..4 {
+            write_next_packet(&mut harness);
+        }
+        assert_eq!(levels(harness.drain_events()), 0);
+    }
+
+    // Runs the poll loop until the packet of a failed write has been written again or
+    // given up on
+    fn finish_sink_retry(harness: &mut Harness) {
+        let Harness {
+            internal, runtime, ..
+        } = harness;
+        runtime.block_on(async {
+            let retried = future::poll_fn(|cx| {
+                let _ = Pin::new(&mut *internal).poll(cx);
+                match internal.sink_retry {
+                    Some(_) => Poll::Pending,
+                    None => Poll::Ready(()),
+                }
+            });
+            tokio::time::timeout(Duration::from_secs(5), retried)
+                .await
+                .unwrap()
+        });
+    }
+
+    // Waits out the delay of a track that ended the queue, then returns whether
+    // on_queue_end was applied. Doesn't run the poll loop, which would play on.
+    fn finish_queue_end(harness: &mut Harness) -> bool {
+        let Harness {
+            internal, runtime, ..
+        } = harness;
+        runtime.block_on(async {
+            tokio::time::sleep(QUEUE_END_DELAY).await;
+            future::poll_fn(|cx| Poll::Ready(internal.poll_queue_end(cx))).await
+        })
+    }
+
+    #[test]
+    fn sink_write_error_is_retried() {
+        let mut harness = Harness::new();
+        load(&mut harness, true);
+        harness.drain_events();
+        harness.sink.set_faults(MockSinkFaults {
+            writes: 2,
+            ..Default::default()
+        });
+
+        // The sink is restarted for each retry, while playback waits
+        write_next_packet(&mut harness);
+        assert!(harness.internal.sink_retry.is_some());
+        assert!(!harness.sink_log().running);
+        finish_sink_retry(&mut harness);
+
+        let log = harness.sink_log();
+        assert!(log.packets > 0);
+        assert!(log.running);
+        assert!(!harness
+            .drain_events()
+            .iter()
+            .any(|event| matches!(event, PlayerEvent::SinkError { .. })));
+    }
+
+    #[test]
+    fn sink_write_error_pauses_after_retries() {
+        let mut harness = Harness::new();
+        harness.internal.config.sink_write_retries = 1;
+        load(&mut harness, true);
+        harness.drain_events();
+        harness.sink.set_faults(MockSinkFaults {
+            writes: 2,
+            ..Default::default()
+        });
+
+        write_next_packet(&mut harness);
+        finish_sink_retry(&mut harness);
+        assert!(matches!(harness.internal.state, PlayerState::Paused { .. }));
+        assert!(!harness.internal.failed);
+        assert_eq!(harness.sink_log().packets, 0);
+        assert!(harness
+            .drain_events()
+            .iter()
+            .any(|event| matches!(event, PlayerEvent::SinkError { .. })));
+    }
+
+    #[test]
+    fn sink_start_error_pauses_until_resumed() {
+        let mut harness = Harness::new();
+        harness.sink.set_faults(MockSinkFaults {
+            starts: 1,
+            ..Default::default()
+        });
+
+        load(&mut harness, true);
+        assert!(matches!(harness.internal.state, PlayerState::Paused { .. }));
+        let events = harness.drain_events();
+        assert!(events
+            .iter()
+            .any(|e| matches!(e, PlayerEvent::SinkError { .. })));
+        assert!(matches!(events.last(), Some(PlayerEvent::Paused { .. })));
+        assert!(!events
+            .iter()
+            .any(|e| matches!(e, PlayerEvent::Playing { .. })));
+
+        // Resuming while the device is still busy answers with Paused again
+        harness.sink.set_faults(MockSinkFaults {
+            starts: 1,
+            ..Default::default()
+        });
+        harness.command(PlayerCommand::Play);
+        assert!(matches!(harness.internal.state, PlayerState::Paused { .. }));
+        let events = harness.drain_events();
+        assert!(matches!(
+            events.as_slice(),
+            [
+                ..,
+                PlayerEvent::SinkError { .. },
+                PlayerEvent::Paused { .. }
+            ]
+        ));
+
+        // Once the device is free again playback can be resumed
+        harness.command(PlayerCommand::Play);
+        assert!(harness.internal.state.is_playing());
+        assert!(harness.sink_log().running);
+    }
+
+    #[test]
+    fn sink_start_error_after_seek_pauses() {
+        let mut harness = Harness::new();
+        load(&mut harness, true);
+        harness.sink.set_faults(MockSinkFaults {
+            writes: 1,
+            ..Default::default()
+        });
+        write_next_packet(&mut harness);
+        assert!(!harness.sink_log().running);
+
+        // Seeking drops the pending retry, so the poll loop has to start the sink
+        harness.sink.set_faults(MockSinkFaults {
+            starts: 1,
+            ..Default::default()
+        });
+        harness.command(PlayerCommand::Seek(2_000));
+        harness.drain_events();
+        let Harness {
+            internal, runtime, ..
+        } = &mut harness;
+        runtime.block_on(future::poll_fn(|cx| {
+            let _ = Pin::new(&mut *internal).poll(cx);
+            Poll::Ready(())
+        }));
+
+        assert!(matches!(harness.internal.state, PlayerState::Paused { .. }));
+        let events = harness.drain_events();
+        assert!(events
+            .iter()
+            .any(|e| matches!(e, PlayerEvent::SinkError { .. })));
+        assert!(!events
+            .iter()
+            .any(|e| matches!(e, PlayerEvent::StateReset { .. })));
+    }
+
+    #[test]
+    fn sink_stop_error_stops_player_without_exiting() {
+        let mut harness = Harness::new();
+        load(&mut harness, true);
+        harness.drain_events();
+        harness.sink.set_faults(MockSinkFaults {
+            stops: 1,
+            ..Default::default()
+        });
+
+        harness.command(PlayerCommand::Stop);
+
+        assert!(harness.internal.state.is_stopped());
+        assert!(harness.internal.failed);
+        assert!(harness
+            .drain_events()
+            .iter()
+            .any(|event| matches!(event, PlayerEvent::FatalError { .. })));
+    }
+
+    #[test]
+    fn invalid_state_is_reset_without_stopping_player() {
+        let mut harness = Harness::new();
+        load(&mut harness, true);
+        harness.drain_events();
+
+        harness.internal.state = PlayerState::Invalid;
+        assert!(harness
+            .internal
+            .handle_command(PlayerCommand::Seek(1_000))
+            .is_err());
+        harness.command(PlayerCommand::Stop);
+
+        assert!(harness.internal.state.is_stopped());
+        assert!(!harness.internal.failed);
+        assert!(harness
+            .drain_events()
+            .iter()
+            .any(|event| matches!(event, PlayerEvent::StateReset { .. })));
+
+        // Still takes commands
+        load(&mut harness, true);
+        assert!(harness.internal.state.is_playing());
+    }
+
+    #[test]
+    fn preload_lead_time_change_rearms_suggestion() {
+        let mut harness = Harness::new();
+        load(&mut harness, true);
+        fn suggested(harness: &mut Harness) -> &mut bool {
+            match harness.internal.state {
+                PlayerState::Playing {
+                    ref mut suggested_to_preload_next_track,
+                    ..
+                } => suggested_to_preload_next_track,
+                _ => unreachable!(),
+            }
+        }
+        *suggested(&mut harness) = true;
+
+        // Still within the longer window, already suggested
+        let lead_time = Duration::from_millis(TRACK_DURATION_MS as u64 + 1_000);
+        harness.command(PlayerCommand::SetPreloadLeadTime(lead_time));
+        assert!(*suggested(&mut harness));
+        assert_eq!(harness.internal.config.preload_lead_time, lead_time);
+
+        // Outside of the shorter window, suggested again once it is reached
+        harness.command(PlayerCommand::SetPreloadLeadTime(Duration::from_secs(1)));
+        assert!(!*suggested(&mut harness));
+    }
+
+    #[test]
+    fn time_until_preload_follows_position() {
+        let mut harness = Harness::new();
+        let time_until_preload = |harness: &mut Harness| {
+            let (tx, mut rx) = oneshot::channel();
+            harness.command(PlayerCommand::GetTimeUntilPreload(tx));
+            rx.try_recv().unwrap()
+        };
+        assert_eq!(time_until_preload(&mut harness), None);
+
+        load(&mut harness, true);
+        harness.internal.config.preload_lead_time = Duration::from_secs(3);
+        harness.command(PlayerCommand::Seek(2_000));
+        assert_eq!(
+            time_until_preload(&mut harness),
+            Some(Duration::from_millis(TRACK_DURATION_MS as u64 - 5_000))
+        );
+
+        harness.command(PlayerCommand::Seek(TRACK_DURATION_MS - 1_000));
+        assert_eq!(time_until_preload(&mut harness), Some(Duration::ZERO));
+
+        harness.command(PlayerCommand::Pause);
+        assert_eq!(time_until_preload(&mut harness), None);
+    }
+
+    #[test]
+    fn queue_end_behavior_applies_without_next_track() {
+        let play_to_end = |on_queue_end| {
+            let mut harness = Harness::new();
+            harness.internal.config.on_queue_end = on_queue_end;
+            load(&mut harness, true);
+            if let PlayerState::Playing { decoder, .. } = &mut harness.internal.state {
+                decoder.seek(TRACK_DURATION_MS).unwrap();
+            }
+            write_next_packet(&mut harness);
+            // Applies only once the controller had time to load something else
+            assert!(matches!(
+                harness.internal.state,
+                PlayerState::EndOfTrack { .. }
+            ));
+            finish_queue_end(&mut harness);
+            harness
+        };
+
+        let harness = play_to_end(QueueEndBehavior::Stop);
+        assert!(matches!(
+            harness.internal.state,
+            PlayerState::EndOfTrack { .. }
+        ));
+
+        let harness = play_to_end(QueueEndBehavior::RepeatAll);
+        assert!(matches!(
+            harness.internal.state,
+            PlayerState::Playing { track_id, stream_position_ms: 0, .. } if track_id == test_track()
+        ));
+
+        let harness = play_to_end(QueueEndBehavior::Pause);
+        assert!(matches!(
+            harness.internal.state,
+            PlayerState::Paused {
+                stream_position_ms: 0,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn queue_end_behavior_yields_to_load() {
+        let mut harness = Harness::new();
+        harness.internal.config.on_queue_end = QueueEndBehavior::RepeatAll;
+        load(&mut harness, true);
+        if let PlayerState::Playing { decoder, .. } = &mut harness.internal.state {
+            decoder.seek(TRACK_DURATION_MS).unwrap();
+        }
+        write_next_packet(&mut harness);
+        assert!(harness.internal.queue_end.is_some());
+
+        // The controller loads what comes next itself
+        load(&mut harness, false);
+        assert!(!finish_queue_end(&mut harness));
+        assert!(matches!(harness.internal.state, PlayerState::Paused { .. }));
+    }
+
+    #[test]
+    fn gapless_transition_is_reported() {
+        let mut harness = Harness::new();
+        let from_track_id = load(&mut harness, true);
+        let to_track_id = SpotifyId::from_uri("spotify:track:0nrRP2bk19rLc0orkWPQk2").unwrap();
+        harness.inject_track(to_track_id);
+        harness.drain_events();
+
+        if let PlayerState::Playing { decoder, .. } = &mut harness.internal.state {
+            decoder.seek(TRACK_DURATION_MS).unwrap();
+        }
+        write_next_packet(&mut harness);
+        assert!(matches!(
+            harness.drain_events()[..],
+            [
+                PlayerEvent::EndOfTrack { .. },
+                PlayerEvent::Transitioning {
+                    from_track_id: from,
+                    to_track_id: to,
+                },
+            ] if from == from_track_id && to == to_track_id
+        ));
+
+        harness.command(PlayerCommand::Load {
+            track_id: to_track_id,
+            play: true,
+            position_ms: 0,
+        });
+        assert!(harness.drain_events().iter().any(|event| matches!(
+            event,
+            PlayerEvent::TransitionFinished { to_track_id: to, .. } if *to == to_track_id
+        )));
+    }
+
+    #[test]
+    fn load_starts_after_trim() {
+        let mut harness = Harness::new();
+        harness.internal.config.trim_start_ms = 500;
+        load(&mut harness, true);
+
+        assert!(matches!(
+            harness.internal.state,
+            PlayerState::Playing {
+                stream_position_ms: 500,
+                ..
+            }
+        ));
+        assert!(matches!(
+            harness.drain_events().last(),
+            Some(PlayerEvent::Playing {
+                position_ms: 500,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn track_ends_before_trim() {
+        let mut harness = Harness::new();
+        harness.internal.config.trim_end_ms = 500;
+        let track_id = load(&mut harness, true);
+        harness.command(PlayerCommand::Seek(TRACK_DURATION_MS - 520));
+        harness.drain_events();
+
+        // The last packet before the trim is still played
+        write_next_packet(&mut harness);
+        assert!(matches!(
+            harness.internal.state,
+            PlayerState::Playing {
+                stream_position_ms: position_ms,
+                ..
+            } if position_ms == TRACK_DURATION_MS - 520
+        ));
+
+        write_next_packet(&mut harness);
+        assert!(matches!(
+            harness.internal.state,
+            PlayerState::EndOfTrack { .. }
+        ));
+        assert!(harness.drain_events().iter().any(|event| matches!(
+            event,
+            PlayerEvent::EndOfTrack { track_id: id, .. } if *id == track_id
+        )));
+    }
+
+    #[test]
+    fn gain_override_applies_to_current_track() {
+        let mut harness = Harness::new();
+        harness.internal.config.normalisation = true;
+        load(&mut harness, true);
+
+        let factor = |harness: &Harness| match harness.internal.state {
+            PlayerState::Playing {
+                normalisation_factor,
+                ..
+            } => normalisation_factor,
+            _ => panic!("Not playing"),
+        };
+        let default_factor = factor(&harness);
+
+        harness.command(PlayerCommand::SetGainOverride(Some(-6.0)));
+        let expected = db_to_ratio(-6.0 + harness.internal.config.normalisation_pregain_db);
+        assert!((factor(&harness) - expected).abs() < 1e-9);
+
+        // The next track uses its own gain again
+        let next_track = SpotifyId::from_uri("spotify:track:2takcwOaAZWiXQijPHIx7B").unwrap();
+        harness.inject_track(next_track);
+        harness.command(PlayerCommand::Load {
+            track_id: next_track,
+            play: true,
+            position_ms: 0,
+        });
+        assert!(harness.internal.gain_override_db.is_none());
+        assert_eq!(factor(&harness), default_factor);
+    }
+
+    #[test]
+    fn peak_normalisation_factor() {
+        let config = PlayerConfig {
+            normalisation: true,
+            normalisation_type: NormalisationType::Track,
+            normalisation_method: NormalisationMethod::Peak,
+            normalisation_pregain_db: 6.0,
+            normalisation_threshold_dbfs: -6.0,
+            ..PlayerConfig::default()
+        };
+        let data = |track_gain_db, track_peak| NormalisationData {
+            track_gain_db,
+            track_peak,
+            ..NormalisationData::default()
+        };
+        let threshold = db_to_ratio(-6.0);
+
+        // Only the peak and threshold count, not the gain or pregain
+        let factor = NormalisationData::get_factor(&config, data(-10.0, 0.25));
+        assert!((factor - threshold / 0.25).abs() < 1e-9);
+        assert!(factor > 1.0);
+        let factor = NormalisationData::get_factor(&config, data(3.0, 0.25));
+        assert!((factor - threshold / 0.25).abs() < 1e-9);
+        let factor = NormalisationData::get_factor(&config, data(0.0, 1.0));
+        assert!((factor - threshold).abs() < 1e-9);
+
+        // Album peak with album normalisation, nothing to go by without a peak
+        let album = PlayerConfig {
+            normalisation_type: NormalisationType::Album,
+            ..config.clone()
+        };
+        let album_data = NormalisationData {
+            album_peak: 0.5,
+            ..data(0.0, 0.25)
+        };
+        let factor = NormalisationData::get_factor(&album, album_data);
+        assert!((factor - threshold / 0.5).abs() < 1e-9);
+        assert_eq!(NormalisationData::get_factor(&config, data(0.0, 0.0)), 1.0);
+    }
+
+    #[test]
+    fn peak_normalisation_leaves_unknown_peaks_and_caps_gain() {
+        let config = PlayerConfig {
+            normalisation: true,
+            normalisation_method: NormalisationMethod::Peak,
+            ..PlayerConfig::default()
+        };
+
+        // Tracks whose normalisation data couldn't be read
+        let factor = NormalisationData::get_factor(&config, NormalisationData::unknown());
+        assert_eq!(factor, 1.0);
+
+        // A near silent track is raised by the maximum only
+        let quiet = NormalisationData {
+            track_peak: 0.01,
+            ..NormalisationData::default()
+        };
+        let factor = NormalisationData::get_factor(&config, quiet);
+        assert!((factor - db_to_ratio(PEAK_NORMALISATION_MAX_GAIN_DB)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn normalisation_method_switches_live() {
+        let mut harness = Harness::new();
+        harness.internal.config.normalisation = true;
+        load(&mut harness, true);
+        harness.command(PlayerCommand::SetGainOverride(Some(6.0)));
+        harness.internal.normalisation_peak = 0.5;
+
+        let factor = |harness: &Harness| match harness.internal.state {
+            PlayerState::Playing {
+                normalisation_factor,
+                ..
+            } => normalisation_factor,
+            _ => panic!("Not playing"),
+        };
+        assert!(factor(&harness) > PCM_AT_0DBFS);
+
+        // Basic never exceeds 0 dBFS
+        harness.command(PlayerCommand::SetNormalisationMethod(
+            NormalisationMethod::Basic,
+        ));
+        assert_eq!(factor(&harness), PCM_AT_0DBFS);
+        assert_eq!(harness.internal.normalisation_peak, 0.0);
+
+        harness.command(PlayerCommand::SetNormalisationMethod(
+            NormalisationMethod::Dynamic,
+        ));
+        assert!((factor(&harness) - db_to_ratio(6.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn next_track_starts_from_preload() {
+        let mut harness = Harness::new();
+        load(&mut harness, true);
+        harness.drain_events();
+        let starts = harness.sink_log().starts;
+
+        // As spirc loads the next track on setNext
+        let next_track = SpotifyId::from_uri("spotify:track:2takcwOaAZWiXQijPHIx7B").unwrap();
+        harness.inject_track(next_track);
+        harness.command(PlayerCommand::Load {
+            track_id: next_track,
+            play: true,
+            position_ms: 0,
+        });
+
+        assert!(matches!(harness.internal.preload, PlayerPreload::None));
+        assert!(matches!(
+            harness.internal.state,
+            PlayerState::Playing { track_id, .. } if track_id == next_track
+        ));
+        // Nothing loaded and the sink kept running
+        let events = harness.drain_events();
+        assert!(!events
+            .iter()
+            .any(|e| matches!(e, PlayerEvent::Loading { .. })));
+        assert!(harness.sink_log().running);
+        assert_eq!(harness.sink_log().starts, starts);
+    }
+
+    #[test]
+    fn track_changed_reports_start_position() {
+        let mut harness = Harness::new();
+        let track_id = test_track();
+        harness.inject_track(track_id);
+        harness.command(PlayerCommand::Load {
+            track_id,
+            play: false,
+            position_ms: 3_000,
+        });
+
+        assert!(harness.drain_events().iter().any(|e| matches!(
+            e,
+            PlayerEvent::TrackChanged {
+                position_ms: 3_000,
+                ..
+            }
+        )));
+    }
+
+    #[test]
+    fn decode_stats_average_over_interval() {
+        let mut harness = Harness::new();
+        load(&mut harness, true);
+        harness.drain_events();
+
+        // Not due yet
+        harness
+            .internal
+            .decode_stats
+            .record(Duration::from_micros(100), false);
+        harness.internal.send_decode_stats();
+        assert!(harness.drain_events().is_empty());
+
+        harness
+            .internal
+            .decode_stats
+            .record(Duration::from_micros(300), true);
+        harness.internal.decode_stats.since -= DECODE_STATS_INTERVAL;
+        harness.internal.send_decode_stats();
+        assert!(matches!(
+            harness.drain_events()[..],
+            [PlayerEvent::DecodeStats {
+                avg_decode_us: 200,
+                packets: 2,
+                skipped: 1,
+            }]
+        ));
+
+        // Nothing decoded since, nothing to report
+        harness.internal.decode_stats.since -= DECODE_STATS_INTERVAL;
+        harness.internal.send_decode_stats();
+        assert!(harness.drain_events().is_empty());
+    }
+
+    #[test]
+    fn buffer_progress_is_reported_until_complete() {
+        let mut harness = Harness::new();
+        load(&mut harness, true);
+        harness.drain_events();
+
+        // Cached files are complete, reported once
+        harness.internal.send_buffer_progress();
+        harness.internal.send_buffer_progress();
+        let progress: Vec<_> = harness
+            .drain_events()
+            .into_iter()
+            .filter_map(|e| match e {
+                PlayerEvent::BufferProgress {
+                    fraction,
+                    range_to_end_available,
+                    ..
+                } => Some((fraction, range_to_end_available)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(progress, [(1.0, true)]);
+
+        let (tx, mut rx) = oneshot::channel();
+        harness.command(PlayerCommand::GetState(tx));
+        let snapshot = rx.try_recv().unwrap();
+        assert_eq!(snapshot.buffered, 1.0);
+        assert!(snapshot.range_to_end_available);
+    }
+
+    #[test]
+    fn resync_repeats_current_state() {
+        let mut harness = Harness::new();
+        let track_id = load(&mut harness, false);
+        harness.command(PlayerCommand::EmitVolumeChangedEvent(1234));
+        harness.command(PlayerCommand::Seek(2_000));
+        harness.drain_events();
+
+        harness.command(PlayerCommand::ResyncState);
+        let events = harness.drain_events();
+        assert!(matches!(
+            events.as_slice(),
+            [
+                PlayerEvent::VolumeChanged { volume: 1234 },
+                PlayerEvent::TrackChanged {
+                    position_ms: 2_000,
+                    ..
+                },
+                PlayerEvent::Paused {
+                    track_id: id,
+                    position_ms: 2_000,
+                    ..
+                },
+            ] if *id == track_id
+        ));
+        assert!(!harness.sink_log().running);
+
+        harness.command(PlayerCommand::Stop);
+        harness.drain_events();
+        harness.command(PlayerCommand::ResyncState);
+        assert!(matches!(
+            harness.drain_events().as_slice(),
+            [
+                PlayerEvent::VolumeChanged { .. },
+                PlayerEvent::Stopped { .. }
+            ]
+        ));
+    }
+
+    #[test]
+    fn resync_after_end_of_track_reports_paused() {
+        let mut harness = Harness::new();
+        harness.internal.config.on_queue_end = QueueEndBehavior::Stop;
+        let track_id = load(&mut harness, true);
+        if let PlayerState::Playing { decoder, .. } = &mut harness.internal.state {
+            decoder.seek(TRACK_DURATION_MS).unwrap();
+        }
+        write_next_packet(&mut harness);
+        assert!(matches!(
+            harness.internal.state,
+            PlayerState::EndOfTrack { .. }
+        ));
+        harness.drain_events();
+
+        harness.command(PlayerCommand::ResyncState);
+        assert!(matches!(
+            harness.drain_events().as_slice(),
+            [
+                PlayerEvent::TrackChanged { .. },
+                PlayerEvent::Paused { track_id: id, .. },
+            ] if *id == track_id
+        ));
+    }
+
+    fn reported_volumes(harness: &mut Harness) -> Vec<u16> {
+        harness
+            .drain_events()
+            .into_iter()
+            .filter_map(|e| match e {
+                PlayerEvent::VolumeChanged { volume } => Some(volume),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn repeated_mixer_volume_is_reported_once() {
+        let mut harness = Harness::new();
+        harness.command(PlayerCommand::EmitVolumeChangedEvent(1000));
+        harness.command(PlayerCommand::EmitMixerVolumeChangedEvent(1000));
+        harness.command(PlayerCommand::EmitMixerVolumeChangedEvent(2000));
+        harness.command(PlayerCommand::EmitMixerVolumeChangedEvent(2000));
+        assert_eq!(reported_volumes(&mut harness), [1000, 2000]);
+    }
+
+    #[test]
+    fn explicit_volume_is_reported_again() {
+        let mut harness = Harness::new();
+        harness.command(PlayerCommand::EmitMixerVolumeChangedEvent(1000));
+        // Spirc announces the volume on activation, even if it didn't change
+        harness.command(PlayerCommand::EmitVolumeChangedEvent(1000));
+        harness.command(PlayerCommand::EmitVolumeChangedEvent(1000));
+        assert_eq!(reported_volumes(&mut harness), [1000, 1000, 1000]);
+    }
+
+    #[test]
+    fn normalisation_cache_evicts_least_recently_used() {
+        let mut cache = NormalisationCache::default();
+        let file = |n: u8| FileId([n; 20]);
+        let data = |gain| NormalisationData {
+            track_gain_db: gain,
+            ..Default::default()
+        };
+
+        for n in 0..NORMALISATION_CACHE_SIZE as u8 {
+            cache.insert(file(n), data(n as f64));
+        }
+        // Using the oldest entry keeps it, the next oldest is evicted instead
+        assert_eq!(cache.get(file(0)).unwrap().track_gain_db, 0.0);
+        cache.insert(file(100), data(100.0));
+        assert!(cache.get(file(1)).is_none());
+        assert!(cache.get(file(0)).is_some());
+
+        cache.remove(file(0));
+        assert!(cache.get(file(0)).is_none());
+        assert_eq!(cache.entries.len(), NORMALISATION_CACHE_SIZE - 1);
+    }
+
+    // Ogg header with normalisation values -3 dB, 0.9, -4 dB and 0.95 at `offset`
+    fn ogg_header(offset: usize) -> io::Cursor<Vec<u8>> {
+        let mut header = vec![0u8; SPOTIFY_OGG_HEADER_END as usize];
+        for (i, value) in [-3.0f32, 0.9, -4.0, 0.95].iter().enumerate() {
+            let start = offset + i * 4;
+            header[start..start + 4].copy_from_slice(&value.to_le_bytes());
+        }
+        io::Cursor::new(header)
+    }
+
+    #[test]
+    fn normalisation_data_is_found_near_usual_offset() {
+        for offset in [144, 137, 150] {
+            let data = NormalisationData::parse_from_ogg(ogg_header(offset)).unwrap();
+            assert_eq!(data.track_gain_db, -3.0);
+            assert_eq!(data.album_gain_db, -4.0);
+            assert_eq!(data.album_peak, 0.95f32 as f64);
+        }
+
+        // Too far off, or only zeros where the values should be
+        assert!(NormalisationData::parse_from_ogg(ogg_header(100)).is_err());
+        let empty = io::Cursor::new(vec![0u8; SPOTIFY_OGG_HEADER_END as usize]);
+        assert!(NormalisationData::parse_from_ogg(empty).is_err());
+    }
+
+    #[test]
+    fn implausible_normalisation_data_is_rejected() {
+        let mut header = ogg_header(144).into_inner();
+        header[144..148].copy_from_slice(&1000.0f32.to_le_bytes());
+        let data = NormalisationData::parse_from_ogg(io::Cursor::new(header));
+        assert!(data.is_err());
+    }
+
+    #[test]
+    fn drop_stops_sink() {
+        let mut harness = Harness::new();
+        load(&mut harness, true);
+        let sink = harness.sink.clone();
+        assert!(sink.log().running);
+
+        drop(harness);
+        assert!(!sink.log().running);
+        assert_eq!(sink.log().stops, 1);
+    }
+
+    #[test]
+    fn subscriber_count_skips_dropped_channels() {
+        let count = |fanout: &mut EventFanout| {
+            let (tx, rx) = oneshot::channel();
+            fanout.subscriber_count(tx);
+            rx.blocking_recv().unwrap()
+        };
+
+        for offload in [false, true] {
+            let mut fanout = EventFanout::new(offload, 0);
+            let (sender, _kept) = player_event_channel(None);
+            fanout.add_sender(sender);
+            let (sender, dropped) = player_event_channel(Some(4));
+            fanout.add_sender(sender);
+            assert_eq!(count(&mut fanout), 2);
+
+            drop(dropped);
+            assert_eq!(count(&mut fanout), 1);
+        }
+
+        let mut harness = Harness::new();
+        let (tx, mut rx) = oneshot::channel();
+        harness.command(PlayerCommand::GetSubscriberCount(tx));
+        assert_eq!(rx.try_recv(), Ok(1));
+    }
+
+    // Run with `cargo test -p librespot-playback -- --ignored --nocapture event_fanout`
+    // to compare how long the player thread spends sending events.
+    #[test]
+    #[ignore]
+    fn event_fanout_cost() {
+        const EVENTS: u32 = 1_000;
+
+        let time_per_event = |offload: bool, subscribers: usize| {
+            let mut fanout = EventFanout::new(offload, 0);
+            let receivers: Vec<_> = (0..subscribers)
+                .map(|_| {
+                    let (sender, receiver) = player_event_channel(None);
+                    fanout.add_sender(sender);
+                    receiver
+                })
+                .collect();
+
+            let start = Instant::now();
+            for _ in 0..EVENTS {
+                fanout.send(PlayerEvent::VolumeChanged { volume: 0 });
+            }
+            let elapsed = start.elapsed() / EVENTS;
+            drop(receivers);
+            elapsed
+        };
+
+        for subscribers in [1, 10, 100, 1_000] {
+            println!(
+                "{:>5} subscribers: inline {:?}, offloaded {:?} per event",
+                subscribers,
+                time_per_event(false, subscribers),
+                time_per_event(true, subscribers),
+            );
+        }
+        assert!(time_per_event(true, 1_000) < time_per_event(false, 1_000));
+    }
+}
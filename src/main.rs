@@ -9,6 +9,7 @@ use std::{
     pin::Pin,
     process::exit,
     str::FromStr,
+    sync::Arc,
     time::{Duration, Instant},
 };
 use sysinfo::{System, SystemExt};
@@ -16,7 +17,7 @@ use thiserror::Error;
 use url::Url;
 
 use librespot::{
-    api_server::Server,
+    api_server::{ListenerConfig, Server, ServerConfig, TlsConfig},
     connect::{config::ConnectConfig, spirc::Spirc},
     core::{
         authentication::Credentials, cache::Cache, config::DeviceType, version, Session,
@@ -25,11 +26,13 @@ use librespot::{
     playback::{
         audio_backend::{self, SinkBuilder, BACKENDS},
         config::{
-            AudioFormat, Bitrate, NormalisationMethod, NormalisationType, PlayerConfig, VolumeCtrl,
+            AudioFormat, Bitrate, EqBand, NormalisationMethod, NormalisationType, PlayerConfig,
+            VolumeCtrl,
         },
         dither,
         mixer::{self, MixerConfig, MixerFn},
         player::{coefficient_to_duration, duration_to_coefficient, Player},
+        SAMPLE_RATE,
     },
 };
 
@@ -168,6 +171,31 @@ fn get_version_string() -> String {
     )
 }
 
+// An API listener as ADDR[;token=TOKEN][;cert=PATH;key=PATH]
+fn parse_listener(listener: &str) -> Option<ListenerConfig> {
+    let mut parts = listener.split(';');
+    let addr = parts.next()?.parse().ok()?;
+    let (mut token, mut cert_path, mut key_path) = (None, None, None);
+    for part in parts {
+        match part.split_once('=')? {
+            ("token", value) if !value.is_empty() => token = Some(value.to_string()),
+            ("cert", value) if !value.is_empty() => cert_path = Some(value.to_string()),
+            ("key", value) if !value.is_empty() => key_path = Some(value.to_string()),
+            _ => return None,
+        }
+    }
+
+    let tls = match (cert_path, key_path) {
+        (Some(cert_path), Some(key_path)) => Some(TlsConfig {
+            cert_path,
+            key_path,
+        }),
+        (None, None) => None,
+        _ => return None,
+    };
+    Some(ListenerConfig { addr, token, tls })
+}
+
 struct Setup {
     format: AudioFormat,
     backend: SinkBuilder,
@@ -185,8 +213,7 @@ struct Setup {
     emit_sink_events: bool,
     zeroconf_ip: Vec<std::net::IpAddr>,
     use_api: bool,
-    use_web: bool,
-    custom_web_path: Option<String>,
+    server_config: ServerConfig,
 }
 
 fn get_setup() -> Setup {
@@ -246,6 +273,22 @@ fn get_setup() -> Setup {
     const ENABLE_API: &str = "enable-api";
     const ENABLE_WEB: &str = "enable-web-interface";
     const WEB_DIR: &str = "custom-web-dir";
+    const API_RAW_EVENTS: &str = "api-raw-events";
+    const API_VOLUME_MAX: &str = "api-volume-max";
+    const API_DEBUG: &str = "api-debug";
+    const API_COVER_PROXY: &str = "api-cover-proxy";
+    const API_METRICS: &str = "api-metrics";
+    const API_RESUME_TTL: &str = "api-resume-ttl";
+    const API_VOLUME_DEBOUNCE: &str = "api-volume-debounce";
+    const API_POSITION_FILE: &str = "api-position-file";
+    const API_SEND_RETRIES: &str = "api-send-retries";
+    const API_WS_COMPRESSION: &str = "api-ws-compression";
+    const API_ALLOW_METHODS: &str = "api-allow-methods";
+    const API_DENY_METHODS: &str = "api-deny-methods";
+    const API_LISTEN: &str = "api-listen";
+    const WEB_BUNDLES: &str = "web-bundles";
+    const DOWNMIX_MONO: &str = "downmix-mono";
+    const EQUALIZER: &str = "equalizer";
 
     // Mostly arbitrary.
     const AP_PORT_SHORT: &str = "a";
@@ -379,6 +422,17 @@ fn get_setup() -> Setup {
         DISABLE_GAPLESS,
         "Disable gapless playback.",
     )
+    .optflag(
+        "",
+        DOWNMIX_MONO,
+        "Downmix stereo to mono by averaging both channels into each.",
+    )
+    .optopt(
+        "",
+        EQUALIZER,
+        "Comma-separated parametric equaliser bands, e.g. 100:3:0.7,3000:-2:1.",
+        "FREQ:GAIN:Q",
+    )
     .optflag(
         EMIT_SINK_EVENTS_SHORT,
         EMIT_SINK_EVENTS,
@@ -396,6 +450,71 @@ fn get_setup() -> Setup {
         ENABLE_WEB_SHORT,
         ENABLE_WEB,
         "Enable static website. Serves default UI, to set custom files also use option [custom-web-dir]"
+    ).optflag(
+        "",
+        API_RAW_EVENTS,
+        "Forward all player events to API websockets as OnRawEvent notifications, for debugging."
+    ).optflag(
+        "",
+        API_DEBUG,
+        "Enable diagnostic API methods, such as getRawNormalisation."
+    ).optflag(
+        "",
+        API_COVER_PROXY,
+        "Serve album art of the current track under /cover/ID, prefetched on track change."
+    ).optflag(
+        "",
+        API_METRICS,
+        "Serve playback and server metrics for Prometheus under /metrics."
+    ).optflag(
+        "",
+        API_WS_COMPRESSION,
+        "Let websocket clients ask for deflate compressed frames with compress=true."
+    ).optopt(
+        "",
+        API_VOLUME_MAX,
+        "Maximum of the volume scale used by API clients, e.g. 100 for percent. Defaults to 65535.",
+        "MAX"
+    ).optopt(
+        "",
+        API_RESUME_TTL,
+        "Seconds a dropped API websocket can resume its subscription with the token from OnSubscribed. Defaults to 60.",
+        "SECONDS"
+    ).optopt(
+        "",
+        API_VOLUME_DEBOUNCE,
+        "Milliseconds within which API volume changes are coalesced before the latest is applied, e.g. 50 for volume sliders. Defaults to 0.",
+        "MS"
+    ).optopt(
+        "",
+        API_POSITION_FILE,
+        "File to save the position of the current track to, loaded paused from it on the next start.",
+        "PATH"
+    ).optopt(
+        "",
+        API_SEND_RETRIES,
+        "Times a stalled or failing API websocket write is retried before the connection is dropped. Defaults to 2.",
+        "N"
+    ).optopt(
+        "",
+        API_ALLOW_METHODS,
+        "Comma-separated API methods that can be called, all others are refused. Defaults to all methods.",
+        "METHOD"
+    ).optopt(
+        "",
+        API_DENY_METHODS,
+        "Comma-separated API methods that can't be called.",
+        "METHOD"
+    ).optopt(
+        "",
+        API_LISTEN,
+        "Comma-separated addresses for the API server, each optionally followed by ;token=TOKEN to require a token and ;cert=PATH;key=PATH to serve HTTPS. Defaults to 0.0.0.0:3030.",
+        "ADDR[;token=TOKEN][;cert=PATH;key=PATH]"
+    ).optopt(
+        "",
+        WEB_BUNDLES,
+        "Comma-separated additional UI bundles, each served under /ui/NAME/. Only available when [enable-web-interface] set.",
+        "NAME=DIR"
     )
     .optopt(
         NAME_SHORT,
@@ -526,7 +645,7 @@ fn get_setup() -> Setup {
     .optopt(
         NORMALISATION_METHOD_SHORT,
         NORMALISATION_METHOD,
-        "Specify the normalisation method to use {basic|dynamic}. Defaults to dynamic.",
+        "Specify the normalisation method to use {basic|dynamic|peak}. Defaults to dynamic.",
         "METHOD",
     )
     .optopt(
@@ -544,7 +663,7 @@ fn get_setup() -> Setup {
     .optopt(
         NORMALISATION_THRESHOLD_SHORT,
         NORMALISATION_THRESHOLD,
-        "Threshold (dBFS) at which point the dynamic limiter engages to prevent clipping from 0.0 to -10.0. Defaults to -2.0.",
+        "Threshold (dBFS) at which point the dynamic limiter engages to prevent clipping from 0.0 to -10.0, or that track peaks are scaled to with the peak method. Defaults to -2.0.",
         "THRESHOLD",
     )
     .optopt(
@@ -1438,7 +1557,7 @@ fn get_setup() -> Setup {
                             NORMALISATION_METHOD,
                             NORMALISATION_METHOD_SHORT,
                             method,
-                            "basic, dynamic",
+                            "basic, dynamic, peak",
                             &format!("{:?}", player_default_config.normalisation_method),
                         );
 
@@ -1622,6 +1741,40 @@ fn get_setup() -> Setup {
             },
         };
 
+        let equalizer = opt_str(EQUALIZER)
+            .map(|bands| {
+                bands
+                    .split(',')
+                    .map(|band| {
+                        let values: Option<Vec<f64>> =
+                            band.trim().split(':').map(|v| v.parse().ok()).collect();
+                        let parsed = values
+                            .and_then(|values| match values[..] {
+                                [frequency, gain_db, q] => Some(EqBand {
+                                    frequency,
+                                    gain_db,
+                                    q,
+                                }),
+                                _ => None,
+                            })
+                            .filter(EqBand::is_valid);
+
+                        match parsed {
+                            Some(band) => band,
+                            None => {
+                                error!("Invalid `--{EQUALIZER}`: \"{band}\"");
+                                println!(
+                                    "Valid `--{EQUALIZER}` values: FREQ:GAIN:Q[,FREQ:GAIN:Q...], FREQ in Hz below {}, GAIN in dB, Q above 0",
+                                    SAMPLE_RATE / 2
+                                );
+                                exit(1);
+                            }
+                        }
+                    })
+                    .collect()
+            })
+            .unwrap_or(player_default_config.equalizer);
+
         #[cfg(feature = "passthrough-decoder")]
         let passthrough = opt_present(PASSTHROUGH);
         #[cfg(not(feature = "passthrough-decoder"))]
@@ -1629,8 +1782,23 @@ fn get_setup() -> Setup {
 
         PlayerConfig {
             bitrate,
+            adaptive_bitrate: player_default_config.adaptive_bitrate,
             gapless,
             passthrough,
+            downmix_mono: opt_present(DOWNMIX_MONO),
+            equalizer,
+            balance: player_default_config.balance,
+            duck_attenuation_db: player_default_config.duck_attenuation_db,
+            duck_ramp: player_default_config.duck_ramp,
+            stop_fade_ms: player_default_config.stop_fade_ms,
+            load_timeout: player_default_config.load_timeout,
+            sink_write_retries: player_default_config.sink_write_retries,
+            offload_events: player_default_config.offload_events,
+            event_channel_capacity: player_default_config.event_channel_capacity,
+            trim_start_ms: player_default_config.trim_start_ms,
+            trim_end_ms: player_default_config.trim_end_ms,
+            on_queue_end: player_default_config.on_queue_end,
+            preload_lead_time: player_default_config.preload_lead_time,
             normalisation,
             normalisation_type,
             normalisation_method,
@@ -1647,9 +1815,133 @@ fn get_setup() -> Setup {
     let emit_sink_events = opt_present(EMIT_SINK_EVENTS);
 
     let use_api = opt_present(ENABLE_API);
-    let use_web = opt_present(ENABLE_WEB);
 
-    let custom_web_path = opt_str(WEB_DIR);
+    let server_default_config = ServerConfig::default();
+
+    let volume_max = opt_str(API_VOLUME_MAX)
+        .map(|volume_max| match volume_max.parse::<u16>() {
+            Ok(value) if value > 0 => value,
+            _ => {
+                error!("Invalid `--{API_VOLUME_MAX}`: \"{volume_max}\"");
+                println!("Valid `--{API_VOLUME_MAX}` values: 1 - {}", u16::MAX);
+                println!("Default: {}", server_default_config.volume_max);
+                exit(1);
+            }
+        })
+        .unwrap_or(server_default_config.volume_max);
+
+    let resume_ttl = opt_str(API_RESUME_TTL)
+        .map(|resume_ttl| match resume_ttl.parse::<u64>() {
+            Ok(value) => Duration::from_secs(value),
+            _ => {
+                error!("Invalid `--{API_RESUME_TTL}`: \"{resume_ttl}\"");
+                println!("Valid `--{API_RESUME_TTL}` values: 0 - {}", u64::MAX);
+                println!("Default: {}", server_default_config.resume_ttl.as_secs());
+                exit(1);
+            }
+        })
+        .unwrap_or(server_default_config.resume_ttl);
+
+    let volume_debounce = opt_str(API_VOLUME_DEBOUNCE)
+        .map(|debounce| match debounce.parse::<u64>() {
+            Ok(value) => Duration::from_millis(value),
+            _ => {
+                error!("Invalid `--{API_VOLUME_DEBOUNCE}`: \"{debounce}\"");
+                println!("Valid `--{API_VOLUME_DEBOUNCE}` values: 0 - {}", u64::MAX);
+                println!(
+                    "Default: {}",
+                    server_default_config.volume_debounce.as_millis()
+                );
+                exit(1);
+            }
+        })
+        .unwrap_or(server_default_config.volume_debounce);
+
+    let send_retries = opt_str(API_SEND_RETRIES)
+        .map(|retries| match retries.parse::<u32>() {
+            Ok(value) => value,
+            _ => {
+                error!("Invalid `--{API_SEND_RETRIES}`: \"{retries}\"");
+                println!("Valid `--{API_SEND_RETRIES}` values: 0 - {}", u32::MAX);
+                println!("Default: {}", server_default_config.send_retries);
+                exit(1);
+            }
+        })
+        .unwrap_or(server_default_config.send_retries);
+
+    let web_bundles = opt_str(WEB_BUNDLES)
+        .map(|bundles| {
+            bundles
+                .split(',')
+                .map(|bundle| match bundle.trim().split_once('=') {
+                    Some((name, dir)) if !name.is_empty() && !name.contains('/') && !dir.is_empty() => {
+                        (name.to_string(), dir.to_string())
+                    }
+                    _ => {
+                        error!("Invalid `--{WEB_BUNDLES}`: \"{bundle}\"");
+                        println!("Valid `--{WEB_BUNDLES}` values: NAME=DIR[,NAME=DIR...], NAME must not contain '/'");
+                        exit(1);
+                    }
+                })
+                .collect()
+        })
+        .unwrap_or(server_default_config.web_bundles);
+
+    let method_list = |methods: String| -> Vec<String> {
+        methods
+            .split(',')
+            .map(|method| method.trim().to_string())
+            .filter(|method| !method.is_empty())
+            .collect()
+    };
+    let allowed_methods = opt_str(API_ALLOW_METHODS).map(method_list);
+    let denied_methods = opt_str(API_DENY_METHODS)
+        .map(method_list)
+        .unwrap_or(server_default_config.denied_methods);
+
+    let listeners = opt_str(API_LISTEN)
+        .map(|listeners| {
+            listeners
+                .split(',')
+                .map(|listener| {
+                    let listener = parse_listener(listener.trim()).unwrap_or_else(|| {
+                        error!("Invalid `--{API_LISTEN}`: \"{listener}\"");
+                        println!("Valid `--{API_LISTEN}` values: ADDR[;token=TOKEN][;cert=PATH;key=PATH][,...], e.g. 127.0.0.1:3030");
+                        exit(1);
+                    });
+                    // The server would only fail to load them once it starts
+                    if let Some(tls) = &listener.tls {
+                        for path in [&tls.cert_path, &tls.key_path] {
+                            if let Err(e) = std::fs::File::open(path) {
+                                error!("Unable to read `--{API_LISTEN}` TLS file \"{path}\": {e}");
+                                exit(1);
+                            }
+                        }
+                    }
+                    listener
+                })
+                .collect()
+        })
+        .unwrap_or(server_default_config.listeners);
+
+    let server_config = ServerConfig {
+        listeners,
+        enable_web: opt_present(ENABLE_WEB),
+        custom_path: opt_str(WEB_DIR),
+        web_bundles,
+        raw_events: opt_present(API_RAW_EVENTS),
+        cover_proxy: opt_present(API_COVER_PROXY),
+        metrics: opt_present(API_METRICS),
+        resume_ttl,
+        debug: opt_present(API_DEBUG),
+        allowed_methods,
+        denied_methods,
+        volume_max,
+        volume_debounce,
+        position_file: opt_str(API_POSITION_FILE),
+        send_retries,
+        ws_compression: opt_present(API_WS_COMPRESSION),
+    };
 
     Setup {
         format,
@@ -1668,8 +1960,7 @@ fn get_setup() -> Setup {
         emit_sink_events,
         zeroconf_ip,
         use_api,
-        use_web,
-        custom_web_path,
+        server_config,
     }
 }
 
@@ -1761,6 +2052,15 @@ async fn main() {
         (backend)(device, format)
     });
 
+    // Report every volume change as an event, also those that don't go through spirc.
+    // Weak, so that the mixer doesn't keep the player alive.
+    let volume_player = Arc::downgrade(&player);
+    mixer.set_volume_callback(Some(Box::new(move |volume| {
+        if let Some(player) = volume_player.upgrade() {
+            player.emit_mixer_volume_changed_event(volume);
+        }
+    })));
+
     if let Some(player_event_program) = setup.player_event_program.clone() {
         _event_handler = Some(EventHandler::new(
             player.get_player_event_channel(),
@@ -1775,11 +2075,7 @@ async fn main() {
     }
 
     if setup.use_api {
-        api_server = Some(Server::new(
-            player.get_player_event_channel(),
-            setup.use_web,
-            setup.custom_web_path,
-        ));
+        api_server = Some(Server::new(player.clone(), setup.server_config));
     }
 
     loop {
@@ -1837,6 +2133,7 @@ async fn main() {
                 };
                 if let Some(server) = &api_server {
                     server.set_spirc_channel(spirc_.commands.clone());
+                    server.set_session(session.clone());
                 }
 
                 spirc = Some(spirc_);
@@ -1882,6 +2179,10 @@ async fn main() {
 
     info!("Gracefully shutting down");
 
+    if let Some(server) = api_server.take() {
+        server.shutdown(Some("librespot is shutting down".to_string()));
+    }
+
     // Shutdown spirc if necessary
     if let Some(spirc) = spirc {
         if let Err(e) = spirc.shutdown() {
@@ -25,7 +25,7 @@ impl EventHandler {
                             env_vars.insert("PLAYER_EVENT", "play_request_id_changed".to_string());
                             env_vars.insert("PLAY_REQUEST_ID", play_request_id.to_string());
                         }
-                        PlayerEvent::TrackChanged { audio_item } => {
+                        PlayerEvent::TrackChanged { audio_item, .. } => {
                             match audio_item.track_id.to_base62() {
                                 Err(e) => {
                                     warn!("PlayerEvent::TrackChanged: Invalid track id: {}", e)
@@ -157,11 +157,14 @@ impl EventHandler {
                                 env_vars.insert("TRACK_ID", id);
                             }
                         },
-                        PlayerEvent::Unavailable { track_id, .. } => match track_id.to_base62() {
+                        PlayerEvent::Unavailable {
+                            track_id, reason, ..
+                        } => match track_id.to_base62() {
                             Err(e) => warn!("PlayerEvent::Unavailable: Invalid track id: {}", e),
                             Ok(id) => {
                                 env_vars.insert("PLAYER_EVENT", "unavailable".to_string());
                                 env_vars.insert("TRACK_ID", id);
+                                env_vars.insert("REASON", reason.as_str().to_string());
                             }
                         },
                         PlayerEvent::VolumeChanged { volume } => {
@@ -234,6 +237,10 @@ impl EventHandler {
                             env_vars.insert("PLAYER_EVENT", "auto_play_changed".to_string());
                             env_vars.insert("AUTO_PLAY", auto_play.to_string());
                         }
+                        PlayerEvent::PlaybackComplete { context_uri } => {
+                            env_vars.insert("PLAYER_EVENT", "playback_complete".to_string());
+                            env_vars.insert("CONTEXT_URI", context_uri);
+                        }
 
                         PlayerEvent::FilterExplicitContentChanged { filter } => {
                             env_vars.insert(
@@ -242,6 +249,117 @@ impl EventHandler {
                             );
                             env_vars.insert("FILTER", filter.to_string());
                         }
+                        PlayerEvent::ExplicitContentSkipped { track_id } => {
+                            match track_id.to_base62() {
+                                Err(e) => warn!(
+                                    "PlayerEvent::ExplicitContentSkipped: Invalid track id: {}",
+                                    e
+                                ),
+                                Ok(id) => {
+                                    env_vars.insert("PLAYER_EVENT", "explicit_skipped".to_string());
+                                    env_vars.insert("TRACK_ID", id);
+                                }
+                            }
+                        }
+                        PlayerEvent::AudioKeyUnavailable { track_id, .. } => {
+                            match track_id.to_base62() {
+                                Err(e) => warn!(
+                                    "PlayerEvent::AudioKeyUnavailable: Invalid track id: {}",
+                                    e
+                                ),
+                                Ok(id) => {
+                                    env_vars.insert(
+                                        "PLAYER_EVENT",
+                                        "audio_key_unavailable".to_string(),
+                                    );
+                                    env_vars.insert("TRACK_ID", id);
+                                }
+                            }
+                        }
+                        PlayerEvent::QualityReduced {
+                            track_id, bitrate, ..
+                        } => match track_id.to_base62() {
+                            Err(e) => {
+                                warn!("PlayerEvent::QualityReduced: Invalid track id: {}", e)
+                            }
+                            Ok(id) => {
+                                env_vars.insert("PLAYER_EVENT", "quality_reduced".to_string());
+                                env_vars.insert("TRACK_ID", id);
+                                env_vars.insert("BITRATE", bitrate.kbps().to_string());
+                            }
+                        },
+                        PlayerEvent::OutputFormat {
+                            track_id,
+                            format,
+                            ditherer,
+                        } => match track_id.to_base62() {
+                            Err(e) => warn!("PlayerEvent::OutputFormat: Invalid track id: {}", e),
+                            Ok(id) => {
+                                env_vars.insert("PLAYER_EVENT", "output_format".to_string());
+                                env_vars.insert("TRACK_ID", id);
+                                // Unconverted samples are F64 or passthrough
+                                match format {
+                                    Some(format) => {
+                                        env_vars.insert("OUTPUT_FORMAT", format!("{:?}", format));
+                                        env_vars.insert(
+                                            "OUTPUT_BIT_DEPTH",
+                                            format.bit_depth().to_string(),
+                                        );
+                                    }
+                                    None => {
+                                        env_vars.insert("OUTPUT_FORMAT", "unconverted".to_string());
+                                    }
+                                }
+                                env_vars.insert("DITHERER", ditherer.unwrap_or("none").to_string());
+                            }
+                        },
+                        // Sink events run the program through the sink event callback,
+                        // see `--emit-sink-events`
+                        PlayerEvent::SinkStatusChanged { .. } => {}
+                        // Sent every second while downloading, too often to run a program for
+                        PlayerEvent::BufferProgress { .. } => {}
+                        // Sent many times per second while a level meter is listening
+                        PlayerEvent::Levels { .. } => {}
+                        // Sent every few seconds while playing, see `--api-position-file`
+                        PlayerEvent::PositionCheckpoint { .. } => {}
+                        // Sent every few seconds while decoding, only of interest for profiling
+                        PlayerEvent::DecodeStats { .. } => {}
+                        // Sent whenever the limiter turns on or off, up to several times a second
+                        PlayerEvent::LimiterState { .. } => {}
+                        PlayerEvent::Transitioning {
+                            from_track_id,
+                            to_track_id,
+                        } => match (from_track_id.to_base62(), to_track_id.to_base62()) {
+                            (Ok(from), Ok(to)) => {
+                                env_vars.insert("PLAYER_EVENT", "transitioning".to_string());
+                                env_vars.insert("FROM_TRACK_ID", from);
+                                env_vars.insert("TO_TRACK_ID", to);
+                            }
+                            _ => warn!("PlayerEvent::Transitioning: Invalid track id"),
+                        },
+                        PlayerEvent::TransitionFinished {
+                            from_track_id,
+                            to_track_id,
+                        } => match (from_track_id.to_base62(), to_track_id.to_base62()) {
+                            (Ok(from), Ok(to)) => {
+                                env_vars.insert("PLAYER_EVENT", "transition_finished".to_string());
+                                env_vars.insert("FROM_TRACK_ID", from);
+                                env_vars.insert("TO_TRACK_ID", to);
+                            }
+                            _ => warn!("PlayerEvent::TransitionFinished: Invalid track id"),
+                        },
+                        PlayerEvent::SinkError { message } => {
+                            env_vars.insert("PLAYER_EVENT", "sink_error".to_string());
+                            env_vars.insert("ERROR", message);
+                        }
+                        PlayerEvent::FatalError { message } => {
+                            env_vars.insert("PLAYER_EVENT", "fatal_error".to_string());
+                            env_vars.insert("ERROR", message);
+                        }
+                        PlayerEvent::StateReset { message } => {
+                            env_vars.insert("PLAYER_EVENT", "state_reset".to_string());
+                            env_vars.insert("ERROR", message);
+                        }
                     }
 
                     if !env_vars.is_empty() {
@@ -271,13 +389,7 @@ pub fn run_program_on_sink_events(sink_status: SinkStatus, onevent: &str) {
 
     env_vars.insert("PLAYER_EVENT", "sink".to_string());
 
-    let sink_status = match sink_status {
-        SinkStatus::Running => "running",
-        SinkStatus::TemporarilyClosed => "temporarily_closed",
-        SinkStatus::Closed => "closed",
-    };
-
-    env_vars.insert("SINK_STATUS", sink_status.to_string());
+    env_vars.insert("SINK_STATUS", sink_status.as_str().to_string());
 
     run_program(env_vars, onevent);
 }